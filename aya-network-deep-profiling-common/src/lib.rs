@@ -44,11 +44,98 @@ pub enum AllocDirection {
 
 #[derive(Copy, Clone, Debug)]
 #[repr(C, packed)]
-pub struct FunctionCall<F: Program> {
+pub struct FunctionCall<F> {
     pub function: F,
     pub direction: FunctionDirection,
     pub depth: u32,
     pub cpuid: u32,
+    pub tid: u32,
+    pub tgid: u32,
+    pub context: ExecutionContext,
+}
+
+/// Completed call of a function, built in the kernel from its entry and its exit
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct FunctionSpan<F> {
+    pub function: F,
+    pub start: u64,
+    pub end: u64,
+    /// Depth of the caller, the outermost calls being at depth 0
+    pub depth: u32,
+    pub cpuid: u32,
+    /// Thread and process running the call, the interrupted ones in interrupt context
+    pub tid: u32,
+    pub tgid: u32,
+    pub context: ExecutionContext,
+}
+
+enum_display! {
+    /// Context a function runs in, the call depth being tracked separately for each of them:
+    /// per thread in task context, per CPU in interrupt context
+    #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+    #[cfg_attr(feature = "user", derive(Serialize))]
+    #[repr(u8)]
+    pub enum ExecutionContext {
+        Task,
+        /// Softirq run inline, on the exit of an interrupt or when a task re-enables the bottom halves
+        Softirq,
+        Hardirq,
+        /// Softirq deferred to the ksoftirqd thread of the CPU, run by `run_ksoftirqd`.
+        /// The softirqs the ksoftirqd thread runs inline when it re-enables bottom halves are `Softirq`.
+        Ksoftirqd,
+    }
+}
+
+/// Event stored in a per-CPU map, keyed by its sequence number on the CPU that recorded it.
+/// The CPUs that did not record an event for a key have a zeroed slot for it, `valid` tells them apart.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct Event<T> {
+    pub timestamp: u64,
+    pub valid: bool,
+    pub data: T,
+}
+
+enum_display! {
+    /// Maps written by the eBPF programs whose writes are counted in `MAP_PRESSURE`, named after them
+    #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+    #[repr(u8)]
+    pub enum EventMap {
+        KernelFunctionsExecutionTimes,
+        UserFunctionsExecutionTimes,
+        DynamicFunctionsExecutionTimes,
+        AllocFunctionsExecutionTimes,
+        XdpTimes,
+        KmallocAllocations,
+        TempKmallocAllocations,
+        KmemCacheAllocations,
+        TempKmemCacheAllocations,
+        UsdtEvents,
+        ThroughputStats,
+        RegisteredFunctions,
+        OpenCalls,
+        PacketStages,
+        PacketArrivals,
+        PacketResidences,
+        PacketDrops,
+        SkbEvents,
+        SkbCopySources,
+        IrqSpans,
+        NapiPolls,
+        SocketEvents,
+        Events,
+    }
+}
+
+/// Writes of the eBPF programs to a map on one CPU
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct MapPressure {
+    /// Entries written, minus the entries removed
+    pub recorded: u64,
+    /// Entries lost because the write failed, most likely because the map is full
+    pub dropped: u64,
 }
 
 enum_display! {
@@ -64,6 +151,172 @@ pub trait Program: Clone + Copy + Eq + PartialEq + Hash {
     fn to_str(self) -> &'static str;
 }
 
+/// Kind of function of a stream record
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "user", derive(Serialize))]
+#[repr(u8)]
+pub enum RecordKind {
+    Kernel,
+    User,
+    Dynamic,
+    Alloc,
+}
+
+/// Function entry or exit sent through the ring buffer with `--stream`, written as is to the stream file.
+/// The layout is fixed, 40 bytes in the native endianness of the host.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct StreamRecord {
+    pub timestamp: u64,
+    /// Id of the function in its enumeration, index of the `--uprobe` target, or function address, depending on the kind
+    pub id: u64,
+    pub depth: u32,
+    pub cpuid: u32,
+    pub tid: u32,
+    pub tgid: u32,
+    pub kind: RecordKind,
+    pub direction: FunctionDirection,
+    pub context: ExecutionContext,
+    pub padding: [u8; 5],
+}
+
+/// Function that can be identified in a stream record
+pub trait RecordId: Copy {
+    const KIND: RecordKind;
+
+    fn record_id(self) -> u64;
+}
+
+/// What the function probes record, set by user space
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum RecordingMode {
+    /// A span per call in the execution times maps
+    Spans,
+    /// Entries and exits sent through the ring buffer with `--stream`
+    Stream,
+    /// A latency histogram per function with `--histogram`
+    Histogram,
+}
+
+/// Buckets of a latency histogram, the bucket `n` counting the durations in `[2^n, 2^(n+1))` nanoseconds, 0 being in the first one
+pub const HISTOGRAM_BUCKETS: usize = 64;
+
+/// Log2 latency histogram of a function on a CPU, updated in the kernel on each return with `--histogram`
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct Histogram {
+    pub buckets: [u64; HISTOGRAM_BUCKETS],
+    pub count: u64,
+    /// Longest call in nanoseconds
+    pub max: u64,
+}
+
+/// Tasks whose events are recorded, set by user space from `--pid`, `--comm` and `--cgroup`
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct Scope {
+    /// cgroup v2 id, 0 for any cgroup
+    pub cgroup_id: u64,
+    /// Task name, empty for any task
+    pub comm: [u8; 16],
+    /// Whether the thread id or the process id must be in the pids map
+    pub filter_pids: bool,
+}
+
+/// Kernel function selected at run time, identified by its entry address and resolved against kallsyms in user space.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct FunctionAddress(pub u64);
+
+/// User-space function given with `--uprobe`, identified by its index in the list of targets, passed as the attach cookie
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct UserFunction(pub u32);
+
+/// Maximum number of arguments of a USDT probe, as in libbpf
+pub const USDT_MAX_ARGS: usize = 12;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum UsdtArgKind {
+    /// `$5`, the value is the constant itself
+    Constant,
+    /// `%rdi`, the value is in a register
+    Register,
+    /// `-8(%rbp)`, the value is in user memory at the address in a register plus an offset
+    RegisterDeref,
+    /// Not captured, e.g. `%xmm0`
+    Unsupported,
+}
+
+/// Location of a USDT argument, from the argument string of its stapsdt note, e.g. `-4@-8(%rbp)`
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct UsdtArgSpec {
+    /// Constant value or dereference offset
+    pub value: i64,
+    /// Offset of the register in `pt_regs`
+    pub register_offset: u16,
+    pub kind: UsdtArgKind,
+    /// Size of the argument in bytes, negative when signed
+    pub size: i8,
+}
+
+/// Arguments of a USDT probe location, indexed by the attach cookie of its uprobe
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct UsdtSpec {
+    pub args: [UsdtArgSpec; USDT_MAX_ARGS],
+    pub arg_count: u8,
+}
+
+/// Hit of a USDT probe location with its raw argument values, truncated and sign extended in user space
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct UsdtEvent {
+    pub timestamp: u64,
+    pub args: [u64; USDT_MAX_ARGS],
+    pub location: u32,
+    pub pid: u32,
+    pub tid: u32,
+    pub cpuid: u32,
+}
+
+/// Kind of function hit by a kprobe_multi program, stored in the upper bits of the attach cookie
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[repr(u8)]
+pub enum MultiProbeKind {
+    Kernel,
+    Alloc,
+    Dynamic,
+}
+
+/// Attach cookie given to each symbol of a kprobe_multi link: the kind of function, and its id for compile-time functions.
+/// Dynamic functions are identified by their address instead.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct MultiProbeCookie(pub u64);
+
+impl MultiProbeCookie {
+    pub const fn new(kind: MultiProbeKind, id: u16) -> Self {
+        MultiProbeCookie(((kind as u64) << 16) | id as u64)
+    }
+
+    pub fn kind(self) -> Option<MultiProbeKind> {
+        match self.0 >> 16 {
+            0 => Some(MultiProbeKind::Kernel),
+            1 => Some(MultiProbeKind::Alloc),
+            2 => Some(MultiProbeKind::Dynamic),
+            _ => None
+        }
+    }
+
+    pub fn id(self) -> u16 {
+        self.0 as u16
+    }
+}
+
 enum_display! {
     #[allow(non_camel_case_types)]
     #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
@@ -93,7 +346,7 @@ enum_display! {
         netif_receive_skb,
         /// Handle initial packet processing. Useful for fine-grained analysis of receive path bottlenecks.
         netif_receive_skb_core,
-        __netif_receive_skb_core_p_constprop_p_0,
+        __netif_receive_skb_core,
         /// Handles Generic Receive Offload (GRO), aggregating packets to improve performance before passing them to the stack.
         napi_gro_receive,
         /// Marks the completion of NAPI polling and re-enables interrupts. This is critical for understanding NAPI performance and interrupt handling.
@@ -123,7 +376,7 @@ enum_display! {
         /// Completes IPv4 packet reception, calling routing or local delivery.
         ip_rcv_finish,
         /// Completes the reception of IPv4 packets after initial validation in ip_rcv. It’s a key part of the receive path.
-        ip_rcv_finish_core_p_isra_p_0,
+        ip_rcv_finish_core,
         /// Handles local delivery of IP packets to the transport layer.
         //ip_input,
         /// Delivers packets to the local host after routing decisions. This is critical for profiling packets destined for the local system.
@@ -134,7 +387,7 @@ enum_display! {
         ip_send_skb,
 
         /* --- Routing Decision --- */
-        ip_route_input_rcu_p_part_p_0,
+        ip_route_input_rcu,
         ip_route_input_noref,
         ip_route_input_slow,
         __fib_lookup,
@@ -156,7 +409,7 @@ enum_display! {
         /// Works with the socket buffer (skb) to prepare the packet payload.
         ip_append_data,
         /// Delays packet transmission to allow multiple writes to be combined into a single packet, improving efficiency (e.g., for UDP or raw sockets)
-        ip_setup_cork_p_constprop_p_0,
+        ip_setup_cork,
 
         /* ===== Transport layer processing ===== */
 
@@ -223,7 +476,7 @@ enum_display! {
         ip_options_compile,
 
         /// Handles IP packet fragmentation.
-        ip_fragment_p_constprop_p_0,
+        ip_fragment,
         /*
         netif_carrier_on,
         netif_carrier_off,
@@ -244,18 +497,6 @@ enum_display! {
     }
 }
 
-enum_display! {
-    #[allow(non_camel_case_types)]
-    #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
-    #[cfg_attr(any(feature = "user-space-program"), repr(u16))]
-    pub enum UserFunction {
-        #[cfg(feature = "user-space-program")]
-        launch => "/root/myServer",
-        #[cfg(feature = "user-space-program")]
-        handle_connection => "/root/myServer",
-    }
-}
-
 enum_display! {
     #[allow(non_camel_case_types)]
     #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
@@ -302,7 +543,6 @@ enum_display! {
         kmem_cache_flags,
         kmem_cache_free,
         kmem_cache_free_bulk,
-        kmem_cache_free_bulk_p_part_p_0,
         kmem_cache_release,
         kmem_cache_shrink,
         kmem_cache_size,
@@ -344,6 +584,73 @@ enum_display! {
     }
 }
 
+enum_display! {
+    /// Tracepoints maintaining the interrupt context of each CPU
+    #[allow(non_camel_case_types)]
+    #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+    #[repr(u8)]
+    pub enum IrqTracepoint {
+        softirq_entry,
+        softirq_exit,
+        irq_handler_entry,
+        irq_handler_exit
+    }
+}
+
+enum_display! {
+    /// Softirq vectors of the kernel (`NR_SOFTIRQS`), by number
+    #[allow(non_camel_case_types)]
+    #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+    #[repr(u8)]
+    pub enum SoftirqVector {
+        hi,
+        timer,
+        net_tx,
+        net_rx,
+        block,
+        irq_poll,
+        tasklet,
+        sched,
+        hrtimer,
+        rcu
+    }
+}
+
+enum_display! {
+    /// Tracepoints ending the path of a packet, when its `sk_buff` is released
+    #[allow(non_camel_case_types)]
+    #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+    #[repr(u8)]
+    pub enum SkbTracepoint {
+        consume_skb,
+        kfree_skb
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[repr(u8)]
+pub enum PacketStageKind {
+    /// The packet entered a profiled function
+    Function,
+    /// The `sk_buff` was released after the packet was delivered or sent
+    Consumed,
+    /// The `sk_buff` was released because the packet was dropped
+    Freed,
+}
+
+/// Step of a packet through the stack with `--packet-paths`, the packet being identified by the address of its `sk_buff`
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct PacketStage {
+    pub skb: u64,
+    pub cpuid: u32,
+    pub tid: u32,
+    pub tgid: u32,
+    /// Function entered, `None` for the releases
+    pub function: Option<KernelFunction>,
+    pub kind: PacketStageKind,
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 #[cfg_attr(feature = "user", derive(Serialize))]
 #[repr(C, packed)]
@@ -351,7 +658,203 @@ pub struct ThroughputStat {
     pub timestamp: u64,
     pub packet_size: u32,
     pub direction: PacketDirection,
-    pub if_index: u32
+    pub if_index: u32,
+    pub flow: FlowId,
+    /// Time stamped in the metadata of the packet by the XDP program, 0 when it did not go through it
+    pub xdp_time: u64,
+}
+
+/// Layer 3 and 4 identity of a packet, parsed by the XDP and TC programs. All zeroes but the VLAN for the packets that are not IP.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "user", derive(Serialize))]
+#[repr(C)]
+pub struct FlowId {
+    /// IPv4 addresses take the first 4 bytes, in network byte order
+    pub src_addr: [u8; 16],
+    pub dst_addr: [u8; 16],
+    /// Ports in host byte order, or type and code for ICMP
+    pub src_port: u16,
+    pub dst_port: u16,
+    /// Outermost VLAN id, 0 when untagged
+    pub vlan_id: u16,
+    /// IP protocol number, of the transport header after the IPv6 extension headers
+    pub protocol: u8,
+    /// 4 or 6, 0 when the packet is not IP
+    pub ip_version: u8,
+}
+
+/// Flows recorded by the XDP and TC programs, set by user space from `--flow-protocol`, `--flow-port` and `--flow-host`.
+/// A zero field matches any packet.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct FlowFilter {
+    /// Address of the source or of the destination, compared when `ip_version` is not 0
+    pub addr: [u8; 16],
+    /// Port of the source or of the destination
+    pub port: u16,
+    pub protocol: u8,
+    pub ip_version: u8,
+}
+
+/// Step of the lifetime of an `sk_buff` with `--skb-lifetimes`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[repr(u8)]
+pub enum SkbEventKind {
+    /// Returned by an allocator
+    Allocated,
+    /// Returned by a clone or a copy of the `parent` sk_buff
+    Cloned,
+    /// Released by `consume_skb`
+    Consumed,
+    /// Released by `kfree_skb`
+    Dropped,
+    /// Released by `__kfree_skb`, also called by the other releases
+    Freed,
+}
+
+/// Event of an `sk_buff` identified by its address, paired with the other events of the same address in user space
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct SkbEvent {
+    pub skb: u64,
+    /// Address of the cloned or copied sk_buff, 0 for the other kinds
+    pub parent: u64,
+    /// Address of the allocator or copy function, 0 for the releases
+    pub function: u64,
+    pub stack_id: i64,
+    pub cpuid: u32,
+    pub kind: SkbEventKind,
+}
+
+/// Offsets of the fields of the `skb:kfree_skb` tracepoint and of the kernel structures read to record a drop, which change between kernel versions.
+/// They are read from the tracepoint format and from the kernel BTF by user space, 0 standing for a field missing in the running kernel.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct SkbDropLayout {
+    pub location: u32,
+    pub protocol: u32,
+    pub reason: u32,
+    /// `sk_buff.dev`
+    pub skb_dev: u32,
+    /// `net_device.ifindex`
+    pub dev_ifindex: u32,
+}
+
+/// Packet freed by `kfree_skb` with `--drops`, with the reason given by the kernel (`enum skb_drop_reason`, 0 before Linux 5.17)
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct PacketDrop {
+    /// Address of the code that dropped the packet
+    pub location: u64,
+    pub stack_id: i64,
+    pub reason: u32,
+    /// Interface of the `sk_buff`, 0 when it has none
+    pub if_index: u32,
+    pub cpuid: u32,
+    /// EtherType of the packet, in host byte order
+    pub protocol: u16,
+}
+
+/// Use of a socket with `--connections`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[repr(u8)]
+pub enum SocketEventKind {
+    /// Read by a system call
+    Receive,
+    /// Written by a system call
+    Send,
+    /// A received packet was enqueued to it, by the softirq of the packet or by the processing of the TCP backlog
+    Deliver,
+}
+
+/// Offsets of the members of `struct sock_common`, at the start of `struct sock`, read from the kernel BTF by user space.
+/// 0 is a valid offset here, `skc_daddr` being the first member: the sockets are only tracked when the IPv4 members were all found,
+/// and the IPv6 ones are only read for the sockets of that family.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct SockLayout {
+    pub family: u32,
+    /// Local port, in host byte order
+    pub num: u32,
+    /// Remote port, in network byte order
+    pub dport: u32,
+    pub rcv_saddr: u32,
+    pub daddr: u32,
+    pub v6_rcv_saddr: u32,
+    pub v6_daddr: u32,
+}
+
+/// Use of a socket identified by its address, linking the threads using it to the delivery of its packets
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct SocketEvent {
+    pub sock: u64,
+    /// Address of the function probed, telling the protocol of the socket
+    pub function: u64,
+    /// Local address and port as source, remote ones as destination, without the protocol
+    pub flow: FlowId,
+    pub cpuid: u32,
+    pub tid: u32,
+    pub tgid: u32,
+    pub kind: SocketEventKind,
+    pub context: ExecutionContext,
+}
+
+/// Softirq or interrupt handler run on a CPU with `--irq-times`, the time of the event being its end
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct IrqSpan {
+    pub start: u64,
+    /// First raise of the softirq since it last ran on the CPU, 0 for the interrupt handlers and the softirqs raised before the capture
+    pub raised: u64,
+    /// Softirq vector or interrupt line
+    pub number: u32,
+    pub cpuid: u32,
+    /// Interrupted thread, or ksoftirqd
+    pub tid: u32,
+    pub context: ExecutionContext,
+}
+
+/// Offsets of the fields of the `napi:napi_poll` tracepoint, read from its format by user space, 0 standing for a field missing in the running kernel
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct NapiPollLayout {
+    pub napi: u32,
+    /// `__data_loc` of the device name
+    pub dev_name: u32,
+    pub work: u32,
+    pub budget: u32,
+}
+
+/// Poll of a NAPI instance with `--irq-times`: packets processed out of its budget, and the context it ran in
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct NapiPoll {
+    pub napi: u64,
+    pub device: [u8; 16],
+    pub work: u32,
+    pub budget: u32,
+    pub cpuid: u32,
+    pub context: ExecutionContext,
+}
+
+/// Arrival of a received packet with `--residence-times`: stamped by the XDP program in the metadata of the packet,
+/// read by the TC ingress program, and recorded when the packet is enqueued to a socket
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct PacketArrival {
+    pub xdp_time: u64,
+    pub tc_time: u64,
+    pub if_index: u32,
+}
+
+/// Packet seen by the XDP program
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct XdpPacket {
+    pub eth_header: EthHeader,
+    pub if_index: u32,
+    pub flow: FlowId,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
@@ -362,24 +865,101 @@ pub enum PacketDirection {
     Egress,
 }
 
+/// Ethernet header as read from the packet. Wire values are kept as raw integers, since any value can be received,
+/// and decoded in user space.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 #[repr(C, packed)]
 pub struct EthHeader {
     pub dst_addr: [u8; 6],
     pub src_addr: [u8; 6],
-    pub ether_type: EtherHeaderType,
+    /// In network byte order
+    pub ether_type: u16,
 }
 
-#[repr(u16)]
-#[derive(Hash, PartialEq, Eq, Debug, Copy, Clone)]
-pub enum EtherHeaderType {
-    Loop = 0x0060_u16.to_be(),
-    Ipv4 = 0x0800_u16.to_be(),
-    Arp = 0x0806_u16.to_be(),
-    Ipv6 = 0x86DD_u16.to_be(),
-    FibreChannel = 0x8906_u16.to_be(),
-    Infiniband = 0x8915_u16.to_be(),
-    LoopbackIeee8023 = 0x9000_u16.to_be(),
+impl KernelFunction {
+    /// Index of the `struct sk_buff *` argument of the function, `None` if it does not take one.
+    /// The functions taking a `struct sk_buff **` are left out.
+    pub fn skb_argument(self) -> Option<usize> {
+        match self {
+            KernelFunction::__netif_receive_skb
+            | KernelFunction::netif_receive_skb
+            | KernelFunction::netif_receive_skb_core
+            | KernelFunction::eth_type_trans
+            | KernelFunction::eth_header
+            | KernelFunction::__dev_queue_xmit
+            | KernelFunction::dev_queue_xmit_nit
+            | KernelFunction::ip_rcv
+            | KernelFunction::ip_rcv_core
+            | KernelFunction::ip_local_deliver
+            | KernelFunction::ip_mr_input
+            | KernelFunction::ip_route_input_rcu
+            | KernelFunction::ip_route_input_noref
+            | KernelFunction::ip_route_input_slow
+            | KernelFunction::ip_forward
+            | KernelFunction::ip_forward_options
+            | KernelFunction::__icmp_send
+            | KernelFunction::tcp_v4_rcv
+            | KernelFunction::udp_rcv
+            | KernelFunction::udp_send_skb
+            | KernelFunction::dev_hard_start_xmit
+            | KernelFunction::netpoll_start_xmit
+            | KernelFunction::sch_direct_xmit
+            | KernelFunction::netif_rx
+            | KernelFunction::netif_rx_internal
+            | KernelFunction::nf_hook_slow => Some(0),
+            KernelFunction::napi_gro_receive
+            | KernelFunction::ip_send_skb
+            | KernelFunction::tcp_v4_do_rcv
+            | KernelFunction::udp_queue_rcv_skb
+            | KernelFunction::__tcp_transmit_skb
+            | KernelFunction::skb_queue_tail
+            | KernelFunction::vsock_queue_rcv_skb
+            | KernelFunction::__sock_queue_rcv_skb
+            | KernelFunction::ip_queue_xmit
+            | KernelFunction::neigh_resolve_output
+            | KernelFunction::ipt_do_table => Some(1),
+            KernelFunction::ip_rcv_finish
+            | KernelFunction::ip_rcv_finish_core
+            | KernelFunction::ip_output
+            | KernelFunction::ip_finish_output
+            | KernelFunction::ip_finish_output2
+            | KernelFunction::ip_options_compile
+            | KernelFunction::ip_fragment => Some(2),
+            _ => None
+        }
+    }
+}
+
+impl RecordId for KernelFunction {
+    const KIND: RecordKind = RecordKind::Kernel;
+
+    fn record_id(self) -> u64 {
+        self.as_id() as u64
+    }
+}
+
+impl RecordId for UserFunction {
+    const KIND: RecordKind = RecordKind::User;
+
+    fn record_id(self) -> u64 {
+        self.0 as u64
+    }
+}
+
+impl RecordId for FunctionAddress {
+    const KIND: RecordKind = RecordKind::Dynamic;
+
+    fn record_id(self) -> u64 {
+        self.0
+    }
+}
+
+impl RecordId for Alloc {
+    const KIND: RecordKind = RecordKind::Alloc;
+
+    fn record_id(self) -> u64 {
+        self.as_id() as u64
+    }
 }
 
 #[cfg(feature = "user")]
@@ -392,10 +972,33 @@ mod user {
     unsafe impl Pod for UserFunction {}
     unsafe impl Pod for Alloc {}
     unsafe impl Pod for FunctionDirection {}
-    unsafe impl<F: Program + 'static> Pod for FunctionCall<F> {}
+    unsafe impl Pod for ExecutionContext {}
+    unsafe impl Pod for FunctionAddress {}
+    unsafe impl Pod for UsdtSpec {}
+    unsafe impl Pod for Scope {}
+    unsafe impl Pod for StreamRecord {}
+    unsafe impl Pod for UsdtEvent {}
+    unsafe impl<F: Pod> Pod for FunctionCall<F> {}
+    unsafe impl<F: Pod> Pod for FunctionSpan<F> {}
+    unsafe impl<T: Pod> Pod for Event<T> {}
+    unsafe impl Pod for MapPressure {}
+    unsafe impl Pod for Histogram {}
+    unsafe impl Pod for PacketStage {}
     unsafe impl Pod for ThroughputStat {}
     unsafe impl Pod for PacketDirection {}
     unsafe impl Pod for EthHeader {}
+    unsafe impl Pod for FlowId {}
+    unsafe impl Pod for FlowFilter {}
+    unsafe impl Pod for XdpPacket {}
+    unsafe impl Pod for PacketArrival {}
+    unsafe impl Pod for SkbDropLayout {}
+    unsafe impl Pod for PacketDrop {}
+    unsafe impl Pod for SkbEvent {}
+    unsafe impl Pod for SockLayout {}
+    unsafe impl Pod for SocketEvent {}
+    unsafe impl Pod for IrqSpan {}
+    unsafe impl Pod for NapiPollLayout {}
+    unsafe impl Pod for NapiPoll {}
 
 
     unsafe impl Send for AllocInfo {}
@@ -413,8 +1016,14 @@ mod user {
     unsafe impl Send for FunctionDirection {}
     unsafe impl Sync for FunctionDirection {}
 
-    unsafe impl<F: Program + Send + Sync> Send for FunctionCall<F> {}
-    unsafe impl<F: Program + Send + Sync> Sync for FunctionCall<F> {}
+    unsafe impl Send for ExecutionContext {}
+    unsafe impl Sync for ExecutionContext {}
+
+    unsafe impl<F: Send + Sync> Send for FunctionCall<F> {}
+    unsafe impl<F: Send + Sync> Sync for FunctionCall<F> {}
+
+    unsafe impl<F: Send + Sync> Send for FunctionSpan<F> {}
+    unsafe impl<F: Send + Sync> Sync for FunctionSpan<F> {}
 
     unsafe impl Send for ThroughputStat {}
     unsafe impl Sync for ThroughputStat {}
@@ -425,7 +1034,4 @@ mod user {
     unsafe impl Send for EthHeader {}
     unsafe impl Sync for EthHeader {}
 
-    unsafe impl Send for EtherHeaderType {}
-    unsafe impl Sync for EtherHeaderType {}
-
 }
\ No newline at end of file
@@ -47,13 +47,14 @@ macro_rules! enum_display {
                     *self as u16
                 }
 
-                pub fn from_id(id: u16) -> $name {
+                /// `None` for an id that is not the one of a variant
+                pub fn from_id(id: u16) -> Option<$name> {
                     return match id {
                         $(
                             $(#[$variant_meta])*
-                            id if id == $name::$variant as u16 => $name::$variant,
+                            id if id == $name::$variant as u16 => Some($name::$variant),
                         )*
-                        _ => unreachable!()
+                        _ => None
                     }
                 }
             }
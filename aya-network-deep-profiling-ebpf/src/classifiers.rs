@@ -2,44 +2,51 @@ use aya_ebpf::bindings::TC_ACT_OK;
 use aya_ebpf::helpers::bpf_ktime_get_ns;
 use aya_ebpf::macros::classifier;
 use aya_ebpf::programs::TcContext;
-use aya_network_deep_profiling_common::{PacketDirection, ThroughputStat};
+use aya_network_deep_profiling_common::{EventMap, PacketDirection, ThroughputStat};
 use crate::THROUGHPUT_STATS;
+use crate::utils::event::count_write;
+use crate::utils::flow::{flow_in_scope, parse_flow};
+use crate::utils::residence::{is_timing_residence, record_arrival, xdp_timestamp};
 
 #[classifier]
 pub fn tc_ingress(ctx: TcContext) -> i32 {
-    try_tc_egress(ctx).unwrap_or_else(|_| TC_ACT_OK)
+    try_tc_packet_log(ctx, PacketDirection::Ingress).unwrap_or_else(|_| TC_ACT_OK)
 }
 
-fn try_tc_ingress(ctx: TcContext) -> Result<i32, ()> {
-    let time = unsafe { bpf_ktime_get_ns() };
-
-    let stat = ThroughputStat {
-        timestamp: time,
-        packet_size: ctx.len(),
-        direction: PacketDirection::Ingress,
-        if_index: unsafe { (*ctx.skb.skb).ifindex },
-    };
-
-    THROUGHPUT_STATS.push(&stat, 0).map_err(|_| ())?;
-    Ok(TC_ACT_OK)
-}
-
-
 #[classifier]
 pub fn tc_egress(ctx: TcContext) -> i32 {
-    try_tc_egress(ctx).unwrap_or_else(|_| TC_ACT_OK)
+    try_tc_packet_log(ctx, PacketDirection::Egress).unwrap_or_else(|_| TC_ACT_OK)
 }
 
-fn try_tc_egress(ctx: TcContext) -> Result<i32, ()> {
+fn try_tc_packet_log(ctx: TcContext, direction: PacketDirection) -> Result<i32, ()> {
     let time = unsafe { bpf_ktime_get_ns() };
 
+    let flow = parse_flow(ctx.data(), ctx.data_end());
+
+    if !flow_in_scope(&flow) {
+        return Ok(TC_ACT_OK);
+    }
+
+    // Only the received packets go through the XDP program
+    let xdp_time = match direction {
+        PacketDirection::Ingress => xdp_timestamp(&ctx),
+        PacketDirection::Egress => None,
+    };
+
     let stat = ThroughputStat {
         timestamp: time,
         packet_size: ctx.len(),
-        direction: PacketDirection::Egress,
+        direction,
         if_index: unsafe { (*ctx.skb.skb).ifindex },
+        flow,
+        xdp_time: xdp_time.unwrap_or(0),
     };
 
-    THROUGHPUT_STATS.push(&stat, 0).map_err(|_| ())?;
+    count_write(EventMap::ThroughputStats, THROUGHPUT_STATS.push(&stat, 0)).map_err(|_| ())?;
+
+    if let (Some(xdp_time), true) = (xdp_time, is_timing_residence()) {
+        record_arrival(&ctx, xdp_time, time)?;
+    }
+
     Ok(TC_ACT_OK)
 }
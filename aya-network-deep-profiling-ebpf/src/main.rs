@@ -1,11 +1,13 @@
 #![no_std]
 #![no_main]
+#![feature(core_intrinsics)]
 #![allow(
     clippy::all,
     dead_code,
     non_camel_case_types,
     non_snake_case,
     non_upper_case_globals,
+    internal_features,
     unnecessary_transmutes,
 )]
 
@@ -18,21 +20,63 @@ pub mod bindings;
 pub mod utils;
 
 use aya_ebpf::macros::map;
-use aya_ebpf::maps::{HashMap, PerCpuHashMap, Queue, StackTrace};
-use aya_network_deep_profiling_common::{Alloc, AllocInfo, EthHeader, FunctionCall, KernelFunction, ThroughputStat, UserFunction};
+use aya_ebpf::maps::{Array, HashMap, LruHashMap, PerCpuArray, PerCpuHashMap, Queue, RingBuf, StackTrace};
+use crate::utils::function::CallState;
+use crate::utils::time::CallKey;
+use aya_network_deep_profiling_common::{Alloc, AllocInfo, Event, EventMap, ExecutionContext, FlowFilter, FunctionAddress, FunctionSpan, Histogram, IrqSpan, KernelFunction, MapPressure, NapiPoll, NapiPollLayout, PacketArrival, PacketDrop, PacketStage, Scope, SkbEvent, SkbDropLayout, SockLayout, SocketEvent, SoftirqVector, ThroughputStat, UsdtEvent, UsdtSpec, UserFunction, XdpPacket};
 
 const MAX_ENTRIES: u32 = 1_000_000;
 
+// Scope
+
+#[map]
+pub static SCOPE: Array<Scope> = Array::with_max_entries(1, 0);
+
+/// Sized by user space from `--pid`
+#[map]
+pub static SCOPE_PIDS: HashMap<u32, u8> = HashMap::with_max_entries(1024, 0);
+
 // Functions
 
 #[map]
 pub static REGISTERED_FUNCTIONS: HashMap<i64, u16> = HashMap::with_max_entries(500, 0);
 
+/// Call states of the threads, by thread id
+#[map]
+static TASK_CALL_STATES: LruHashMap<u32, CallState> = LruHashMap::with_max_entries(32768, 0);
+
+/// Call states of the interrupt contexts of each CPU, by `ExecutionContext`
+#[map]
+static IRQ_CALL_STATES: PerCpuArray<CallState> = PerCpuArray::with_max_entries(ExecutionContext::VARIANT_COUNT as u32, 0);
+
+/// Interrupt nesting of each CPU, by `ExecutionContext`, maintained by the irq tracepoints
+#[map]
+static IRQ_NESTING: PerCpuArray<u32> = PerCpuArray::with_max_entries(ExecutionContext::VARIANT_COUNT as u32, 0);
+
+/// Whether each CPU runs `run_ksoftirqd`, the softirqs it runs being the deferred ones
+#[map]
+pub static KSOFTIRQD_RUNNING: PerCpuArray<u32> = PerCpuArray::with_max_entries(1, 0);
+
+/// Whether the softirqs, interrupt handlers and NAPI polls are recorded, set by user space with `--irq-times`
+#[map]
+pub static IRQ_TIMING: Array<u32> = Array::with_max_entries(1, 0);
+
+/// Softirq or interrupt handler running on each CPU, by `ExecutionContext`
+#[map]
+static IRQ_STARTS: PerCpuArray<IrqSpan> = PerCpuArray::with_max_entries(ExecutionContext::VARIANT_COUNT as u32, 0);
+
+/// First raise of each softirq vector on each CPU since it last ran, 0 when it is not pending
+#[map]
+static SOFTIRQ_RAISES: PerCpuArray<u64> = PerCpuArray::with_max_entries(SoftirqVector::VARIANT_COUNT as u32, 0);
+
 #[map]
-static ACTIVE_FUNCTIONS: PerCpuHashMap<u32, bool> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+pub static IRQ_SPANS: PerCpuHashMap<u64, Event<IrqSpan>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
 
 #[map]
-pub static DEPTH_COUNTER: PerCpuHashMap<u32, u32> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+pub static NAPI_POLL_LAYOUT: Array<NapiPollLayout> = Array::with_max_entries(1, 0);
+
+#[map]
+pub static NAPI_POLLS: PerCpuHashMap<u64, Event<NapiPoll>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
 
 #[map]
 pub static STACK_TRACES: StackTrace = StackTrace::with_max_entries(MAX_ENTRIES, 0);
@@ -56,20 +100,121 @@ pub static KMEM_CACHE_ALLOCATIONS: Queue<AllocInfo> = Queue::with_max_entries(MA
 #[map]
 static TEMP_KMEM_CACHE_ALLOCATIONS: HashMap<u64, AllocInfo> = HashMap::with_max_entries(MAX_ENTRIES, 0);
 
+// Events
+
+/// Sequence number of the next event of each CPU, keying the per-CPU event maps
+#[map]
+pub static EVENT_SEQUENCE: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+/// Recorded and dropped entries of each `EventMap`, read by user space during the run
+#[map]
+pub static MAP_PRESSURE: PerCpuArray<MapPressure> = PerCpuArray::with_max_entries(EventMap::VARIANT_COUNT as u32, 0);
+
 // Execution times
 
+/// Entry times of the calls not returned yet, turned into spans on return
 #[map]
-pub static KERNEL_FUNCTIONS_EXECUTION_TIMES: PerCpuHashMap<u64, FunctionCall<KernelFunction>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+static OPEN_CALLS: LruHashMap<CallKey, u64> = LruHashMap::with_max_entries(MAX_ENTRIES, 0);
 
+/// `RecordingMode` of the function probes, set by user space
+#[map]
+pub static RECORDING_MODE: Array<u32> = Array::with_max_entries(1, 0);
+
+/// Sized by user space with `--stream-buffer`
+#[map]
+pub static EVENTS: RingBuf = RingBuf::with_byte_size(64 * 1024 * 1024, 0);
+
+/// Latency histograms of the functions with `--histogram`: the kernel functions, the allocs and the user functions by id, then the dynamic functions.
+/// Sized by user space from the number of functions.
+#[map]
+pub static HISTOGRAMS: PerCpuArray<Histogram> = PerCpuArray::with_max_entries(1, 0);
+
+/// Slot in `HISTOGRAMS` of the dynamic functions, by address, set by user space
+#[map]
+pub static HISTOGRAM_SLOTS: HashMap<u64, u32> = HashMap::with_max_entries(1, 0);
+
+#[map]
+pub static KERNEL_FUNCTIONS_EXECUTION_TIMES: PerCpuHashMap<u64, Event<FunctionSpan<KernelFunction>>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+
+
+#[map]
+pub static USER_FUNCTIONS_EXECUTION_TIMES: PerCpuHashMap<u64, Event<FunctionSpan<UserFunction>>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+
+#[map]
+pub static DYNAMIC_FUNCTIONS_EXECUTION_TIMES: PerCpuHashMap<u64, Event<FunctionSpan<FunctionAddress>>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+
+#[map]
+pub static ALLOC_FUNCTIONS_EXECUTION_TIMES: PerCpuHashMap<u64, Event<FunctionSpan<Alloc>>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+
+// Packet paths
+
+/// Whether the packet stages are recorded, set by user space with `--packet-paths`
+#[map]
+pub static PACKET_PATHS: Array<u32> = Array::with_max_entries(1, 0);
+
+#[map]
+pub static PACKET_STAGES: PerCpuHashMap<u64, Event<PacketStage>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+
+// USDT
+
+/// Sized by user space from the locations of the `--usdt` probes
+#[map]
+pub static USDT_SPECS: HashMap<u32, UsdtSpec> = HashMap::with_max_entries(1024, 0);
+
+#[map]
+pub static USDT_EVENTS: Queue<UsdtEvent> = Queue::with_max_entries(MAX_ENTRIES, 0);
+
+// Packets
+
+/// Flows recorded by the XDP and TC programs, set by user space
+#[map]
+pub static FLOW_FILTER: Array<FlowFilter> = Array::with_max_entries(1, 0);
+
+/// Whether the allocations, copies and releases of the `sk_buff`s are recorded, set by user space with `--skb-lifetimes`
+#[map]
+pub static SKB_TRACKING: Array<u32> = Array::with_max_entries(1, 0);
+
+#[map]
+pub static SKB_EVENTS: PerCpuHashMap<u64, Event<SkbEvent>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+
+/// sk_buff given to a clone or copy function until it returns, by thread in task context and by CPU in interrupt context
+#[map]
+pub static SKB_COPY_SOURCES: LruHashMap<u64, u64> = LruHashMap::with_max_entries(32768, 0);
+
+/// Whether the packets freed by `kfree_skb` are recorded, set by user space with `--drops`
+#[map]
+pub static DROP_TRACING: Array<u32> = Array::with_max_entries(1, 0);
+
+#[map]
+pub static SKB_DROP_LAYOUT: Array<SkbDropLayout> = Array::with_max_entries(1, 0);
+
+#[map]
+pub static PACKET_DROPS: PerCpuHashMap<u64, Event<PacketDrop>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+
+/// Whether the uses of the sockets are recorded, set by user space with `--connections`
+#[map]
+pub static SOCKET_TRACKING: Array<u32> = Array::with_max_entries(1, 0);
+
+#[map]
+pub static SOCK_LAYOUT: Array<SockLayout> = Array::with_max_entries(1, 0);
+
+#[map]
+pub static SOCKET_EVENTS: PerCpuHashMap<u64, Event<SocketEvent>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+
+/// Whether the packets are timed from the XDP hook to their socket, set by user space with `--residence-times`
+#[map]
+pub static RESIDENCE_TIMES: Array<u32> = Array::with_max_entries(1, 0);
 
+/// Packets that went through the TC ingress program, by `sk_buff` address, until they are enqueued to a socket
 #[map]
-pub static USER_FUNCTIONS_EXECUTION_TIMES: PerCpuHashMap<u64, FunctionCall<UserFunction>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+pub static PACKET_ARRIVALS: LruHashMap<u64, PacketArrival> = LruHashMap::with_max_entries(MAX_ENTRIES, 0);
 
+/// Packets enqueued to a socket, the time of the event being the enqueue time
 #[map]
-pub static ALLOC_FUNCTIONS_EXECUTION_TIMES: PerCpuHashMap<u64, FunctionCall<Alloc>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
+pub static PACKET_RESIDENCES: PerCpuHashMap<u64, Event<PacketArrival>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
 
 #[map]
-pub static XDP_TIMES: HashMap<u64, EthHeader> = HashMap::with_max_entries(MAX_ENTRIES, 0);
+pub static XDP_TIMES: PerCpuHashMap<u64, Event<XdpPacket>> = PerCpuHashMap::with_max_entries(MAX_ENTRIES, 0);
 
 // Throughput stats
 
@@ -43,7 +43,6 @@ log_time!(
     kmem_cache_flags,
     kmem_cache_free,
     kmem_cache_free_bulk,
-    kmem_cache_free_bulk_p_part_p_0,
     kmem_cache_release,
     kmem_cache_shrink,
     kmem_cache_size,
@@ -0,0 +1,69 @@
+use aya_ebpf::EbpfContext;
+use aya_ebpf::helpers::bpf_get_func_ip;
+use aya_ebpf::macros::{kprobe, kretprobe};
+use aya_ebpf::programs::{ProbeContext, RetProbeContext};
+use aya_network_deep_profiling_common::{FunctionAddress, FunctionDirection};
+use crate::utils::context::CallContext;
+use crate::utils::function::{decrement_depth, increment_depth, set_function_active};
+use crate::utils::time::log_dynamic_time;
+use crate::utils::scope::in_scope;
+
+// Attached at run time to every function given with `--functions`.
+// The function is not known at compile time, it is identified by the address returned by `bpf_get_func_ip`,
+// which points to the function entry for both kprobes and kretprobes.
+
+#[kprobe]
+pub fn probe_enter_dynamic(ctx: ProbeContext) -> u32 {
+    match probe_try_enter_dynamic(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_enter_dynamic");
+            }
+            ret
+        },
+    }
+}
+
+fn probe_try_enter_dynamic(ctx: ProbeContext) -> Result<u32, u32> {
+    if !in_scope() {
+        return Ok(0);
+    }
+
+    let call = CallContext::current();
+    let function = FunctionAddress(unsafe { bpf_get_func_ip(ctx.as_ptr()) });
+
+    let depth = increment_depth(&call)?;
+    log_dynamic_time(function, FunctionDirection::Entry, depth, &call)?;
+    set_function_active(&call, true)?;
+
+    Ok(0)
+}
+
+#[kretprobe]
+pub fn probe_ret_dynamic(ctx: RetProbeContext) -> u32 {
+    match probe_try_ret_dynamic(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_ret_dynamic");
+            }
+            ret
+        },
+    }
+}
+
+fn probe_try_ret_dynamic(ctx: RetProbeContext) -> Result<u32, u32> {
+    if !in_scope() {
+        return Ok(0);
+    }
+
+    let call = CallContext::current();
+    let function = FunctionAddress(unsafe { bpf_get_func_ip(ctx.as_ptr()) });
+
+    let depth = decrement_depth(&call)?;
+    log_dynamic_time(function, FunctionDirection::Exit, depth, &call)?;
+    set_function_active(&call, false)?;
+
+    Ok(0)
+}
@@ -0,0 +1,18 @@
+use aya_ebpf::macros::{kprobe, kretprobe};
+use aya_ebpf::programs::{ProbeContext, RetProbeContext};
+use crate::utils::irq::set_ksoftirqd_running;
+
+// Always attached to `run_ksoftirqd(unsigned int cpu)`, run by the ksoftirqd thread of a CPU for the softirqs deferred by the interrupts.
+// The thread is bound to its CPU and runs them with bottom halves disabled, so the flag of that CPU tells the softirq tracepoints they are deferred.
+
+#[kprobe]
+pub fn probe_ksoftirqd_entry(_ctx: ProbeContext) -> u32 {
+    set_ksoftirqd_running(true);
+    0
+}
+
+#[kretprobe]
+pub fn probe_ksoftirqd_return(_ctx: RetProbeContext) -> u32 {
+    set_ksoftirqd_running(false);
+    0
+}
@@ -4,3 +4,8 @@ pub mod link;
 pub mod network;
 pub mod transport;
 pub mod others;
+pub mod dynamic;
+pub mod multi;
+pub mod socket;
+pub mod skb;
+pub mod ksoftirqd;
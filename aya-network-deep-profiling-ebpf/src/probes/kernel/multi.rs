@@ -0,0 +1,135 @@
+use aya_ebpf::EbpfContext;
+use aya_ebpf::helpers::{bpf_get_attach_cookie, bpf_get_func_ip};
+use aya_ebpf::macros::{kprobe, kretprobe};
+use aya_ebpf::programs::{ProbeContext, RetProbeContext};
+use aya_network_deep_profiling_common::{Alloc, FunctionAddress, FunctionDirection, KernelFunction, MultiProbeCookie, MultiProbeKind};
+use crate::STACK_TRACES;
+use crate::utils::context::CallContext;
+use crate::utils::packet::trace_packet;
+use crate::utils::function::{decrement_depth, increment_depth, register_function, set_function_active, should_profile_stack_id};
+use crate::utils::time::{log_alloc_time, log_dynamic_time, log_kernel_time};
+use crate::utils::scope::in_scope;
+
+// Loaded by user space as BPF_TRACE_KPROBE_MULTI programs and attached to the whole symbol set with a single link each,
+// instead of one kprobe per symbol. The function hit is identified by the attach cookie given to its symbol,
+// see `MultiProbeCookie`, and reproduces the logic of the per-function probes.
+
+#[kprobe]
+pub fn probe_enter_multi(ctx: ProbeContext) -> u32 {
+    match probe_try_enter_multi(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_enter_multi");
+            }
+            ret
+        },
+    }
+}
+
+fn probe_try_enter_multi(ctx: ProbeContext) -> Result<u32, u32> {
+    if !in_scope() {
+        return Ok(0);
+    }
+
+    let call = CallContext::current();
+    let cookie = MultiProbeCookie(unsafe { bpf_get_attach_cookie(ctx.as_ptr()) });
+    let direction = FunctionDirection::Entry;
+
+    match cookie.kind().ok_or(0u32)? {
+        MultiProbeKind::Kernel => {
+            // The id comes from `KernelFunction::as_id` in user space
+            let Some(function) = KernelFunction::from_id(cookie.id()) else {
+                return Ok(0);
+            };
+
+            let depth = increment_depth(&call)?;
+            log_kernel_time(function, direction, depth, &call)?;
+            set_function_active(&call, true)?;
+            trace_packet(&ctx, function, &call)?;
+        }
+        MultiProbeKind::Alloc => {
+            if !should_profile_stack_id(&call) {
+                return Ok(0);
+            }
+
+            let Some(function) = Alloc::from_id(cookie.id()) else {
+                return Ok(0);
+            };
+
+            let depth = increment_depth(&call)?;
+            log_alloc_time(function, direction, depth, &call)?;
+
+            let stack_id = match unsafe { STACK_TRACES.get_stackid(&ctx, 0) } {
+                Ok(stack_id) => stack_id,
+                _ => return Err(0),
+            };
+            register_function(&stack_id, function.as_id())?;
+        }
+        MultiProbeKind::Dynamic => {
+            let function = FunctionAddress(unsafe { bpf_get_func_ip(ctx.as_ptr()) });
+
+            let depth = increment_depth(&call)?;
+            log_dynamic_time(function, direction, depth, &call)?;
+            set_function_active(&call, true)?;
+        }
+    }
+
+    Ok(0)
+}
+
+#[kretprobe]
+pub fn probe_ret_multi(ctx: RetProbeContext) -> u32 {
+    match probe_try_ret_multi(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_ret_multi");
+            }
+            ret
+        },
+    }
+}
+
+fn probe_try_ret_multi(ctx: RetProbeContext) -> Result<u32, u32> {
+    if !in_scope() {
+        return Ok(0);
+    }
+
+    let call = CallContext::current();
+    let cookie = MultiProbeCookie(unsafe { bpf_get_attach_cookie(ctx.as_ptr()) });
+    let direction = FunctionDirection::Exit;
+
+    match cookie.kind().ok_or(0u32)? {
+        MultiProbeKind::Kernel => {
+            let Some(function) = KernelFunction::from_id(cookie.id()) else {
+                return Ok(0);
+            };
+
+            let depth = decrement_depth(&call)?;
+            log_kernel_time(function, direction, depth, &call)?;
+            set_function_active(&call, false)?;
+        }
+        MultiProbeKind::Alloc => {
+            if !should_profile_stack_id(&call) {
+                return Ok(0);
+            }
+
+            let Some(function) = Alloc::from_id(cookie.id()) else {
+                return Ok(0);
+            };
+
+            let depth = decrement_depth(&call)?;
+            log_alloc_time(function, direction, depth, &call)?;
+        }
+        MultiProbeKind::Dynamic => {
+            let function = FunctionAddress(unsafe { bpf_get_func_ip(ctx.as_ptr()) });
+
+            let depth = decrement_depth(&call)?;
+            log_dynamic_time(function, direction, depth, &call)?;
+            set_function_active(&call, false)?;
+        }
+    }
+
+    Ok(0)
+}
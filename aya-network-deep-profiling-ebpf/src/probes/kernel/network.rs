@@ -6,11 +6,11 @@ profile_function!(Kernel, k,
     ip_rcv,
     ip_rcv_core,
     ip_rcv_finish,
-    ip_rcv_finish_core_p_isra_p_0,
+    ip_rcv_finish_core,
     ip_local_deliver,
     ip_mr_input,
     ip_send_skb,
-    ip_route_input_rcu_p_part_p_0,
+    ip_route_input_rcu,
     ip_route_input_noref,
     ip_route_input_slow,
     __fib_lookup,
@@ -22,5 +22,5 @@ profile_function!(Kernel, k,
     __icmp_send,
     icmp_push_reply,
     ip_append_data,
-    ip_setup_cork_p_constprop_p_0,
+    ip_setup_cork,
 );
\ No newline at end of file
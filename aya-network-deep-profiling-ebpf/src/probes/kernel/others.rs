@@ -6,7 +6,7 @@ profile_function!(Kernel, k,
     nf_hook_slow,
     ipt_do_table,
     ip_options_compile,
-    ip_fragment_p_constprop_p_0,
+    ip_fragment,
     xdp_do_redirect,
     do_xdp_generic,
     bpf_xdp_redirect,
@@ -7,7 +7,7 @@ profile_function!(Kernel, k,
     __netif_receive_skb,
     netif_receive_skb,
     netif_receive_skb_core,
-    __netif_receive_skb_core_p_constprop_p_0,
+    __netif_receive_skb_core,
     napi_gro_receive,
     napi_complete_done,
     skb_gro_receive_list,
@@ -0,0 +1,98 @@
+use aya_ebpf::EbpfContext;
+use aya_ebpf::helpers::bpf_get_func_ip;
+use aya_ebpf::macros::{kprobe, kretprobe};
+use aya_ebpf::programs::{ProbeContext, RetProbeContext};
+use aya_network_deep_profiling_common::SkbEventKind;
+use crate::utils::skb::{enter_skb_copy, exit_skb_copy, record_skb_event};
+
+// Attached with `--skb-lifetimes` to the functions allocating, cloning, copying and freeing an `sk_buff`,
+// the releases by `consume_skb` and `kfree_skb` being recorded by their tracepoints.
+// The function is identified by its address, `bpf_get_func_ip` pointing to the function entry for both kprobes and kretprobes.
+// They run in the softirq of the packet, whichever task it interrupted, so the scope is not checked.
+
+#[kretprobe]
+pub fn probe_skb_alloc_return(ctx: RetProbeContext) -> u32 {
+    match try_probe_skb_alloc_return(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_skb_alloc_return");
+            }
+            ret
+        },
+    }
+}
+
+fn try_probe_skb_alloc_return(ctx: RetProbeContext) -> Result<u32, u32> {
+    let skb: u64 = ctx.ret().ok_or(0u32)?;
+    let function = unsafe { bpf_get_func_ip(ctx.as_ptr()) };
+
+    record_skb_event(&ctx, skb, 0, function, SkbEventKind::Allocated)?;
+
+    Ok(0)
+}
+
+#[kprobe]
+pub fn probe_skb_copy_entry(ctx: ProbeContext) -> u32 {
+    match try_probe_skb_copy_entry(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_skb_copy_entry");
+            }
+            ret
+        },
+    }
+}
+
+fn try_probe_skb_copy_entry(ctx: ProbeContext) -> Result<u32, u32> {
+    let skb: u64 = ctx.arg(0).ok_or(0u32)?;
+
+    enter_skb_copy(skb)?;
+
+    Ok(0)
+}
+
+#[kretprobe]
+pub fn probe_skb_copy_return(ctx: RetProbeContext) -> u32 {
+    match try_probe_skb_copy_return(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_skb_copy_return");
+            }
+            ret
+        },
+    }
+}
+
+fn try_probe_skb_copy_return(ctx: RetProbeContext) -> Result<u32, u32> {
+    let parent = exit_skb_copy();
+    let skb: u64 = ctx.ret().ok_or(0u32)?;
+    let function = unsafe { bpf_get_func_ip(ctx.as_ptr()) };
+
+    record_skb_event(&ctx, skb, parent, function, SkbEventKind::Cloned)?;
+
+    Ok(0)
+}
+
+#[kprobe]
+pub fn probe_skb_free(ctx: ProbeContext) -> u32 {
+    match try_probe_skb_free(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_skb_free");
+            }
+            ret
+        },
+    }
+}
+
+fn try_probe_skb_free(ctx: ProbeContext) -> Result<u32, u32> {
+    let skb: u64 = ctx.arg(0).ok_or(0u32)?;
+
+    record_skb_event(&ctx, skb, 0, 0, SkbEventKind::Freed)?;
+
+    Ok(0)
+}
@@ -0,0 +1,83 @@
+use aya_ebpf::EbpfContext;
+use aya_ebpf::helpers::bpf_get_func_ip;
+use aya_ebpf::macros::kprobe;
+use aya_ebpf::programs::ProbeContext;
+use aya_network_deep_profiling_common::SocketEventKind;
+use crate::utils::context::CallContext;
+use crate::utils::residence::record_socket_enqueue;
+use crate::utils::scope::in_scope;
+use crate::utils::socket::record_socket_event;
+
+// Attached with `--residence-times` or `--connections` to the functions enqueuing a received `sk_buff` to a socket,
+// `__udp_enqueue_schedule_skb(struct sock *sk, struct sk_buff *skb)`, `__sock_queue_rcv_skb(struct sock *sk, struct sk_buff *skb)`
+// and `tcp_queue_rcv(struct sock *sk, struct sk_buff *skb, bool *fragstolen)`.
+// They run in the softirq of the packet, whichever task it interrupted, so the scope is not checked.
+//
+// Attached with `--connections` to the functions receiving from and sending to a socket in the context of the task,
+// `tcp_recvmsg`, `udp_recvmsg` and `udpv6_recvmsg`, then `tcp_sendmsg`, `udp_sendmsg` and `udpv6_sendmsg`, all taking the `struct sock *` first.
+
+const SOCK_ARGUMENT: usize = 0;
+const SKB_ARGUMENT: usize = 1;
+
+#[kprobe]
+pub fn probe_socket_enqueue(ctx: ProbeContext) -> u32 {
+    match try_probe_socket_enqueue(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_socket_enqueue");
+            }
+            ret
+        },
+    }
+}
+
+fn try_probe_socket_enqueue(ctx: ProbeContext) -> Result<u32, u32> {
+    let sock: u64 = ctx.arg(SOCK_ARGUMENT).ok_or(0u32)?;
+    let skb: u64 = ctx.arg(SKB_ARGUMENT).ok_or(0u32)?;
+    let function = unsafe { bpf_get_func_ip(ctx.as_ptr()) };
+
+    record_socket_enqueue(skb)?;
+    record_socket_event(sock, function, SocketEventKind::Deliver, &CallContext::current())?;
+
+    Ok(0)
+}
+
+#[kprobe]
+pub fn probe_socket_receive(ctx: ProbeContext) -> u32 {
+    match try_probe_socket_use(ctx, SocketEventKind::Receive) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_socket_receive");
+            }
+            ret
+        },
+    }
+}
+
+#[kprobe]
+pub fn probe_socket_send(ctx: ProbeContext) -> u32 {
+    match try_probe_socket_use(ctx, SocketEventKind::Send) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_socket_send");
+            }
+            ret
+        },
+    }
+}
+
+fn try_probe_socket_use(ctx: ProbeContext, kind: SocketEventKind) -> Result<u32, u32> {
+    if !in_scope() {
+        return Ok(0);
+    }
+
+    let sock: u64 = ctx.arg(SOCK_ARGUMENT).ok_or(0u32)?;
+    let function = unsafe { bpf_get_func_ip(ctx.as_ptr()) };
+
+    record_socket_event(sock, function, kind, &CallContext::current())?;
+
+    Ok(0)
+}
@@ -0,0 +1,68 @@
+use aya_ebpf::EbpfContext;
+use aya_ebpf::helpers::bpf_get_attach_cookie;
+use aya_ebpf::macros::{uprobe, uretprobe};
+use aya_ebpf::programs::{ProbeContext, RetProbeContext};
+use aya_network_deep_profiling_common::{FunctionDirection, UserFunction};
+use crate::utils::context::CallContext;
+use crate::utils::function::{decrement_depth, increment_depth, set_function_active};
+use crate::utils::time::log_user_time;
+use crate::utils::scope::in_scope;
+
+// Attached at run time to every target given with `--uprobe`.
+// The function is identified by the attach cookie, its index in the list of targets resolved by user space.
+
+#[uprobe]
+pub fn probe_enter_user(ctx: ProbeContext) -> u32 {
+    match probe_try_enter_user(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_enter_user");
+            }
+            ret
+        },
+    }
+}
+
+fn probe_try_enter_user(ctx: ProbeContext) -> Result<u32, u32> {
+    if !in_scope() {
+        return Ok(0);
+    }
+
+    let call = CallContext::current();
+    let function = UserFunction(unsafe { bpf_get_attach_cookie(ctx.as_ptr()) } as u32);
+
+    let depth = increment_depth(&call)?;
+    log_user_time(function, FunctionDirection::Entry, depth, &call)?;
+    set_function_active(&call, true)?;
+
+    Ok(0)
+}
+
+#[uretprobe]
+pub fn probe_ret_user(ctx: RetProbeContext) -> u32 {
+    match probe_try_ret_user(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_ret_user");
+            }
+            ret
+        },
+    }
+}
+
+fn probe_try_ret_user(ctx: RetProbeContext) -> Result<u32, u32> {
+    if !in_scope() {
+        return Ok(0);
+    }
+
+    let call = CallContext::current();
+    let function = UserFunction(unsafe { bpf_get_attach_cookie(ctx.as_ptr()) } as u32);
+
+    let depth = decrement_depth(&call)?;
+    log_user_time(function, FunctionDirection::Exit, depth, &call)?;
+    set_function_active(&call, false)?;
+
+    Ok(0)
+}
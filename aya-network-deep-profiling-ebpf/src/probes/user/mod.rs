@@ -1,2 +1,2 @@
-#[cfg(feature = "user-space-program")]
-pub mod user_space_program;
\ No newline at end of file
+pub mod dynamic;
+pub mod usdt;
@@ -0,0 +1,70 @@
+use aya_ebpf::EbpfContext;
+use aya_ebpf::helpers::{bpf_get_attach_cookie, bpf_get_smp_processor_id, bpf_ktime_get_ns, bpf_probe_read_kernel, bpf_probe_read_user};
+use aya_ebpf::macros::uprobe;
+use aya_ebpf::programs::ProbeContext;
+use aya_network_deep_profiling_common::{EventMap, UsdtArgKind, UsdtEvent, USDT_MAX_ARGS};
+use crate::{USDT_EVENTS, USDT_SPECS};
+use crate::utils::event::count_write;
+use crate::utils::scope::in_scope;
+
+// Attached at run time to every location of the probes given with `--usdt`.
+// The location is identified by the attach cookie, its arguments are read as described by its spec.
+
+#[uprobe]
+pub fn probe_usdt(ctx: ProbeContext) -> u32 {
+    match probe_try_usdt(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in probe_usdt");
+            }
+            ret
+        },
+    }
+}
+
+fn probe_try_usdt(ctx: ProbeContext) -> Result<u32, u32> {
+    if !in_scope() {
+        return Ok(0);
+    }
+
+    let location = unsafe { bpf_get_attach_cookie(ctx.as_ptr()) } as u32;
+    let spec = unsafe { USDT_SPECS.get(&location).ok_or(0u32)? };
+
+    let mut event = UsdtEvent {
+        timestamp: unsafe { bpf_ktime_get_ns() },
+        args: [0; USDT_MAX_ARGS],
+        location,
+        pid: ctx.tgid(),
+        tid: ctx.pid(),
+        cpuid: unsafe { bpf_get_smp_processor_id() } as u32,
+    };
+
+    for index in 0..USDT_MAX_ARGS {
+        if index >= spec.arg_count as usize {
+            break;
+        }
+
+        let arg = &spec.args[index];
+
+        event.args[index] = match arg.kind {
+            UsdtArgKind::Constant => arg.value as u64,
+            UsdtArgKind::Register => read_register(&ctx, arg.register_offset)?,
+            UsdtArgKind::RegisterDeref => {
+                let address = read_register(&ctx, arg.register_offset)?.wrapping_add(arg.value as u64);
+                unsafe { bpf_probe_read_user(address as *const u64).map_err(|_| 0u32)? }
+            }
+            UsdtArgKind::Unsupported => 0,
+        };
+    }
+
+    count_write(EventMap::UsdtEvents, USDT_EVENTS.push(&event, 0)).map_err(|_| 0u32)?;
+
+    Ok(0)
+}
+
+/// Reads a register of the probed task, at an offset of `pt_regs` only known at run time
+fn read_register(ctx: &ProbeContext, register_offset: u16) -> Result<u64, u32> {
+    let register = unsafe { (ctx.as_ptr() as *const u8).add(register_offset as usize) } as *const u64;
+    unsafe { bpf_probe_read_kernel(register).map_err(|_| 0u32) }
+}
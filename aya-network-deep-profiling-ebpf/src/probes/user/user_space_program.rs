@@ -1,6 +0,0 @@
-use crate::profile_function;
-
-profile_function!(User, u,
-    launch,
-    handle_connection,
-);
\ No newline at end of file
@@ -0,0 +1,54 @@
+use aya_ebpf::macros::tracepoint;
+use aya_ebpf::programs::TracePointContext;
+use aya_network_deep_profiling_common::ExecutionContext;
+use crate::utils::context::{enter_irq_context, exit_irq_context};
+use crate::utils::irq::{end_irq_span, record_softirq_raise, softirq_context, start_irq_span};
+
+// Maintain the interrupt context of each CPU, so that the functions run by softirqs and interrupt handlers
+// have their own call depth instead of being nested in the calls of the task they interrupted,
+// and time the softirqs and interrupt handlers with `--irq-times`.
+// The softirq tracepoints start with `unsigned int vec` and the irq handler ones with `int irq`, after the 8 bytes of common fields.
+
+const NUMBER_OFFSET: usize = 8;
+
+fn read_number(ctx: &TracePointContext) -> u32 {
+    unsafe { ctx.read_at(NUMBER_OFFSET) }.unwrap_or(u32::MAX)
+}
+
+#[tracepoint]
+pub fn tracepoint_softirq_entry(ctx: TracePointContext) -> u32 {
+    let context = softirq_context();
+
+    enter_irq_context(context);
+    start_irq_span(context, read_number(&ctx));
+    0
+}
+
+#[tracepoint]
+pub fn tracepoint_softirq_exit(ctx: TracePointContext) -> u32 {
+    let context = softirq_context();
+
+    let _ = end_irq_span(context, read_number(&ctx));
+    exit_irq_context(context);
+    0
+}
+
+#[tracepoint]
+pub fn tracepoint_softirq_raise(ctx: TracePointContext) -> u32 {
+    record_softirq_raise(read_number(&ctx));
+    0
+}
+
+#[tracepoint]
+pub fn tracepoint_irq_handler_entry(ctx: TracePointContext) -> u32 {
+    enter_irq_context(ExecutionContext::Hardirq);
+    start_irq_span(ExecutionContext::Hardirq, read_number(&ctx));
+    0
+}
+
+#[tracepoint]
+pub fn tracepoint_irq_handler_exit(ctx: TracePointContext) -> u32 {
+    let _ = end_irq_span(ExecutionContext::Hardirq, read_number(&ctx));
+    exit_irq_context(ExecutionContext::Hardirq);
+    0
+}
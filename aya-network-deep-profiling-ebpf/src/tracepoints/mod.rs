@@ -1 +1,4 @@
-pub mod alloc;
\ No newline at end of file
+pub mod alloc;
+pub mod irq;
+pub mod napi;
+pub mod skb;
\ No newline at end of file
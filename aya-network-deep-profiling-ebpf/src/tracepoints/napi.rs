@@ -0,0 +1,19 @@
+use aya_ebpf::macros::tracepoint;
+use aya_ebpf::programs::TracePointContext;
+use crate::utils::irq::record_napi_poll;
+
+// Record the packets processed by each NAPI poll with `--irq-times`, and whether the poll ran in an inline softirq,
+// in ksoftirqd, or in task context for busy polling and threaded NAPI.
+
+#[tracepoint]
+pub fn tracepoint_napi_poll(ctx: TracePointContext) -> u32 {
+    match record_napi_poll(&ctx) {
+        Ok(()) => 0,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in tracepoint_napi_poll");
+            }
+            ret
+        },
+    }
+}
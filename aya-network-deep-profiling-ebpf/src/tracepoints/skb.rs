@@ -0,0 +1,70 @@
+use aya_ebpf::macros::tracepoint;
+use aya_ebpf::programs::TracePointContext;
+use aya_network_deep_profiling_common::{PacketStageKind, SkbEventKind};
+use crate::utils::context::CallContext;
+use crate::utils::drop::record_drop;
+use crate::utils::packet::record_packet_stage;
+use crate::utils::residence::{forget_arrival, is_timing_residence};
+use crate::utils::scope::in_scope;
+use crate::utils::skb::{is_tracking_skbs, record_skb_event};
+
+// End the path of a packet when its sk_buff is released, the address being reused by the next packets,
+// forget its arrival with `--residence-times`, and record the drops with `--drops` and the releases with `--skb-lifetimes`.
+// Both tracepoints start with `void *skbaddr`, after the 8 bytes of common fields.
+
+const SKBADDR_OFFSET: usize = 8;
+
+#[tracepoint]
+pub fn tracepoint_consume_skb(ctx: TracePointContext) -> u32 {
+    match try_tracepoint_skb(ctx, PacketStageKind::Consumed) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in tracepoint_consume_skb");
+            }
+            ret
+        },
+    }
+}
+
+#[tracepoint]
+pub fn tracepoint_kfree_skb(ctx: TracePointContext) -> u32 {
+    match try_tracepoint_skb(ctx, PacketStageKind::Freed) {
+        Ok(ret) => ret,
+        Err(ret) => {
+            unsafe {
+                aya_ebpf::bpf_printk!(b"Error in tracepoint_kfree_skb");
+            }
+            ret
+        },
+    }
+}
+
+fn try_tracepoint_skb(ctx: TracePointContext, kind: PacketStageKind) -> Result<u32, u32> {
+    let skb: u64 = unsafe { ctx.read_at(SKBADDR_OFFSET).map_err(|_| 0u32)? };
+
+    // The recordings are independent, the failure of one of them (e.g. a full map) not losing the others
+
+    // Like the allocations, the releases are recorded whichever task is interrupted
+    let release = match (is_tracking_skbs(), kind) {
+        (false, _) => Ok(()),
+        (true, PacketStageKind::Consumed) => record_skb_event(&ctx, skb, 0, 0, SkbEventKind::Consumed),
+        (true, _) => record_skb_event(&ctx, skb, 0, 0, SkbEventKind::Dropped),
+    };
+
+    if is_timing_residence() {
+        forget_arrival(skb);
+    }
+
+    if !in_scope() {
+        return release.map(|_| 0);
+    }
+
+    let drop = match kind {
+        PacketStageKind::Freed => record_drop(&ctx, skb),
+        _ => Ok(()),
+    };
+    let stage = record_packet_stage(skb, None, kind, &CallContext::current());
+
+    release.and(drop).and(stage).map(|_| 0)
+}
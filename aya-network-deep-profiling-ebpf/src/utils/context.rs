@@ -1,6 +1,7 @@
 use aya_ebpf::EbpfContext;
-use aya_ebpf::helpers::bpf_get_smp_processor_id;
-use crate::STACK_TRACES;
+use aya_ebpf::helpers::{bpf_get_current_pid_tgid, bpf_get_smp_processor_id};
+use aya_network_deep_profiling_common::ExecutionContext;
+use crate::{IRQ_NESTING, STACK_TRACES};
 
 pub struct FullContext<T: EbpfContext> {
     pub uid: u32,
@@ -33,4 +34,51 @@ pub fn get_full_ctx<T: EbpfContext>(ctx: T) -> Result<FullContext<T>, u32> {
         stack_id,
         ctx,
     })
-}
\ No newline at end of file
+}
+
+/// Where a function call runs, its depth and activity being tracked per thread in task context and per CPU in interrupt context
+pub struct CallContext {
+    pub context: ExecutionContext,
+    pub tid: u32,
+    pub tgid: u32,
+    pub cpuid: u32,
+}
+
+impl CallContext {
+    pub fn current() -> CallContext {
+        let pid_tgid = bpf_get_current_pid_tgid();
+
+        CallContext {
+            context: execution_context(),
+            tid: pid_tgid as u32,
+            tgid: (pid_tgid >> 32) as u32,
+            cpuid: unsafe { bpf_get_smp_processor_id() } as u32,
+        }
+    }
+}
+
+/// Interrupt context of the current CPU, from the nesting maintained by the irq tracepoints.
+/// Functions run by the interrupt entry code before the handler are attributed to the interrupted context.
+pub fn execution_context() -> ExecutionContext {
+    let nesting = |context: ExecutionContext| IRQ_NESTING.get(context as u32).is_some_and(|nesting| *nesting > 0);
+
+    match (nesting(ExecutionContext::Hardirq), nesting(ExecutionContext::Softirq), nesting(ExecutionContext::Ksoftirqd)) {
+        (true, _, _) => ExecutionContext::Hardirq,
+        (false, true, _) => ExecutionContext::Softirq,
+        (false, false, true) => ExecutionContext::Ksoftirqd,
+        (false, false, false) => ExecutionContext::Task,
+    }
+}
+
+pub fn enter_irq_context(context: ExecutionContext) {
+    if let Some(nesting) = IRQ_NESTING.get_ptr_mut(context as u32) {
+        unsafe { *nesting += 1 };
+    }
+}
+
+/// The exit of an interrupt entered before the tracepoints were attached is ignored
+pub fn exit_irq_context(context: ExecutionContext) {
+    if let Some(nesting) = IRQ_NESTING.get_ptr_mut(context as u32) {
+        unsafe { *nesting = (*nesting).saturating_sub(1) };
+    }
+}
@@ -0,0 +1,55 @@
+use aya_ebpf::helpers::{bpf_get_smp_processor_id, bpf_ktime_get_ns, bpf_probe_read_kernel};
+use aya_ebpf::programs::TracePointContext;
+use aya_network_deep_profiling_common::{EventMap, PacketDrop, SkbDropLayout};
+use crate::utils::event::record_event;
+use crate::{DROP_TRACING, PACKET_DROPS, SKB_DROP_LAYOUT, STACK_TRACES};
+
+fn is_tracing_drops() -> bool {
+    DROP_TRACING.get(0).is_some_and(|drop_tracing| *drop_tracing != 0)
+}
+
+/// Reads a field of the tracepoint, 0 when it does not exist in the running kernel
+pub fn read_field<T: Default>(ctx: &TracePointContext, offset: u32) -> T {
+    match offset {
+        0 => T::default(),
+        offset => unsafe { ctx.read_at(offset as usize) }.unwrap_or_default()
+    }
+}
+
+/// `skb->dev->ifindex`, 0 when the `sk_buff` has no device
+fn device_ifindex(skb: u64, layout: &SkbDropLayout) -> u32 {
+    if layout.skb_dev == 0 || layout.dev_ifindex == 0 {
+        return 0;
+    }
+
+    let dev = unsafe { bpf_probe_read_kernel((skb + layout.skb_dev as u64) as *const u64) }.unwrap_or(0);
+
+    if dev == 0 {
+        return 0;
+    }
+
+    unsafe { bpf_probe_read_kernel((dev + layout.dev_ifindex as u64) as *const u32) }.unwrap_or(0)
+}
+
+/// Records a packet freed by `kfree_skb`, with the stack of the code that dropped it
+pub fn record_drop(ctx: &TracePointContext, skb: u64) -> Result<(), u32> {
+    if !is_tracing_drops() {
+        return Ok(());
+    }
+
+    let Some(layout) = SKB_DROP_LAYOUT.get(0) else {
+        return Ok(());
+    };
+
+    let time = unsafe { bpf_ktime_get_ns() };
+    let drop = PacketDrop {
+        location: read_field(ctx, layout.location),
+        stack_id: unsafe { STACK_TRACES.get_stackid(ctx, 0) }.unwrap_or(-1),
+        reason: read_field(ctx, layout.reason),
+        if_index: device_ifindex(skb, layout),
+        cpuid: unsafe { bpf_get_smp_processor_id() },
+        protocol: read_field(ctx, layout.protocol),
+    };
+
+    record_event(&PACKET_DROPS, EventMap::PacketDrops, time, drop).map_err(|_| 0u32)
+}
@@ -0,0 +1,57 @@
+use core::intrinsics::{atomic_xadd, AtomicOrdering};
+use aya_ebpf::maps::PerCpuHashMap;
+use aya_network_deep_profiling_common::{Event, EventMap};
+use crate::{EVENT_SEQUENCE, MAP_PRESSURE};
+
+/// Adds to a per-CPU counter, atomically since a probe may interrupt another one on the same CPU.
+/// The bpf target has no compare-and-swap, so `AtomicU64::fetch_add` is not available, the intrinsic compiles to a BPF atomic fetch-and-add.
+pub fn fetch_add(counter: *mut u64, value: u64) -> u64 {
+    unsafe { atomic_xadd::<u64, u64, { AtomicOrdering::Relaxed }>(counter, value) }
+}
+
+/// Takes the next sequence number of the current CPU, shared by all the event maps
+pub fn next_sequence() -> Option<u64> {
+    let sequence = EVENT_SEQUENCE.get_ptr_mut(0)?;
+
+    Some(fetch_add(sequence, 1))
+}
+
+/// Counts a write to `map` as recorded or dropped depending on its result, which is passed through
+pub fn count_write<T, E>(map: EventMap, result: Result<T, E>) -> Result<T, E> {
+    if let Some(pressure) = MAP_PRESSURE.get_ptr_mut(map as u32) {
+        match result {
+            Ok(_) => fetch_add(unsafe { &raw mut (*pressure).recorded }, 1),
+            Err(_) => fetch_add(unsafe { &raw mut (*pressure).dropped }, 1),
+        };
+    }
+
+    result
+}
+
+/// Counts an entry of `map` lost without a failed write, such as an entry evicted from an LRU map
+pub fn count_drop(map: EventMap) {
+    if let Some(pressure) = MAP_PRESSURE.get_ptr_mut(map as u32) {
+        fetch_add(unsafe { &raw mut (*pressure).dropped }, 1);
+    }
+}
+
+/// Counts the removal of an entry of `map`, the recorded entries of a CPU wrapping below zero when it removes entries written by another one
+pub fn count_removal<T, E>(map: EventMap, result: Result<T, E>) -> Result<T, E> {
+    if let (Some(pressure), Ok(_)) = (MAP_PRESSURE.get_ptr_mut(map as u32), &result) {
+        fetch_add(unsafe { &raw mut (*pressure).recorded }, u64::MAX);
+    }
+
+    result
+}
+
+/// Stores an event under the next sequence number of the current CPU, so that no two events share a slot
+pub fn record_event<T>(events: &PerCpuHashMap<u64, Event<T>>, map: EventMap, timestamp: u64, data: T) -> Result<(), i64> {
+    let sequence = next_sequence().ok_or(0i64)?;
+    let event = Event {
+        timestamp,
+        valid: true,
+        data,
+    };
+
+    count_write(map, events.insert(&sequence, &event, 0))
+}
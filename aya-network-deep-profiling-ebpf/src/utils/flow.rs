@@ -0,0 +1,163 @@
+use core::mem;
+use aya_network_deep_profiling_common::FlowId;
+use crate::FLOW_FILTER;
+
+const ETH_HEADER_LEN: usize = 14;
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86DD;
+const ETH_P_8021Q: u16 = 0x8100;
+const ETH_P_8021AD: u16 = 0x88A8;
+
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const IPPROTO_ICMPV6: u8 = 58;
+
+const IPV6_HEADER_LEN: usize = 40;
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_DESTINATION: u8 = 60;
+
+/// Tags skipped before the EtherType of the payload, QinQ having two
+const MAX_VLAN_TAGS: usize = 2;
+/// Extension headers skipped before the transport header, the loop being bounded for the verifier
+const MAX_IPV6_EXTENSION_HEADERS: usize = 4;
+
+/// Reads a value of the packet between `data` and `data_end`, `None` if it is out of bounds
+#[inline(always)]
+fn load<T: Copy>(data: usize, data_end: usize, offset: usize) -> Option<T> {
+    if data + offset + mem::size_of::<T>() > data_end {
+        return None;
+    }
+
+    Some(unsafe { ((data + offset) as *const T).read_unaligned() })
+}
+
+/// Parses the VLAN tags, the IPv4 or IPv6 header and the TCP, UDP or ICMP header of a packet starting with its Ethernet header.
+/// Returns what could be parsed before the end of the linear data.
+#[inline(always)]
+pub fn parse_flow(data: usize, data_end: usize) -> FlowId {
+    let mut flow = FlowId::default();
+    let _ = parse_headers(data, data_end, &mut flow);
+
+    flow
+}
+
+#[inline(always)]
+fn parse_headers(data: usize, data_end: usize, flow: &mut FlowId) -> Option<()> {
+    let mut ether_type = u16::from_be(load(data, data_end, 12)?);
+    let mut offset = ETH_HEADER_LEN;
+
+    for _ in 0..MAX_VLAN_TAGS {
+        if ether_type != ETH_P_8021Q && ether_type != ETH_P_8021AD {
+            break;
+        }
+
+        let tci = u16::from_be(load(data, data_end, offset)?);
+
+        if flow.vlan_id == 0 {
+            flow.vlan_id = tci & 0x0FFF;
+        }
+
+        ether_type = u16::from_be(load(data, data_end, offset + 2)?);
+        offset += 4;
+    }
+
+    match ether_type {
+        ETH_P_IP => {
+            let version_ihl: u8 = load(data, data_end, offset)?;
+            let header_len = (version_ihl & 0x0F) as usize * 4;
+            let fragment = u16::from_be(load(data, data_end, offset + 6)?);
+
+            flow.ip_version = 4;
+            flow.protocol = load(data, data_end, offset + 9)?;
+            flow.src_addr[..4].copy_from_slice(&load::<[u8; 4]>(data, data_end, offset + 12)?);
+            flow.dst_addr[..4].copy_from_slice(&load::<[u8; 4]>(data, data_end, offset + 16)?);
+
+            // Only the first fragment holds the transport header
+            if header_len < 20 || fragment & 0x1FFF != 0 {
+                return Some(());
+            }
+
+            offset += header_len;
+        }
+        ETH_P_IPV6 => {
+            let mut next_header: u8 = load(data, data_end, offset + 6)?;
+
+            flow.ip_version = 6;
+            flow.src_addr = load(data, data_end, offset + 8)?;
+            flow.dst_addr = load(data, data_end, offset + 24)?;
+            offset += IPV6_HEADER_LEN;
+
+            for _ in 0..MAX_IPV6_EXTENSION_HEADERS {
+                match next_header {
+                    IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_DESTINATION => {
+                        let length: u8 = load(data, data_end, offset + 1)?;
+                        next_header = load(data, data_end, offset)?;
+                        offset += (length as usize + 1) * 8;
+                    }
+                    IPV6_FRAGMENT => {
+                        let fragment = u16::from_be(load(data, data_end, offset + 2)?);
+                        next_header = load(data, data_end, offset)?;
+                        offset += 8;
+
+                        if fragment & 0xFFF8 != 0 {
+                            flow.protocol = next_header;
+                            return Some(());
+                        }
+                    }
+                    _ => break
+                }
+            }
+
+            flow.protocol = next_header;
+        }
+        _ => return Some(())
+    }
+
+    match flow.protocol {
+        IPPROTO_TCP | IPPROTO_UDP => {
+            flow.src_port = u16::from_be(load(data, data_end, offset)?);
+            flow.dst_port = u16::from_be(load(data, data_end, offset + 2)?);
+        }
+        IPPROTO_ICMP | IPPROTO_ICMPV6 => {
+            flow.src_port = load::<u8>(data, data_end, offset)? as u16;
+            flow.dst_port = load::<u8>(data, data_end, offset + 1)? as u16;
+        }
+        _ => {}
+    }
+
+    Some(())
+}
+
+/// Whether a packet matches the `--flow-protocol`, `--flow-port` and `--flow-host` filters
+pub fn flow_in_scope(flow: &FlowId) -> bool {
+    let Some(filter) = FLOW_FILTER.get(0) else {
+        return true;
+    };
+
+    if filter.protocol != 0 && flow.protocol != filter.protocol {
+        return false;
+    }
+
+    if filter.port != 0 && flow.src_port != filter.port && flow.dst_port != filter.port {
+        return false;
+    }
+
+    if filter.ip_version != 0 {
+        // Compared as integers to avoid a call to memcmp
+        let addr: [u64; 2] = unsafe { mem::transmute(filter.addr) };
+        let src_addr: [u64; 2] = unsafe { mem::transmute(flow.src_addr) };
+        let dst_addr: [u64; 2] = unsafe { mem::transmute(flow.dst_addr) };
+
+        let is_src = src_addr[0] == addr[0] && src_addr[1] == addr[1];
+        let is_dst = dst_addr[0] == addr[0] && dst_addr[1] == addr[1];
+
+        if flow.ip_version != filter.ip_version || !(is_src || is_dst) {
+            return false;
+        }
+    }
+
+    true
+}
@@ -1,54 +1,63 @@
-use crate::{ACTIVE_FUNCTIONS, DEPTH_COUNTER, REGISTERED_FUNCTIONS};
+use aya_network_deep_profiling_common::{EventMap, ExecutionContext};
+use crate::{IRQ_CALL_STATES, REGISTERED_FUNCTIONS, TASK_CALL_STATES};
+use crate::utils::context::CallContext;
+use crate::utils::event::count_write;
 
-pub fn set_function_active(cpuid: &u32, state: bool) -> Result<(), u32> {
-    match ACTIVE_FUNCTIONS.get_ptr_mut(cpuid) {
-        None => ACTIVE_FUNCTIONS.insert(cpuid, &state, 0).map_err(|_| 0u32)?,
-        Some(active) => unsafe { *active = state }
-    };
+/// Call depth of a thread or of an interrupt context, and whether a profiled function is running in it
+#[derive(Copy, Clone)]
+pub struct CallState {
+    pub depth: u32,
+    pub active: bool,
+}
+
+fn call_state(call: &CallContext) -> Result<*mut CallState, u32> {
+    if call.context != ExecutionContext::Task {
+        return IRQ_CALL_STATES.get_ptr_mut(call.context as u32).ok_or(0u32);
+    }
+
+    if let Some(state) = TASK_CALL_STATES.get_ptr_mut(&call.tid) {
+        return Ok(state);
+    }
+
+    TASK_CALL_STATES.insert(&call.tid, &CallState { depth: 0, active: false }, 0).map_err(|_| 0u32)?;
+    TASK_CALL_STATES.get_ptr_mut(&call.tid).ok_or(0u32)
+}
+
+pub fn set_function_active(call: &CallContext, active: bool) -> Result<(), u32> {
+    let state = call_state(call)?;
+    unsafe { (*state).active = active };
 
     Ok(())
 }
 
-pub fn increment_depth(cpuid: &u32) -> Result<u32, u32> {
-    let new_depth = match DEPTH_COUNTER.get_ptr_mut(cpuid) {
-        Some(ptr) => unsafe {
-            *ptr += 1;
-            *ptr
-        },
-        None => {
-            DEPTH_COUNTER.insert(cpuid, &1, 0).map_err(|_| 0u32)?;
-            1
-        }
-    };
-
-    Ok(new_depth)
+pub fn increment_depth(call: &CallContext) -> Result<u32, u32> {
+    let state = call_state(call)?;
+
+    unsafe {
+        (*state).depth += 1;
+        Ok((*state).depth)
+    }
 }
 
-pub fn decrement_depth(cpuid: &u32) -> Result<u32, u32> {
-    let new_depth = match DEPTH_COUNTER.get_ptr_mut(cpuid) {
-        Some(ptr) => unsafe {
-            *ptr = (*ptr).saturating_sub(1);
-            *ptr
-        },
-        None => {
-            DEPTH_COUNTER.insert(cpuid, &0, 0).map_err(|_| 0u32)?;
-            0
-        }
-    };
-
-    Ok(new_depth)
+pub fn decrement_depth(call: &CallContext) -> Result<u32, u32> {
+    let state = call_state(call)?;
+
+    unsafe {
+        (*state).depth = (*state).depth.saturating_sub(1);
+        Ok((*state).depth)
+    }
 }
 
-pub fn should_profile_stack_id(cpuid: u32) -> bool {
-    match unsafe { ACTIVE_FUNCTIONS.get(&cpuid) } {
-        None => false,
-        Some(active) => *active
+pub fn should_profile_stack_id(call: &CallContext) -> bool {
+    match call.context {
+        ExecutionContext::Task => unsafe { TASK_CALL_STATES.get(&call.tid) }.is_some_and(|state| state.active),
+        context => IRQ_CALL_STATES.get(context as u32).is_some_and(|state| state.active),
     }
 }
 
 pub fn register_function(stack_id: &i64, function_id: u16) -> Result<(), u32> {
     if REGISTERED_FUNCTIONS.get_ptr(stack_id).is_none() {
-        REGISTERED_FUNCTIONS.insert(stack_id, &function_id, 0).map_err(|_| 0u32)?;
+        count_write(EventMap::RegisteredFunctions, REGISTERED_FUNCTIONS.insert(stack_id, &function_id, 0)).map_err(|_| 0u32)?;
     }
 
     Ok(())
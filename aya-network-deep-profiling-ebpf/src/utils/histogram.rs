@@ -0,0 +1,49 @@
+use aya_network_deep_profiling_common::{Alloc, KernelFunction, RecordId, RecordKind, HISTOGRAM_BUCKETS};
+use crate::utils::event::fetch_add;
+use crate::{HISTOGRAMS, HISTOGRAM_SLOTS};
+
+/// Slot of a function in `HISTOGRAMS`, dynamic functions being looked up by address
+fn histogram_slot<F: RecordId>(function: F) -> Option<u32> {
+    let id = function.record_id() as u32;
+    let user_offset = KernelFunction::VARIANT_COUNT as u32 + Alloc::VARIANT_COUNT as u32;
+
+    match F::KIND {
+        RecordKind::Kernel => Some(id),
+        RecordKind::Alloc => Some(KernelFunction::VARIANT_COUNT as u32 + id),
+        RecordKind::User => Some(user_offset + id),
+        RecordKind::Dynamic => unsafe { HISTOGRAM_SLOTS.get(&function.record_id()) }.copied(),
+    }
+}
+
+/// Floor of the base 2 logarithm, 0 for 0. The bpf target has no count leading zeros instruction.
+fn log2(mut value: u64) -> usize {
+    let mut log = 0;
+
+    for shift in [32, 16, 8, 4, 2, 1] {
+        if value >> shift != 0 {
+            value >>= shift;
+            log += shift;
+        }
+    }
+
+    log
+}
+
+/// Counts a call of `duration` nanoseconds in the histogram of its function on the current CPU
+pub fn record_histogram<F: RecordId>(function: F, duration: u64) -> Result<(), u32> {
+    let slot = histogram_slot(function).ok_or(0u32)?;
+    let histogram = HISTOGRAMS.get_ptr_mut(slot).ok_or(0u32)?;
+    let bucket = log2(duration).min(HISTOGRAM_BUCKETS - 1);
+
+    unsafe {
+        fetch_add(&raw mut (*histogram).buckets[bucket], 1);
+        fetch_add(&raw mut (*histogram).count, 1);
+
+        // Not atomic, a longer call returning in between may be overwritten: the max is a lower bound
+        if duration > (*histogram).max {
+            (*histogram).max = duration;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use aya_ebpf::EbpfContext;
+use aya_ebpf::helpers::{bpf_get_current_pid_tgid, bpf_get_smp_processor_id, bpf_ktime_get_ns, bpf_probe_read_kernel_str_bytes};
+use aya_ebpf::programs::TracePointContext;
+use aya_network_deep_profiling_common::{EventMap, ExecutionContext, IrqSpan, NapiPoll};
+use crate::utils::context::execution_context;
+use crate::utils::drop::read_field;
+use crate::utils::event::record_event;
+use crate::{IRQ_SPANS, IRQ_STARTS, IRQ_TIMING, KSOFTIRQD_RUNNING, NAPI_POLLS, NAPI_POLL_LAYOUT, SOFTIRQ_RAISES};
+
+pub fn is_timing_irqs() -> bool {
+    IRQ_TIMING.get(0).is_some_and(|irq_timing| *irq_timing != 0)
+}
+
+/// Context of the softirqs run on the current CPU, the ones run by `run_ksoftirqd` being the softirqs deferred by the interrupts.
+/// The ksoftirqd threads also run softirqs inline when they re-enable bottom halves, so their name does not tell the deferred ones.
+pub fn softirq_context() -> ExecutionContext {
+    match KSOFTIRQD_RUNNING.get(0) {
+        Some(running) if *running != 0 => ExecutionContext::Ksoftirqd,
+        _ => ExecutionContext::Softirq,
+    }
+}
+
+/// Marks the current CPU as running the softirqs deferred to its ksoftirqd thread, or as done with them
+pub fn set_ksoftirqd_running(running: bool) {
+    if let Some(flag) = KSOFTIRQD_RUNNING.get_ptr_mut(0) {
+        unsafe { *flag = running as u32 };
+    }
+}
+
+/// Keeps the first raise of a softirq until it runs, the raises of a pending softirq being served by the same run
+pub fn record_softirq_raise(vector: u32) {
+    if !is_timing_irqs() {
+        return;
+    }
+
+    if let Some(raised) = SOFTIRQ_RAISES.get_ptr_mut(vector) {
+        unsafe {
+            if *raised == 0 {
+                *raised = bpf_ktime_get_ns();
+            }
+        }
+    }
+}
+
+/// Opens the span of a softirq or interrupt handler entered on the current CPU
+pub fn start_irq_span(context: ExecutionContext, number: u32) {
+    if !is_timing_irqs() {
+        return;
+    }
+
+    let raised = match (context, SOFTIRQ_RAISES.get_ptr_mut(number)) {
+        (ExecutionContext::Hardirq, _) | (_, None) => 0,
+        (_, Some(raised)) => unsafe { core::mem::replace(&mut *raised, 0) },
+    };
+
+    if let Some(span) = IRQ_STARTS.get_ptr_mut(context as u32) {
+        unsafe {
+            *span = IrqSpan {
+                start: bpf_ktime_get_ns(),
+                raised,
+                number,
+                cpuid: bpf_get_smp_processor_id(),
+                tid: bpf_get_current_pid_tgid() as u32,
+                context,
+            };
+        }
+    }
+}
+
+/// Records the span of a softirq or interrupt handler exiting on the current CPU, the ones entered before the capture being ignored
+pub fn end_irq_span(context: ExecutionContext, number: u32) -> Result<(), u32> {
+    if !is_timing_irqs() {
+        return Ok(());
+    }
+
+    let Some(span) = IRQ_STARTS.get_ptr_mut(context as u32) else {
+        return Ok(());
+    };
+
+    let started = unsafe { core::mem::replace(&mut (*span).start, 0) };
+    let span = unsafe { *span };
+
+    if started == 0 || span.number != number {
+        return Ok(());
+    }
+
+    let time = unsafe { bpf_ktime_get_ns() };
+
+    record_event(&IRQ_SPANS, EventMap::IrqSpans, time, IrqSpan { start: started, ..span }).map_err(|_| 0u32)
+}
+
+/// Records a poll of a NAPI instance, with the name of its device read from the `__data_loc` of the tracepoint
+pub fn record_napi_poll(ctx: &TracePointContext) -> Result<(), u32> {
+    if !is_timing_irqs() {
+        return Ok(());
+    }
+
+    let Some(layout) = NAPI_POLL_LAYOUT.get(0) else {
+        return Ok(());
+    };
+
+    let mut device = [0u8; 16];
+
+    if layout.dev_name != 0 {
+        // The offset of the string in the record is in the lower 16 bits, its length in the upper ones
+        let data_loc: u32 = read_field(ctx, layout.dev_name);
+        let name = unsafe { (ctx.as_ptr() as *const u8).add((data_loc & 0xFFFF) as usize) };
+        let _ = unsafe { bpf_probe_read_kernel_str_bytes(name, &mut device) };
+    }
+
+    let time = unsafe { bpf_ktime_get_ns() };
+    let poll = NapiPoll {
+        napi: read_field(ctx, layout.napi),
+        device,
+        work: read_field(ctx, layout.work),
+        budget: read_field(ctx, layout.budget),
+        cpuid: unsafe { bpf_get_smp_processor_id() },
+        context: execution_context(),
+    };
+
+    record_event(&NAPI_POLLS, EventMap::NapiPolls, time, poll).map_err(|_| 0u32)
+}
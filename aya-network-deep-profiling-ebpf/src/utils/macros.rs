@@ -1,15 +1,31 @@
 #[macro_export]
 macro_rules! profile_function {
+    (
+        Kernel,
+        k,
+        $($function:ident),*$(,)?
+    ) => {
+        $crate::profile_function!(@probes Kernel, k, $($function),*);
+        $crate::trampoline!(probe_try_enter_, probe_try_ret_, $($function),*);
+    };
     (
         $function_type:expr,
         $probe_type:expr,
         $($function:ident),*$(,)?
+    ) => {
+        $crate::profile_function!(@probes $function_type, $probe_type, $($function),*);
+    };
+    (
+        @probes
+        $function_type:expr,
+        $probe_type:expr,
+        $($function:ident),*$(,)?
     ) => {
         paste::paste! {
             $(
                 #[aya_ebpf::macros::[<$probe_type:lower probe>]]
-                pub fn [<probe_enter_ $function>](_ctx: aya_ebpf::programs::ProbeContext) -> u32 {
-                    match [<probe_try_enter_ $function>]() {
+                pub fn [<probe_enter_ $function>](ctx: aya_ebpf::programs::ProbeContext) -> u32 {
+                    match [<probe_try_enter_ $function>](ctx) {
                         Ok(ret) => ret,
                         Err(ret) => {
                             unsafe {
@@ -20,8 +36,12 @@ macro_rules! profile_function {
                     }
                 }
 
-                fn [<probe_try_enter_ $function>]() -> Result<u32, u32> {
-                    let cpuid = unsafe { aya_ebpf::helpers::bpf_get_smp_processor_id() } as u32;
+                fn [<probe_try_enter_ $function>]<C: crate::utils::packet::FunctionArguments>(ctx: C) -> Result<u32, u32> {
+                    if !crate::utils::scope::in_scope() {
+                        return Ok(0);
+                    }
+
+                    let call = crate::utils::context::CallContext::current();
                     /*
                     let stack_id = match unsafe { crate::STACK_TRACES.get_stackid(&ctx, 0) } {
                         Ok(stack_id) => stack_id,
@@ -32,9 +52,10 @@ macro_rules! profile_function {
                     let direction = aya_network_deep_profiling_common::FunctionDirection::Entry;
 
                     //crate::utils::log::log_ctx(crate::utils::log::LogType::Debug, &fctx.ctx, function.as_str(), Some(direction));
-                    let depth = crate::utils::function::increment_depth(&cpuid)?;
-                    crate::utils::time::[<log_ $function_type:snake:lower _time>](function, direction, depth, cpuid)?;
-                    crate::utils::function::set_function_active(&cpuid, true)?;
+                    let depth = crate::utils::function::increment_depth(&call)?;
+                    crate::utils::time::[<log_ $function_type:snake:lower _time>](function, direction, depth, &call)?;
+                    crate::utils::function::set_function_active(&call, true)?;
+                    crate::utils::packet::trace_packet(&ctx, function, &call)?;
                     //crate::utils::function::register_function(&stack_id, function.as_id())?;
 
                     Ok(0)
@@ -55,15 +76,19 @@ macro_rules! profile_function {
                 }
 
                 fn [<probe_try_ret_ $function>]() -> Result<u32, u32> {
-                    let cpuid = unsafe { aya_ebpf::helpers::bpf_get_smp_processor_id() } as u32;
+                    if !crate::utils::scope::in_scope() {
+                        return Ok(0);
+                    }
+
+                    let call = crate::utils::context::CallContext::current();
 
                     let function = aya_network_deep_profiling_common::[<$function_type:camel Function>]::$function;
                     let direction = aya_network_deep_profiling_common::FunctionDirection::Exit;
 
                     //crate::utils::log::log_ctx(crate::utils::log::LogType::Debug, &fctx.ctx, function.as_str(), Some(direction));
-                    let depth = crate::utils::function::decrement_depth(&cpuid)?;
-                    crate::utils::time::[<log_ $function_type:snake:lower _time>](function, direction, depth, cpuid)?;
-                    crate::utils::function::set_function_active(&cpuid, false)?;
+                    let depth = crate::utils::function::decrement_depth(&call)?;
+                    crate::utils::time::[<log_ $function_type:snake:lower _time>](function, direction, depth, &call)?;
+                    crate::utils::function::set_function_active(&call, false)?;
 
                     Ok(0)
                 }
@@ -72,6 +97,43 @@ macro_rules! profile_function {
     };
 }
 
+/// fentry/fexit counterparts of the kprobe programs, sharing their entry and return logic.
+/// One program is needed per function since a BTF trampoline program is bound to its target at load time.
+#[macro_export]
+macro_rules! trampoline {
+    ($try_enter:ident, $try_ret:ident, $($function:ident),*$(,)?) => {
+        paste::paste! {
+            $(
+                #[aya_ebpf::macros::fentry]
+                pub fn [<fentry_ $function>](ctx: aya_ebpf::programs::FEntryContext) -> u32 {
+                    match [<$try_enter $function>](ctx) {
+                        Ok(ret) => ret,
+                        Err(ret) => {
+                            unsafe {
+                                aya_ebpf::bpf_printk!(b"Error in $function");
+                            }
+                            ret
+                        },
+                    }
+                }
+
+                #[aya_ebpf::macros::fexit]
+                pub fn [<fexit_ $function>](_ctx: aya_ebpf::programs::FExitContext) -> u32 {
+                    match [<$try_ret $function>]() {
+                        Ok(ret) => ret,
+                        Err(ret) => {
+                            unsafe {
+                                aya_ebpf::bpf_printk!(b"Error in $function");
+                            }
+                            ret
+                        },
+                    }
+                }
+            )*
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! alloc {
     ($alloc_type:ident, $($function:ident),*) => {
@@ -91,9 +153,13 @@ macro_rules! alloc {
                 }
 
                 fn [<try_tracepoint_ $function>](ctx: aya_ebpf::programs::TracePointContext) -> Result<u32, u32> {
+                    if !crate::utils::scope::in_scope() {
+                        return Ok(0);
+                    }
+
                     let fctx = crate::utils::context::get_full_ctx(ctx)?;
 
-                    if !crate::utils::function::should_profile_stack_id(fctx.cpuid) {
+                    if !crate::utils::function::should_profile_stack_id(&crate::utils::context::CallContext::current()) {
                         return Err(0);
                     }
 
@@ -111,8 +177,8 @@ macro_rules! alloc {
                         pid: fctx.pid,
                     };
 
-                    crate::[<$alloc_type:upper _ALLOCATIONS>].push(&alloc_info, 0).map_err(|_| 0u32)?;
-                    crate::[<TEMP_ $alloc_type:upper _ALLOCATIONS>].insert(&ptr, &alloc_info, 0).map_err(|_| 0u32)?;
+                    crate::utils::event::count_write(aya_network_deep_profiling_common::EventMap::[<$alloc_type:camel Allocations>], crate::[<$alloc_type:upper _ALLOCATIONS>].push(&alloc_info, 0)).map_err(|_| 0u32)?;
+                    crate::utils::event::count_write(aya_network_deep_profiling_common::EventMap::[<Temp $alloc_type:camel Allocations>], crate::[<TEMP_ $alloc_type:upper _ALLOCATIONS>].insert(&ptr, &alloc_info, 0)).map_err(|_| 0u32)?;
 
                     //aya_log_ebpf::trace!(&fctx.ctx, "ALLOC {} at {:X}", size, ptr);
 
@@ -142,9 +208,13 @@ macro_rules! free {
                 }
 
                 fn [<try_tracepoint_ $function>](ctx: aya_ebpf::programs::TracePointContext) -> Result<u32, u32> {
+                    if !crate::utils::scope::in_scope() {
+                        return Ok(0);
+                    }
+
                     let fctx = crate::utils::context::get_full_ctx(ctx)?;
 
-                    if !crate::utils::function::should_profile_stack_id(fctx.cpuid) {
+                    if !crate::utils::function::should_profile_stack_id(&crate::utils::context::CallContext::current()) {
                         return Err(0);
                     }
 
@@ -162,8 +232,8 @@ macro_rules! free {
                         pid: alloc_info.pid,
                     };
 
-                    crate::[<$alloc_type:upper _ALLOCATIONS>].push(&alloc_info, 0).map_err(|_| 0u32)?;
-                    crate::[<TEMP_ $alloc_type:upper _ALLOCATIONS>].remove(&ptr).map_err(|_| 0u32)?;
+                    crate::utils::event::count_write(aya_network_deep_profiling_common::EventMap::[<$alloc_type:camel Allocations>], crate::[<$alloc_type:upper _ALLOCATIONS>].push(&alloc_info, 0)).map_err(|_| 0u32)?;
+                    crate::utils::event::count_removal(aya_network_deep_profiling_common::EventMap::[<Temp $alloc_type:camel Allocations>], crate::[<TEMP_ $alloc_type:upper _ALLOCATIONS>].remove(&ptr)).map_err(|_| 0u32)?;
 
                     //aya_log_ebpf::trace!(&fctx.ctx, "FREED {} at {:X}", *size, ptr);
 
@@ -177,6 +247,8 @@ macro_rules! free {
 #[macro_export]
 macro_rules! log_time {
     ($($function:ident),*) => {
+        $crate::trampoline!(probe_try_enter_, probe_try_ret_, $($function),*);
+
         paste::paste! {
             $(
                 #[aya_ebpf::macros::kprobe]
@@ -192,18 +264,22 @@ macro_rules! log_time {
                     }
                 }
 
-                pub fn [<probe_try_enter_ $function>](ctx: aya_ebpf::programs::ProbeContext) -> Result<u32, u32> {
-                    let cpuid = unsafe { aya_ebpf::helpers::bpf_get_smp_processor_id() } as u32;
+                pub fn [<probe_try_enter_ $function>]<C: aya_ebpf::EbpfContext>(ctx: C) -> Result<u32, u32> {
+                    if !crate::utils::scope::in_scope() {
+                        return Ok(0);
+                    }
+
+                    let call = crate::utils::context::CallContext::current();
 
-                    if !crate::utils::function::should_profile_stack_id(cpuid) {
+                    if !crate::utils::function::should_profile_stack_id(&call) {
                         return Ok(0);
                     }
 
                     let function = aya_network_deep_profiling_common::Alloc::$function;
                     let direction = aya_network_deep_profiling_common::FunctionDirection::Entry;
 
-                    let depth = crate::utils::function::increment_depth(&cpuid)?;
-                    crate::utils::time::log_alloc_time(function, direction, depth, cpuid)?;
+                    let depth = crate::utils::function::increment_depth(&call)?;
+                    crate::utils::time::log_alloc_time(function, direction, depth, &call)?;
 
                     let stack_id = match unsafe { crate::STACK_TRACES.get_stackid(&ctx, 0) } {
                         Ok(stack_id) => stack_id,
@@ -228,17 +304,21 @@ macro_rules! log_time {
                 }
 
                 pub fn [<probe_try_ret_ $function>]() -> Result<u32, u32> {
-                    let cpuid = unsafe { aya_ebpf::helpers::bpf_get_smp_processor_id() } as u32;
+                    if !crate::utils::scope::in_scope() {
+                        return Ok(0);
+                    }
+
+                    let call = crate::utils::context::CallContext::current();
 
-                    if !crate::utils::function::should_profile_stack_id(cpuid) {
+                    if !crate::utils::function::should_profile_stack_id(&call) {
                         return Ok(0);
                     }
 
                     let function = aya_network_deep_profiling_common::Alloc::$function;
                     let direction = aya_network_deep_profiling_common::FunctionDirection::Exit;
 
-                    let depth = crate::utils::function::decrement_depth(&cpuid)?;
-                    crate::utils::time::log_alloc_time(function, direction, depth, cpuid)?;
+                    let depth = crate::utils::function::decrement_depth(&call)?;
+                    crate::utils::time::log_alloc_time(function, direction, depth, &call)?;
 
                     Ok(0)
                 }
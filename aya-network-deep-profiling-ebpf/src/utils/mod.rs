@@ -1,5 +1,16 @@
 pub mod context;
+pub mod drop;
+pub mod event;
+pub mod flow;
 pub mod function;
+pub mod histogram;
+pub mod irq;
 pub mod log;
+mod macros;
+pub mod packet;
+pub mod residence;
+pub mod scope;
+pub mod skb;
+pub mod socket;
+pub mod stream;
 pub mod time;
-mod macros;
\ No newline at end of file
@@ -0,0 +1,54 @@
+use aya_ebpf::EbpfContext;
+use aya_ebpf::programs::{FEntryContext, ProbeContext};
+use aya_network_deep_profiling_common::{EventMap, KernelFunction, PacketStage, PacketStageKind};
+use crate::utils::context::CallContext;
+use crate::utils::event::record_event;
+use crate::{PACKET_PATHS, PACKET_STAGES};
+
+/// Context of a program run on the entry of a function, giving access to its arguments
+pub trait FunctionArguments: EbpfContext {
+    fn argument(&self, n: usize) -> Option<u64>;
+}
+
+impl FunctionArguments for ProbeContext {
+    fn argument(&self, n: usize) -> Option<u64> {
+        self.arg(n)
+    }
+}
+
+impl FunctionArguments for FEntryContext {
+    fn argument(&self, n: usize) -> Option<u64> {
+        Some(unsafe { self.arg(n) })
+    }
+}
+
+fn is_tracing_packets() -> bool {
+    PACKET_PATHS.get(0).is_some_and(|packet_paths| *packet_paths != 0)
+}
+
+pub fn record_packet_stage(skb: u64, function: Option<KernelFunction>, kind: PacketStageKind, call: &CallContext) -> Result<(), u32> {
+    if skb == 0 || !is_tracing_packets() {
+        return Ok(());
+    }
+
+    let time = unsafe { aya_ebpf::helpers::bpf_ktime_get_ns() };
+    let stage = PacketStage {
+        skb,
+        cpuid: call.cpuid,
+        tid: call.tid,
+        tgid: call.tgid,
+        function,
+        kind,
+    };
+
+    record_event(&PACKET_STAGES, EventMap::PacketStages, time, stage).map_err(|_| 0u32)
+}
+
+/// Records that the packet given to a profiled function went through it, if the function takes a `sk_buff`
+pub fn trace_packet<C: FunctionArguments>(ctx: &C, function: KernelFunction, call: &CallContext) -> Result<(), u32> {
+    let Some(skb) = function.skb_argument().and_then(|n| ctx.argument(n)) else {
+        return Ok(());
+    };
+
+    record_packet_stage(skb, Some(function), PacketStageKind::Function, call)
+}
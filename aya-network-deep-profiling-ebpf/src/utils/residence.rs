@@ -0,0 +1,90 @@
+use core::mem;
+use aya_ebpf::EbpfContext;
+use aya_ebpf::helpers::{bpf_ktime_get_ns, bpf_xdp_adjust_meta};
+use aya_ebpf::programs::{TcContext, XdpContext};
+use aya_network_deep_profiling_common::{EventMap, PacketArrival};
+use crate::utils::event::{count_removal, count_write, record_event};
+use crate::{PACKET_ARRIVALS, PACKET_RESIDENCES, RESIDENCE_TIMES};
+
+/// Tells our metadata apart from the metadata of another XDP program
+const XDP_METADATA_MAGIC: u32 = 0x4144_5052;
+
+/// Metadata prepended to the packet by the XDP program, the kernel keeping it in the `sk_buff` up to the TC programs
+#[repr(C)]
+struct XdpMetadata {
+    timestamp: u64,
+    magic: u32,
+    /// The metadata size must be a multiple of 4
+    padding: u32,
+}
+
+pub fn is_timing_residence() -> bool {
+    RESIDENCE_TIMES.get(0).is_some_and(|residence_times| *residence_times != 0)
+}
+
+/// Stamps the arrival time of the packet in its metadata, identifying it in the TC ingress program.
+/// The packet pointers are invalidated, this must be called after the packet is read.
+pub fn stamp_arrival(ctx: &XdpContext, time: u64) -> Result<(), ()> {
+    if unsafe { bpf_xdp_adjust_meta(ctx.ctx, -(mem::size_of::<XdpMetadata>() as i32)) } != 0 {
+        // Not supported by the driver
+        return Err(());
+    }
+
+    let metadata = ctx.metadata();
+
+    if metadata + mem::size_of::<XdpMetadata>() > ctx.metadata_end() {
+        return Err(());
+    }
+
+    unsafe {
+        *(metadata as *mut XdpMetadata) = XdpMetadata {
+            timestamp: time,
+            magic: XDP_METADATA_MAGIC,
+            padding: 0,
+        };
+    }
+
+    Ok(())
+}
+
+/// Time stamped by the XDP program in the metadata of a received packet, `None` when the packet did not go through it
+pub fn xdp_timestamp(ctx: &TcContext) -> Option<u64> {
+    let metadata = unsafe { (*ctx.skb.skb).data_meta } as usize;
+
+    if metadata + mem::size_of::<XdpMetadata>() > ctx.data() {
+        return None;
+    }
+
+    let metadata = unsafe { &*(metadata as *const XdpMetadata) };
+
+    (metadata.magic == XDP_METADATA_MAGIC).then_some(metadata.timestamp)
+}
+
+/// Keeps the arrival of a packet stamped by the XDP program until it is enqueued to a socket.
+/// The context of a TC program is the `sk_buff` itself, its address identifies the packet in the socket probe.
+pub fn record_arrival(ctx: &TcContext, xdp_time: u64, tc_time: u64) -> Result<(), ()> {
+    let arrival = PacketArrival {
+        xdp_time,
+        tc_time,
+        if_index: unsafe { (*ctx.skb.skb).ifindex },
+    };
+
+    count_write(EventMap::PacketArrivals, PACKET_ARRIVALS.insert(&(ctx.as_ptr() as u64), &arrival, 0)).map_err(|_| ())
+}
+
+/// Records the residence of a packet in the stack when it is enqueued to a socket
+pub fn record_socket_enqueue(skb: u64) -> Result<(), u32> {
+    let Some(arrival) = (unsafe { PACKET_ARRIVALS.get(&skb) }).copied() else {
+        return Ok(());
+    };
+    count_removal(EventMap::PacketArrivals, PACKET_ARRIVALS.remove(&skb)).map_err(|_| 0u32)?;
+
+    let time = unsafe { bpf_ktime_get_ns() };
+    record_event(&PACKET_RESIDENCES, EventMap::PacketResidences, time, arrival).map_err(|_| 0u32)
+}
+
+/// Forgets the arrival of a packet released before reaching a socket, e.g. forwarded or dropped, the next packets reusing its address
+pub fn forget_arrival(skb: u64) {
+    // Most of the released packets were never stamped
+    let _ = count_removal(EventMap::PacketArrivals, PACKET_ARRIVALS.remove(&skb));
+}
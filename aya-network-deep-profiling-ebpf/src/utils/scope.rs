@@ -0,0 +1,40 @@
+use aya_ebpf::helpers::{bpf_get_current_cgroup_id, bpf_get_current_comm, bpf_get_current_pid_tgid};
+use crate::{SCOPE, SCOPE_PIDS};
+
+/// Whether the current task is in the scope given with `--pid`, `--comm` and `--cgroup`.
+/// Events of tasks out of scope are dropped before being recorded.
+pub fn in_scope() -> bool {
+    let Some(scope) = SCOPE.get(0) else {
+        return true;
+    };
+
+    if scope.filter_pids {
+        let pid_tgid = bpf_get_current_pid_tgid();
+        let tgid = (pid_tgid >> 32) as u32;
+        let pid = pid_tgid as u32;
+
+        if unsafe { SCOPE_PIDS.get(&tgid).is_none() && SCOPE_PIDS.get(&pid).is_none() } {
+            return false;
+        }
+    }
+
+    if scope.comm[0] != 0 {
+        let Ok(comm) = bpf_get_current_comm() else {
+            return false;
+        };
+
+        // Compared as integers to avoid a call to memcmp
+        let comm: [u64; 2] = unsafe { core::mem::transmute(comm) };
+        let expected_comm: [u64; 2] = unsafe { core::mem::transmute(scope.comm) };
+
+        if comm[0] != expected_comm[0] || comm[1] != expected_comm[1] {
+            return false;
+        }
+    }
+
+    if scope.cgroup_id != 0 && unsafe { bpf_get_current_cgroup_id() } != scope.cgroup_id {
+        return false;
+    }
+
+    true
+}
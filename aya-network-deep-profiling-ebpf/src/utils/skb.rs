@@ -0,0 +1,56 @@
+use aya_ebpf::EbpfContext;
+use aya_ebpf::helpers::{bpf_get_smp_processor_id, bpf_ktime_get_ns};
+use aya_network_deep_profiling_common::{EventMap, ExecutionContext, SkbEvent, SkbEventKind};
+use crate::utils::context::CallContext;
+use crate::utils::event::{count_removal, count_write, record_event};
+use crate::{SKB_COPY_SOURCES, SKB_EVENTS, SKB_TRACKING, STACK_TRACES};
+
+pub fn is_tracking_skbs() -> bool {
+    SKB_TRACKING.get(0).is_some_and(|skb_tracking| *skb_tracking != 0)
+}
+
+/// Owner of a clone or copy call, as the owner of the open calls
+fn copy_key(call: &CallContext) -> u64 {
+    match call.context {
+        ExecutionContext::Task => call.tid as u64,
+        context => (context as u64) << 32 | call.cpuid as u64,
+    }
+}
+
+/// Records an event of an `sk_buff`, with the stack giving its allocation or release site
+pub fn record_skb_event<C: EbpfContext>(ctx: &C, skb: u64, parent: u64, function: u64, kind: SkbEventKind) -> Result<(), u32> {
+    if skb == 0 {
+        return Ok(());
+    }
+
+    let time = unsafe { bpf_ktime_get_ns() };
+    let event = SkbEvent {
+        skb,
+        parent,
+        function,
+        stack_id: unsafe { STACK_TRACES.get_stackid(ctx, 0) }.unwrap_or(-1),
+        cpuid: unsafe { bpf_get_smp_processor_id() },
+        kind,
+    };
+
+    record_event(&SKB_EVENTS, EventMap::SkbEvents, time, event).map_err(|_| 0u32)
+}
+
+/// Keeps the sk_buff given to a clone or copy function until it returns the new one
+pub fn enter_skb_copy(skb: u64) -> Result<(), u32> {
+    let key = copy_key(&CallContext::current());
+
+    count_write(EventMap::SkbCopySources, SKB_COPY_SOURCES.insert(&key, &skb, 0)).map_err(|_| 0u32)
+}
+
+/// sk_buff given to the clone or copy function returning, 0 when its entry was missed
+pub fn exit_skb_copy() -> u64 {
+    let key = copy_key(&CallContext::current());
+
+    let Some(parent) = (unsafe { SKB_COPY_SOURCES.get(&key) }).copied() else {
+        return 0;
+    };
+    let _ = count_removal(EventMap::SkbCopySources, SKB_COPY_SOURCES.remove(&key));
+
+    parent
+}
@@ -0,0 +1,67 @@
+use aya_ebpf::helpers::{bpf_ktime_get_ns, bpf_probe_read_kernel};
+use aya_network_deep_profiling_common::{EventMap, FlowId, SockLayout, SocketEvent, SocketEventKind};
+use crate::utils::context::CallContext;
+use crate::utils::event::record_event;
+use crate::{SOCKET_EVENTS, SOCKET_TRACKING, SOCK_LAYOUT};
+
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+
+pub fn is_tracking_sockets() -> bool {
+    SOCKET_TRACKING.get(0).is_some_and(|socket_tracking| *socket_tracking != 0)
+}
+
+/// Reads a member of a socket, the default value when it cannot be read
+fn read_member<T: Default>(sock: u64, offset: u32) -> T {
+    unsafe { bpf_probe_read_kernel((sock + offset as u64) as *const T) }.unwrap_or_default()
+}
+
+/// Local and remote addresses and ports of a socket
+fn socket_flow(sock: u64, layout: &SockLayout) -> FlowId {
+    let mut flow = FlowId {
+        src_port: read_member(sock, layout.num),
+        dst_port: u16::from_be(read_member(sock, layout.dport)),
+        ..FlowId::default()
+    };
+
+    match read_member::<u16>(sock, layout.family) {
+        AF_INET => {
+            flow.ip_version = 4;
+            flow.src_addr[..4].copy_from_slice(&read_member::<[u8; 4]>(sock, layout.rcv_saddr));
+            flow.dst_addr[..4].copy_from_slice(&read_member::<[u8; 4]>(sock, layout.daddr));
+        }
+        AF_INET6 => {
+            flow.ip_version = 6;
+            flow.src_addr = read_member(sock, layout.v6_rcv_saddr);
+            flow.dst_addr = read_member(sock, layout.v6_daddr);
+        }
+        _ => {}
+    }
+
+    flow
+}
+
+/// Records a use of a socket by the current thread or interrupt context
+pub fn record_socket_event(sock: u64, function: u64, kind: SocketEventKind, call: &CallContext) -> Result<(), u32> {
+    if sock == 0 || !is_tracking_sockets() {
+        return Ok(());
+    }
+
+    let Some(layout) = SOCK_LAYOUT.get(0) else {
+        return Ok(());
+    };
+
+    let time = unsafe { bpf_ktime_get_ns() };
+    let event = SocketEvent {
+        sock,
+        function,
+        flow: socket_flow(sock, layout),
+        cpuid: call.cpuid,
+        tid: call.tid,
+        tgid: call.tgid,
+        kind,
+        context: call.context,
+    };
+
+    record_event(&SOCKET_EVENTS, EventMap::SocketEvents, time, event).map_err(|_| 0u32)
+}
@@ -0,0 +1,21 @@
+use aya_network_deep_profiling_common::{EventMap, FunctionCall, RecordId, StreamRecord};
+use crate::utils::event::count_write;
+use crate::EVENTS;
+
+/// Sends a function call to user space through the ring buffer
+pub fn stream_function_call<F: RecordId>(timestamp: u64, function_call: &FunctionCall<F>) -> Result<(), u32> {
+    let record = StreamRecord {
+        timestamp,
+        id: function_call.function.record_id(),
+        depth: function_call.depth,
+        cpuid: function_call.cpuid,
+        tid: function_call.tid,
+        tgid: function_call.tgid,
+        kind: F::KIND,
+        direction: function_call.direction,
+        context: function_call.context,
+        padding: [0; 5],
+    };
+
+    count_write(EventMap::Events, EVENTS.output(&record, 0)).map_err(|_| 0u32)
+}
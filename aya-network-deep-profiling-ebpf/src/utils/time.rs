@@ -1,17 +1,35 @@
+use aya_ebpf::maps::PerCpuHashMap;
+use aya_network_deep_profiling_common::{Event, EventMap, ExecutionContext, FunctionCall, FunctionDirection, FunctionSpan, RecordId, RecordKind, RecordingMode};
+use crate::{OPEN_CALLS, RECORDING_MODE};
+use crate::utils::context::CallContext;
+use crate::utils::event::{count_drop, count_removal, count_write, record_event};
+use crate::utils::histogram::record_histogram;
+use crate::utils::stream::stream_function_call;
+
+fn recording_mode() -> u32 {
+    RECORDING_MODE.get(0).copied().unwrap_or(RecordingMode::Spans as u32)
+}
+
+/// Open call of a function, owned by its thread in task context and by its CPU in interrupt context
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct CallKey {
+    pub function: u64,
+    pub owner: u32,
+    /// Depth of the caller
+    pub depth: u32,
+    pub kind: RecordKind,
+    pub context: ExecutionContext,
+    /// Hashed with the rest of the key, so it must be zeroed
+    pub padding: [u8; 6],
+}
+
 macro_rules! log_time {
     ($($name:expr, $function_enumeration:expr),*) => {
         paste::paste! {
             $(
-                pub fn [<log_ $name:snake:lower _time>](function: aya_network_deep_profiling_common::$function_enumeration, direction: aya_network_deep_profiling_common::FunctionDirection, depth: u32, cpuid: u32) -> Result<(), u32> {
-                    let time = unsafe { aya_ebpf::helpers::bpf_ktime_get_ns() };
-                    let function_call = aya_network_deep_profiling_common::FunctionCall {
-                        function,
-                        direction,
-                        depth,
-                        cpuid
-                    };
-
-                    crate::[<$name:snake:upper _FUNCTIONS_EXECUTION_TIMES>].insert(&time, &function_call, 0).map_err(|_| 0u32)
+                pub fn [<log_ $name:snake:lower _time>](function: aya_network_deep_profiling_common::$function_enumeration, direction: aya_network_deep_profiling_common::FunctionDirection, depth: u32, call: &crate::utils::context::CallContext) -> Result<(), u32> {
+                    log_call(&crate::[<$name:snake:upper _FUNCTIONS_EXECUTION_TIMES>], aya_network_deep_profiling_common::EventMap::[<$name:camel FunctionsExecutionTimes>], function, direction, depth, call)
                 }
             )*
         }
@@ -21,5 +39,72 @@ macro_rules! log_time {
 log_time!(
     kernel, KernelFunction,
     user, UserFunction,
+    dynamic, FunctionAddress,
     alloc, Alloc
-);
\ No newline at end of file
+);
+
+/// Records the entry or the exit of a function, `depth` being its depth on entry and the depth of its caller on exit.
+/// The entry time is kept until the function returns, to record a single span or to count the call in the histogram of the function.
+fn log_call<F: RecordId>(spans: &PerCpuHashMap<u64, Event<FunctionSpan<F>>>, map: EventMap, function: F, direction: FunctionDirection, depth: u32, call: &CallContext) -> Result<(), u32> {
+    let time = unsafe { aya_ebpf::helpers::bpf_ktime_get_ns() };
+
+    let recording_mode = recording_mode();
+
+    if recording_mode == RecordingMode::Stream as u32 {
+        let function_call = FunctionCall {
+            function,
+            direction,
+            depth,
+            cpuid: call.cpuid,
+            tid: call.tid,
+            tgid: call.tgid,
+            context: call.context,
+        };
+
+        return stream_function_call(time, &function_call);
+    }
+
+    let key = CallKey {
+        function: function.record_id(),
+        owner: match call.context {
+            ExecutionContext::Task => call.tid,
+            _ => call.cpuid,
+        },
+        depth: match direction {
+            FunctionDirection::Entry => depth.saturating_sub(1),
+            FunctionDirection::Exit => depth,
+        },
+        kind: F::KIND,
+        context: call.context,
+        padding: [0; 6],
+    };
+
+    match direction {
+        FunctionDirection::Entry => count_write(EventMap::OpenCalls, OPEN_CALLS.insert(&key, &time, 0)).map_err(|_| 0u32),
+        FunctionDirection::Exit => {
+            // Evicted, OPEN_CALLS being an LRU map whose inserts never fail, or entered before the programs were attached
+            let Some(start) = (unsafe { OPEN_CALLS.get(&key) }).copied() else {
+                count_drop(EventMap::OpenCalls);
+                return Ok(());
+            };
+            count_removal(EventMap::OpenCalls, OPEN_CALLS.remove(&key)).map_err(|_| 0u32)?;
+
+            if recording_mode == RecordingMode::Histogram as u32 {
+                return record_histogram(function, time.saturating_sub(start));
+            }
+
+            let span = FunctionSpan {
+                function,
+                start,
+                end: time,
+                depth,
+                cpuid: call.cpuid,
+                tid: call.tid,
+                tgid: call.tgid,
+                context: call.context,
+            };
+
+            record_event(spans, map, time, span).map_err(|_| 0u32)
+        }
+    }
+}
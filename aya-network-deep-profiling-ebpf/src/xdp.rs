@@ -1,9 +1,12 @@
 use crate::XDP_TIMES;
+use crate::utils::event::record_event;
+use crate::utils::flow::{flow_in_scope, parse_flow};
+use crate::utils::residence::stamp_arrival;
 use aya_ebpf::bindings::xdp_action::XDP_PASS;
 use aya_ebpf::helpers::bpf_ktime_get_ns;
 use aya_ebpf::macros::xdp;
 use aya_ebpf::programs::XdpContext;
-use aya_network_deep_profiling_common::EthHeader;
+use aya_network_deep_profiling_common::{EthHeader, EventMap, XdpPacket};
 
 #[xdp]
 pub fn xdp_packet_log(ctx: XdpContext) -> u32 {
@@ -34,6 +37,12 @@ fn try_xdp_packet_log(ctx: XdpContext) -> Result<u32, ()> {
     let eth_header: *const EthHeader = unsafe { ptr_at(&ctx, 0)? };
     let time = unsafe { bpf_ktime_get_ns() };
 
+    let flow = parse_flow(ctx.data(), ctx.data_end());
+
+    if !flow_in_scope(&flow) {
+        return Ok(XDP_PASS);
+    }
+
     /*
     unsafe {
         debug!(
@@ -48,7 +57,16 @@ fn try_xdp_packet_log(ctx: XdpContext) -> Result<u32, ()> {
         );
     }*/
 
-    XDP_TIMES.insert(&time, unsafe { &* eth_header }, 0).map_err(|_| ())?;
+    let packet = XdpPacket {
+        eth_header: unsafe { *eth_header },
+        if_index: unsafe { (*ctx.ctx).ingress_ifindex },
+        flow,
+    };
+
+    // Identifies the packet in the TC ingress program, for the flow latencies and the residence times, even when its XDP event is dropped
+    stamp_arrival(&ctx, time)?;
+
+    record_event(&XDP_TIMES, EventMap::XdpTimes, time, packet).map_err(|_| ())?;
 
     Ok(XDP_PASS)
-}
\ No newline at end of file
+}
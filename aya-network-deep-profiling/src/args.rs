@@ -1,5 +1,9 @@
-use clap::Parser;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use aya::programs::XdpFlags;
+use clap::{Parser, ValueEnum};
 use clap_verbosity_flag::Verbosity;
+use serde::Serialize;
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
@@ -10,6 +14,183 @@ pub struct Args {
     #[arg(long, default_value_t = 5_000_000_000)]
     pub timeout: u64,
 
+    /// Additional kernel functions to profile, attached at run time without rebuilding the eBPF object
+    #[arg(long, value_delimiter = ',')]
+    pub functions: Vec<String>,
+
+    /// File listing additional kernel functions to profile, one per line
+    #[arg(long)]
+    pub functions_file: Option<PathBuf>,
+
+    /// Kernel functions to profile matching a glob (`ip_rcv*`) or a regex between slashes (`/^tcp_v4_.*/`), expanded against available_filter_functions
+    #[arg(long = "kprobe", value_name = "PATTERN")]
+    pub kprobes: Vec<String>,
+
+    /// User-space function to profile, as `/path/to/binary:symbol` or `library.so:symbol` for a shared library of the ld.so cache
+    #[arg(long = "uprobe", value_name = "PATH:SYMBOL")]
+    pub uprobes: Vec<String>,
+
+    /// USDT probe to record with its arguments, as `PATH:PROVIDER:NAME`
+    #[arg(long = "usdt", value_name = "PATH:PROVIDER:NAME")]
+    pub usdts: Vec<String>,
+
+    /// List the USDT probes of a binary or shared library and exit
+    #[arg(long, value_name = "PATH")]
+    pub list_usdt: Option<String>,
+
+    /// Only record the events of these processes or threads
+    #[arg(long = "pid")]
+    pub pids: Vec<u32>,
+
+    /// Only record the events of the tasks with this name
+    #[arg(long)]
+    pub comm: Option<String>,
+
+    /// Only record the events of the tasks in this cgroup v2, under /sys/fs/cgroup or relative to it as in /proc/<pid>/cgroup
+    #[arg(long)]
+    pub cgroup: Option<PathBuf>,
+
+    /// Only attach the XDP and TC programs to these interfaces, all interfaces by default
+    #[arg(long = "iface", value_name = "INTERFACE")]
+    pub ifaces: Vec<String>,
+
+    /// Do not attach the XDP and TC programs to these interfaces
+    #[arg(long = "exclude-iface", value_name = "INTERFACE")]
+    pub exclude_ifaces: Vec<String>,
+
+    /// Only record the packets of this transport protocol in the XDP and TC programs
+    #[arg(long, value_enum)]
+    pub flow_protocol: Option<FlowProtocol>,
+
+    /// Only record the packets from or to this port in the XDP and TC programs
+    #[arg(long)]
+    pub flow_port: Option<u16>,
+
+    /// Only record the packets from or to this IPv4 or IPv6 address in the XDP and TC programs
+    #[arg(long)]
+    pub flow_host: Option<IpAddr>,
+
+    /// XDP attach mode, drv falls back to skb on the interfaces that refuse native XDP
+    #[arg(long, value_enum, default_value_t = XdpMode::Drv)]
+    pub xdp_mode: XdpMode,
+
+    /// Stream the function calls to shared/events.bin during the run, instead of storing them in maps read at the end
+    #[arg(long)]
+    pub stream: bool,
+
+    /// Size in MiB of the ring buffer of `--stream`, rounded up to a power of two, the buffer taking a single page without `--stream`
+    #[arg(long, value_name = "MIB", default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..=2048))]
+    pub stream_buffer: u32,
+
+    /// Keep a log2 latency histogram per function in the kernel instead of recording each call, cheap enough to leave running
+    #[arg(long, conflicts_with = "stream")]
+    pub histogram: bool,
+
+    /// Record the packets going through the profiled functions taking a `sk_buff`, to rebuild the path of each packet
+    #[arg(long)]
+    pub packet_paths: bool,
+
+    /// Time the received packets from the XDP hook to the TC ingress hook and to their socket, per packet and per interface
+    #[arg(long)]
+    pub residence_times: bool,
+
+    /// Record the packets dropped by the kernel with their drop reason, the function that dropped them and its stack
+    #[arg(long)]
+    pub drops: bool,
+
+    /// Follow each sk_buff from its allocation or copy to its release, to find the buffer churn and leaks
+    #[arg(long)]
+    pub skb_lifetimes: bool,
+
+    /// Time the softirqs and interrupt handlers on each CPU, the latency from the raise of a softirq to its run,
+    /// and the network work done inline or deferred to ksoftirqd
+    #[arg(long)]
+    pub irq_times: bool,
+
+    /// Group the request timelines of the `--uprobe` functions per connection, from the sockets their thread received from or sent to,
+    /// with the delivery of the packets of these sockets done by the softirqs of any thread or CPU
+    #[arg(long, requires = "uprobes")]
+    pub connections: bool,
+
+    /// Also count the drops of each reason over windows of this many milliseconds
+    #[arg(long, value_name = "MS", requires = "drops")]
+    pub drop_interval: Option<u64>,
+
+    /// Max entries of each map recording events, such as the execution times or the allocations
+    #[arg(long, default_value_t = 1_000_000)]
+    pub max_events: u32,
+
+    /// Max entries of the stack traces map
+    #[arg(long, default_value_t = 1_000_000)]
+    pub max_stacks: u32,
+
+    /// Max entries of the map of the stack ids of the profiled functions
+    #[arg(long, default_value_t = 500)]
+    pub max_registered_functions: u32,
+
+    /// Max number of threads whose call depth is tracked at once, the least recently used being evicted
+    #[arg(long, default_value_t = 32768)]
+    pub max_tasks: u32,
+
+    /// Interval in seconds between two readouts of the map fill levels during the run, 0 to disable them
+    #[arg(long, default_value_t = 5)]
+    pub pressure_interval: u64,
+
+    /// Mechanism used to time the kernel functions, fentry and kprobe-multi fall back to kprobes per function when they cannot be used
+    #[arg(long, value_enum, default_value_t = ProbeBackend::Kprobe)]
+    pub backend: ProbeBackend,
+
+    /// Keep going when a program cannot be loaded or attached, failures are listed in the attach report
+    #[arg(long)]
+    pub keep_going: bool,
+
     #[command(flatten)]
     pub verbosity: Verbosity,
 }
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+pub enum ProbeBackend {
+    Kprobe,
+    Fentry,
+    KprobeMulti,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+pub enum FlowProtocol {
+    Tcp,
+    Udp,
+    Icmp,
+    Icmpv6,
+}
+
+impl FlowProtocol {
+    /// IP protocol number
+    pub fn number(self) -> u8 {
+        match self {
+            FlowProtocol::Icmp => 1,
+            FlowProtocol::Tcp => 6,
+            FlowProtocol::Udp => 17,
+            FlowProtocol::Icmpv6 => 58
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum, Serialize)]
+pub enum XdpMode {
+    /// Generic XDP, after the socket buffer allocation
+    Skb,
+    /// Native XDP, in the driver
+    Drv,
+    /// Offloaded to the NIC
+    Hw,
+}
+
+impl XdpMode {
+    pub fn flags(self) -> XdpFlags {
+        match self {
+            XdpMode::Skb => XdpFlags::SKB_MODE,
+            XdpMode::Drv => XdpFlags::DRV_MODE,
+            XdpMode::Hw => XdpFlags::HW_MODE
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::io;
+use anyhow::anyhow;
+use aya::{Btf, Ebpf};
+use aya::programs::{FEntry, FExit, KProbeError, ProgramError, TracePointError, UProbeError};
+use aya::programs::fentry::FEntryLinkId;
+use aya::programs::fexit::FExitLinkId;
+use log::warn;
+use serde::Serialize;
+use crate::ARGS;
+use crate::symbols::{kernel_function_targets, symbol_table, TRACEABLE_FUNCTIONS};
+
+/// Mechanism that produced the data of a function
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub enum ProbeMechanism {
+    Kprobe,
+    KprobeMulti,
+    Fentry,
+    Uprobe,
+    Usdt,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub enum AttachStatus {
+    Attached,
+    MissingSymbol,
+    NoTrace,
+    VerifierError,
+    PermissionDenied,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AttachReportRow {
+    pub program: String,
+    pub target: String,
+    pub status: AttachStatus,
+    pub error: Option<String>,
+}
+
+/// Outcome of every program load and attach.
+/// Without `--keep-going` the first failure is returned as an error, as before.
+#[derive(Default)]
+pub struct AttachReport {
+    pub rows: Vec<AttachReportRow>,
+}
+
+impl AttachReport {
+    /// Records a program load, a failed load is reported for each of the program's targets
+    pub fn load<T: AsRef<str>>(&mut self, program: &str, targets: &[T], result: Result<(), ProgramError>) -> anyhow::Result<bool> {
+        match result {
+            Ok(()) => Ok(true),
+            Err(error) => {
+                if !ARGS.keep_going {
+                    return Err(error.into());
+                }
+
+                for target in targets {
+                    self.failure(program, target.as_ref(), &error);
+                }
+
+                Ok(false)
+            }
+        }
+    }
+
+    /// Records an attachment, returns the link id if the program was attached
+    pub fn attach<L>(&mut self, program: &str, target: &str, result: Result<L, ProgramError>) -> anyhow::Result<Option<L>> {
+        match result {
+            Ok(link_id) => {
+                self.attached(program, target);
+                Ok(Some(link_id))
+            }
+            Err(error) => {
+                if !ARGS.keep_going {
+                    return Err(error.into());
+                }
+
+                self.failure(program, target, &error);
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Records a target that could not be resolved to a symbol
+    pub fn missing_symbol(&mut self, program: &str, target: &str, error: anyhow::Error) -> anyhow::Result<()> {
+        if !ARGS.keep_going {
+            return Err(error);
+        }
+
+        warn!("Could not attach program {program} to {target}: {:?} ({error})", AttachStatus::MissingSymbol);
+
+        self.rows.push(AttachReportRow {
+            program: program.to_string(),
+            target: target.to_string(),
+            status: AttachStatus::MissingSymbol,
+            error: Some(error.to_string()),
+        });
+
+        Ok(())
+    }
+
+    pub fn attached(&mut self, program: &str, target: &str) {
+        self.rows.push(AttachReportRow {
+            program: program.to_string(),
+            target: target.to_string(),
+            status: AttachStatus::Attached,
+            error: None,
+        });
+    }
+
+    /// Whether a program was attached to a target
+    pub fn is_attached(&self, program: &str, target: &str) -> bool {
+        self.rows.iter().any(|row| row.status == AttachStatus::Attached && row.program == program && row.target == target)
+    }
+
+    fn failure(&mut self, program: &str, target: &str, error: &ProgramError) {
+        let status = failure_status(target, error);
+        warn!("Could not attach program {program} to {target}: {status:?} ({error})");
+
+        self.rows.push(AttachReportRow {
+            program: program.to_string(),
+            target: target.to_string(),
+            status,
+            error: Some(error.to_string()),
+        });
+    }
+
+    pub fn print(&self) {
+        let count = |status: AttachStatus| self.rows.iter().filter(|row| row.status == status).count();
+
+        println!("================================================ Attach Report ================================================");
+        println!(
+            "Attached: {}, Missing symbol: {}, No trace: {}, Verifier error: {}, Permission denied: {}, Failed: {}",
+            count(AttachStatus::Attached),
+            count(AttachStatus::MissingSymbol),
+            count(AttachStatus::NoTrace),
+            count(AttachStatus::VerifierError),
+            count(AttachStatus::PermissionDenied),
+            count(AttachStatus::Failed),
+        );
+
+        let failures = self.rows.iter().filter(|row| row.status != AttachStatus::Attached).collect::<Vec<&AttachReportRow>>();
+
+        if failures.is_empty() {
+            return;
+        }
+
+        println!("---------------------------------------------------------------------------------------------------------------");
+        println!("{: <40} {: <45} {: <20}", "Program", "Target", "Status");
+
+        for row in failures {
+            println!("{: <40} {: <45} {: <20}", row.program, row.target, format!("{:?}", row.status));
+        }
+    }
+}
+
+/// Attaches the fentry and fexit programs of a compile-time profiled function, returns the target symbol.
+/// A trampoline is bound to a single symbol, so functions with several clones are left to the kprobes.
+pub fn attach_trampoline(ebpf: &mut Ebpf, btf: &Btf, function: &str) -> anyhow::Result<(String, FEntryLinkId, FExitLinkId)> {
+    let targets = kernel_function_targets(&function.replace("_p_", "."));
+    let [target] = targets.as_slice() else {
+        return Err(anyhow!("{} symbols to attach to", targets.len()));
+    };
+
+    let fentry: &mut FEntry = ebpf.program_mut(&format!("fentry_{function}")).unwrap().try_into()?;
+    fentry.load(target, btf)?;
+    let fentry_link_id = fentry.attach()?;
+
+    let fexit: &mut FExit = ebpf.program_mut(&format!("fexit_{function}")).unwrap().try_into()?;
+    let fexit_link_id = match fexit.load(target, btf).and_then(|_| fexit.attach()) {
+        Ok(link_id) => link_id,
+        Err(error) => {
+            let fentry: &mut FEntry = ebpf.program_mut(&format!("fentry_{function}")).unwrap().try_into()?;
+            fentry.detach(fentry_link_id)?;
+            return Err(error.into());
+        }
+    };
+
+    Ok((target.clone(), fentry_link_id, fexit_link_id))
+}
+
+fn io_error(error: &ProgramError) -> Option<&io::Error> {
+    match error {
+        ProgramError::LoadError { io_error, .. } => Some(io_error),
+        ProgramError::SyscallError(syscall_error) => Some(&syscall_error.io_error),
+        ProgramError::KProbeError(KProbeError::FileError { io_error, .. }) => Some(io_error),
+        ProgramError::TracePointError(TracePointError::FileError { io_error, .. }) => Some(io_error),
+        ProgramError::UProbeError(UProbeError::FileError { io_error, .. }) => Some(io_error),
+        ProgramError::IOError(io_error) => Some(io_error),
+        _ => None
+    }
+}
+
+fn failure_status(target: &str, error: &ProgramError) -> AttachStatus {
+    let errno = io_error(error).and_then(io::Error::raw_os_error);
+
+    match error {
+        ProgramError::LoadError { .. } if errno == Some(libc::EPERM) => AttachStatus::PermissionDenied,
+        ProgramError::LoadError { .. } => AttachStatus::VerifierError,
+        _ if matches!(errno, Some(libc::EPERM) | Some(libc::EACCES)) => AttachStatus::PermissionDenied,
+        ProgramError::UProbeError(UProbeError::SymbolError { .. } | UProbeError::InvalidTarget { .. }) => AttachStatus::MissingSymbol,
+        ProgramError::TracePointError(_) if errno == Some(libc::ENOENT) => AttachStatus::MissingSymbol,
+        // Unlike kernel functions, user functions and tracepoints are written `a:b`
+        ProgramError::SyscallError(_) | ProgramError::KProbeError(_) if !target.contains(':') => {
+            if !symbol_table().values().any(|name| name == target) {
+                AttachStatus::MissingSymbol
+            }
+            else if TRACEABLE_FUNCTIONS.as_ref().is_some_and(|functions| !functions.contains(target)) {
+                AttachStatus::NoTrace
+            }
+            else {
+                AttachStatus::Failed
+            }
+        }
+        _ => AttachStatus::Failed
+    }
+}
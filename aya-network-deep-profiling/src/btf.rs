@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fs;
+use anyhow::anyhow;
+
+const VMLINUX_BTF: &str = "/sys/kernel/btf/vmlinux";
+const BTF_MAGIC: u16 = 0xEB9F;
+
+const BTF_KIND_INT: u32 = 1;
+const BTF_KIND_ARRAY: u32 = 3;
+const BTF_KIND_STRUCT: u32 = 4;
+const BTF_KIND_UNION: u32 = 5;
+const BTF_KIND_ENUM: u32 = 6;
+const BTF_KIND_TYPEDEF: u32 = 8;
+const BTF_KIND_VOLATILE: u32 = 9;
+const BTF_KIND_CONST: u32 = 10;
+const BTF_KIND_RESTRICT: u32 = 11;
+const BTF_KIND_FUNC_PROTO: u32 = 13;
+const BTF_KIND_VAR: u32 = 14;
+const BTF_KIND_DATASEC: u32 = 15;
+const BTF_KIND_DECL_TAG: u32 = 17;
+const BTF_KIND_TYPE_TAG: u32 = 18;
+const BTF_KIND_ENUM64: u32 = 19;
+
+struct Member {
+    name: String,
+    type_id: u32,
+    /// In bits
+    offset: u32,
+}
+
+struct Type {
+    kind: u32,
+    /// Type referred to by a typedef or a modifier
+    type_id: u32,
+    members: Vec<Member>,
+}
+
+/// Struct layouts of the running kernel, read from its BTF.
+/// aya keeps the members of its BTF types private, only the layouts needed to read kernel structures from the eBPF programs are parsed here.
+pub struct KernelBtf {
+    types: Vec<Type>,
+    structs: HashMap<String, u32>,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> anyhow::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("Truncated BTF"))
+}
+
+fn read_string(strings: &[u8], offset: u32) -> String {
+    strings
+        .get(offset as usize..)
+        .and_then(|bytes| bytes.split(|byte| *byte == 0).next())
+        .map(|name| String::from_utf8_lossy(name).to_string())
+        .unwrap_or_default()
+}
+
+impl KernelBtf {
+    pub fn from_sys_fs() -> anyhow::Result<KernelBtf> {
+        KernelBtf::parse(&fs::read(VMLINUX_BTF)?)
+    }
+
+    fn parse(data: &[u8]) -> anyhow::Result<KernelBtf> {
+        if data.len() < 24 || u16::from_ne_bytes([data[0], data[1]]) != BTF_MAGIC {
+            return Err(anyhow!("Not a native endian BTF"));
+        }
+
+        let header_len = read_u32(data, 4)? as usize;
+        let type_offset = header_len + read_u32(data, 8)? as usize;
+        let type_len = read_u32(data, 12)? as usize;
+        let string_offset = header_len + read_u32(data, 16)? as usize;
+        let string_len = read_u32(data, 20)? as usize;
+
+        let strings = data.get(string_offset..string_offset + string_len).ok_or_else(|| anyhow!("Truncated BTF"))?;
+        let type_data = data.get(type_offset..type_offset + type_len).ok_or_else(|| anyhow!("Truncated BTF"))?;
+
+        // The type 0 is void
+        let mut types = vec![Type { kind: 0, type_id: 0, members: Vec::new() }];
+        let mut structs = HashMap::new();
+        let mut offset = 0;
+
+        while offset < type_data.len() {
+            let name = read_string(strings, read_u32(type_data, offset)?);
+            let info = read_u32(type_data, offset + 4)?;
+            let size_or_type = read_u32(type_data, offset + 8)?;
+            offset += 12;
+
+            let kind = (info >> 24) & 0x1F;
+            let vlen = (info & 0xFFFF) as usize;
+            let bitfields = info >> 31 == 1;
+            let mut members = Vec::new();
+
+            match kind {
+                BTF_KIND_STRUCT | BTF_KIND_UNION => {
+                    for _ in 0..vlen {
+                        let member_offset = read_u32(type_data, offset + 8)?;
+
+                        members.push(Member {
+                            name: read_string(strings, read_u32(type_data, offset)?),
+                            type_id: read_u32(type_data, offset + 4)?,
+                            // The upper bits hold the size of a bitfield
+                            offset: if bitfields { member_offset & 0xFF_FFFF } else { member_offset },
+                        });
+                        offset += 12;
+                    }
+                }
+                BTF_KIND_INT | BTF_KIND_VAR | BTF_KIND_DECL_TAG => offset += 4,
+                BTF_KIND_ARRAY => offset += 12,
+                BTF_KIND_ENUM | BTF_KIND_FUNC_PROTO => offset += 8 * vlen,
+                BTF_KIND_DATASEC | BTF_KIND_ENUM64 => offset += 12 * vlen,
+                _ => {}
+            }
+
+            if kind == BTF_KIND_STRUCT && !name.is_empty() {
+                structs.entry(name).or_insert(types.len() as u32);
+            }
+
+            types.push(Type { kind, type_id: size_or_type, members });
+        }
+
+        Ok(KernelBtf { types, structs })
+    }
+
+    /// Struct or union behind typedefs and modifiers
+    fn resolve(&self, mut type_id: u32) -> Option<&Type> {
+        loop {
+            let btf_type = self.types.get(type_id as usize)?;
+
+            match btf_type.kind {
+                BTF_KIND_TYPEDEF | BTF_KIND_VOLATILE | BTF_KIND_CONST | BTF_KIND_RESTRICT | BTF_KIND_TYPE_TAG => type_id = btf_type.type_id,
+                _ => return Some(btf_type)
+            }
+        }
+    }
+
+    /// Offset in bits of a member, looked up in the anonymous structs and unions of the type too
+    fn member_offset_in(&self, btf_type: &Type, member: &str) -> Option<u32> {
+        for candidate in &btf_type.members {
+            if candidate.name == member {
+                return Some(candidate.offset);
+            }
+
+            if !candidate.name.is_empty() {
+                continue;
+            }
+
+            if let Some(offset) = self.resolve(candidate.type_id).and_then(|nested| self.member_offset_in(nested, member)) {
+                return Some(candidate.offset + offset);
+            }
+        }
+
+        None
+    }
+
+    /// Offset in bytes of a member of a kernel struct, e.g. `member_offset("sk_buff", "dev")`
+    pub fn member_offset(&self, struct_name: &str, member: &str) -> Option<u32> {
+        let btf_type = self.types.get(*self.structs.get(struct_name)? as usize)?;
+
+        self.member_offset_in(btf_type, member).map(|offset| offset / 8)
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use aya::Ebpf;
+use aya::maps::{Array, MapData, StackTraceMap};
+use getifaddrs::Interface;
+use log::{info, warn};
+use regex::Regex;
+use serde::Serialize;
+use aya_network_deep_profiling_common::{PacketDrop, SkbDropLayout};
+use crate::btf::KernelBtf;
+use crate::protocol::EtherType;
+use crate::symbols::{kernel_symbol_name, logical_name};
+use crate::ARGS;
+
+const KFREE_SKB_FORMAT: [&str; 2] = [
+    "/sys/kernel/tracing/events/skb/kfree_skb/format",
+    "/sys/kernel/debug/tracing/events/skb/kfree_skb/format",
+];
+
+/// Names of `enum skb_drop_reason`, by value, as printed by the tracepoint
+pub type DropReasons = HashMap<u32, String>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketDropRow {
+    pub timestamp: u64,
+    pub reason: String,
+    /// Function that dropped the packet, from the location given by the tracepoint
+    pub function: String,
+    pub location: u64,
+    pub interface: String,
+    pub protocol: EtherType,
+    pub cpuid: u32,
+    /// Key of the symbolized stack in `stacks`, -1 when it could not be recorded
+    pub stack_id: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DropCountRow {
+    pub name: String,
+    pub count: u64,
+}
+
+/// Drops of a reason in a `--drop-interval` window
+#[derive(Debug, Clone, Serialize)]
+pub struct DropIntervalRow {
+    pub start_time: u64,
+    pub reason: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketDrops {
+    pub drops: Vec<PacketDropRow>,
+    pub by_reason: Vec<DropCountRow>,
+    pub by_function: Vec<DropCountRow>,
+    pub time_series: Vec<DropIntervalRow>,
+    pub stacks: BTreeMap<i64, Vec<String>>,
+}
+
+/// Offset of a field in the format of a tracepoint, e.g. `field:void * location; offset:16; size:8; signed:0;`
+pub fn field_offset(format: &str, field: &str) -> Option<u32> {
+    let regex = Regex::new(&format!(r"field:[^;]*\b{field};\s*offset:(\d+);")).unwrap();
+
+    regex.captures(format).and_then(|captures| captures[1].parse().ok())
+}
+
+/// Names of the drop reasons, from the `__print_symbolic(REC->reason, { 2, "NOT_SPECIFIED" }, ...)` of the print format
+fn drop_reasons(format: &str) -> DropReasons {
+    let Some((_, symbols)) = format.split_once("__print_symbolic(REC->reason") else {
+        return DropReasons::new();
+    };
+
+    Regex::new(r#"\{\s*(\d+),\s*"(\w+)"\s*\}"#)
+        .unwrap()
+        .captures_iter(symbols)
+        .filter_map(|captures| Some((captures[1].parse().ok()?, captures[2].to_string())))
+        .collect()
+}
+
+/// Pushes the layout of the `skb:kfree_skb` tracepoint and of the structures read by the drop recording, and enables it.
+/// Returns the names of the drop reasons of the running kernel.
+pub fn apply_drop_layout(ebpf: &mut Ebpf) -> anyhow::Result<DropReasons> {
+    let format = KFREE_SKB_FORMAT
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .ok_or_else(|| anyhow::anyhow!("Could not read the format of skb:kfree_skb, is tracefs mounted?"))?;
+
+    let mut layout = SkbDropLayout {
+        location: field_offset(&format, "location").unwrap_or_default(),
+        protocol: field_offset(&format, "protocol").unwrap_or_default(),
+        reason: field_offset(&format, "reason").unwrap_or_default(),
+        ..SkbDropLayout::default()
+    };
+
+    match KernelBtf::from_sys_fs() {
+        Ok(btf) => {
+            layout.skb_dev = btf.member_offset("sk_buff", "dev").unwrap_or_default();
+            layout.dev_ifindex = btf.member_offset("net_device", "ifindex").unwrap_or_default();
+        }
+        Err(e) => warn!("Could not read the kernel BTF, the interfaces of the drops are not recorded: {e}")
+    }
+
+    if layout.reason == 0 {
+        warn!("skb:kfree_skb has no drop reason on this kernel, the drops are only attributed to their function");
+    }
+
+    info!("Recording the packet drops with {layout:?}");
+
+    let mut skb_drop_layout: Array<_, SkbDropLayout> = Array::try_from(ebpf.map_mut("SKB_DROP_LAYOUT").unwrap())?;
+    skb_drop_layout.set(0, layout, 0)?;
+
+    let mut drop_tracing: Array<_, u32> = Array::try_from(ebpf.map_mut("DROP_TRACING").unwrap())?;
+    drop_tracing.set(0, 1, 0)?;
+
+    Ok(drop_reasons(&format))
+}
+
+fn count_rows(counts: HashMap<String, u64>) -> Vec<DropCountRow> {
+    let mut rows = counts.into_iter().map(|(name, count)| DropCountRow { name, count }).collect::<Vec<DropCountRow>>();
+    rows.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    rows
+}
+
+/// Aggregates the drops by reason and by function, and over `--drop-interval` windows when given.
+/// The drops must be ordered by time.
+pub fn process_drops(
+    drops: Vec<(u64, PacketDrop)>,
+    reasons: &DropReasons,
+    stack_traces: &StackTraceMap<MapData>,
+    interfaces: &[Interface],
+    initial_time: u64
+) -> PacketDrops {
+    let mut stacks: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+
+    let drops = drops
+        .into_iter()
+        .map(|(time, drop)| {
+            if drop.stack_id >= 0 && !stacks.contains_key(&drop.stack_id) {
+                let frames = match stack_traces.get(&(drop.stack_id as u32), 0) {
+                    Ok(stack_trace) => stack_trace.frames().iter().map(|frame| kernel_symbol_name(frame.ip)).collect(),
+                    Err(_) => Vec::new()
+                };
+
+                stacks.insert(drop.stack_id, frames);
+            }
+
+            PacketDropRow {
+                timestamp: time.saturating_sub(initial_time),
+                reason: reasons.get(&drop.reason).cloned().unwrap_or_else(|| drop.reason.to_string()),
+                function: logical_name(&kernel_symbol_name(drop.location)).to_string(),
+                location: drop.location,
+                interface: match drop.if_index {
+                    0 => String::from("None"),
+                    if_index => interfaces
+                        .iter()
+                        .find(|interface| interface.index == Some(if_index))
+                        .map(|interface| interface.name.clone())
+                        .unwrap_or_else(|| if_index.to_string())
+                },
+                protocol: EtherType::from(drop.protocol),
+                cpuid: drop.cpuid,
+                stack_id: drop.stack_id,
+            }
+        })
+        .collect::<Vec<PacketDropRow>>();
+
+    let mut by_reason: HashMap<String, u64> = HashMap::new();
+    let mut by_function: HashMap<String, u64> = HashMap::new();
+    let mut intervals: BTreeMap<(u64, String), u64> = BTreeMap::new();
+
+    for drop in &drops {
+        *by_reason.entry(drop.reason.clone()).or_default() += 1;
+        *by_function.entry(drop.function.clone()).or_default() += 1;
+
+        if let Some(interval) = ARGS.drop_interval.filter(|interval| *interval > 0) {
+            let interval = interval * 1_000_000;
+            *intervals.entry((drop.timestamp / interval * interval, drop.reason.clone())).or_default() += 1;
+        }
+    }
+
+    let by_reason = count_rows(by_reason);
+    let by_function = count_rows(by_function);
+    let time_series = intervals
+        .into_iter()
+        .map(|((start_time, reason), count)| DropIntervalRow { start_time, reason, count })
+        .collect::<Vec<DropIntervalRow>>();
+
+    println!("================================================= Packet Drops =================================================");
+    println!("{: <40} {: >10}   {: <40} {: >10}", "Reason", "Drops", "Function", "Drops");
+    println!("----------------------------------------------------------------------------------------------------------------");
+
+    for index in 0..by_reason.len().max(by_function.len()) {
+        let column = |rows: &[DropCountRow]| match rows.get(index) {
+            Some(row) => format!("{: <40} {: >10}", row.name, row.count),
+            None => format!("{: <51}", "")
+        };
+
+        println!("{}   {}", column(&by_reason), column(&by_function));
+    }
+
+    println!("{} packets dropped", drops.len());
+
+    PacketDrops {
+        drops,
+        by_reason,
+        by_function,
+        time_series,
+        stacks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: &str = r#"name: kfree_skb
+ID: 1455
+format:
+	field:unsigned short common_type;	offset:0;	size:2;	signed:0;
+	field:unsigned char common_flags;	offset:2;	size:1;	signed:0;
+
+	field:void * skbaddr;	offset:8;	size:8;	signed:0;
+	field:void * location;	offset:16;	size:8;	signed:0;
+	field:unsigned short protocol;	offset:24;	size:2;	signed:0;
+	field:enum skb_drop_reason reason;	offset:28;	size:4;	signed:0;
+
+print fmt: "skbaddr=%p protocol=%u location=%pS reason: %s", REC->skbaddr, REC->protocol, REC->location, __print_symbolic(REC->reason, { 2, "NOT_SPECIFIED" }, { 3, "NO_SOCKET" }, { 5, "TCP_CSUM" })
+"#;
+
+    #[test]
+    fn field_offsets() {
+        assert_eq!(field_offset(FORMAT, "location"), Some(16));
+        assert_eq!(field_offset(FORMAT, "protocol"), Some(24));
+        assert_eq!(field_offset(FORMAT, "reason"), Some(28));
+        assert_eq!(field_offset(FORMAT, "rx_sk"), None);
+    }
+
+    #[test]
+    fn drop_reason_names() {
+        let reasons = drop_reasons(FORMAT);
+
+        assert_eq!(reasons.len(), 3);
+        assert_eq!(reasons[&2], "NOT_SPECIFIED");
+        assert_eq!(reasons[&5], "TCP_CSUM");
+        assert!(drop_reasons("print fmt: \"skbaddr=%p\", REC->skbaddr").is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use aya::Ebpf;
+use aya::maps::Array;
+use log::info;
+use serde::Serialize;
+use aya_network_deep_profiling_common::{FlowFilter, FlowId, PacketDirection, ThroughputStat, XdpPacket};
+use crate::protocol::IpProtocol;
+use crate::utils::{mean, median};
+use crate::ARGS;
+
+/// Packets and bytes of a flow, with the time its packets took from the XDP program to the TC ingress program
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FlowRow {
+    pub flow: String,
+    pub ingress_packets: u64,
+    pub ingress_bytes: u64,
+    pub egress_packets: u64,
+    pub egress_bytes: u64,
+    pub xdp_packets: u64,
+    /// Mean and median XDP to TC ingress latency in nanoseconds, 0 when no packet was seen by both
+    pub mean_latency: u64,
+    pub median_latency: u64,
+}
+
+/// Pushes the `--flow-protocol`, `--flow-port` and `--flow-host` filters to the XDP and TC programs, which pass the other packets without recording them
+pub fn apply_flow_filter(ebpf: &mut Ebpf) -> anyhow::Result<()> {
+    let mut filter = FlowFilter {
+        port: ARGS.flow_port.unwrap_or_default(),
+        protocol: ARGS.flow_protocol.map_or(0, |protocol| protocol.number()),
+        ..FlowFilter::default()
+    };
+
+    match ARGS.flow_host {
+        Some(IpAddr::V4(addr)) => {
+            filter.addr[..4].copy_from_slice(&addr.octets());
+            filter.ip_version = 4;
+        }
+        Some(IpAddr::V6(addr)) => {
+            filter.addr = addr.octets();
+            filter.ip_version = 6;
+        }
+        None => {}
+    }
+
+    let mut flow_filter: Array<_, FlowFilter> = Array::try_from(ebpf.map_mut("FLOW_FILTER").unwrap())?;
+    flow_filter.set(0, filter, 0)?;
+
+    if ARGS.flow_protocol.is_some() || ARGS.flow_port.is_some() || ARGS.flow_host.is_some() {
+        info!("Recording only the packets of protocol {:?}, port {:?}, host {:?}", ARGS.flow_protocol, ARGS.flow_port, ARGS.flow_host);
+    }
+
+    Ok(())
+}
+
+/// e.g. `TCP 10.0.1.2:443 > 10.0.1.1:51234 vlan 10`, ICMP giving the type and code instead of the ports
+pub fn flow_name(flow: &FlowId) -> String {
+    let (src, dst) = match flow.ip_version {
+        4 => (
+            IpAddr::V4(Ipv4Addr::new(flow.src_addr[0], flow.src_addr[1], flow.src_addr[2], flow.src_addr[3])),
+            IpAddr::V4(Ipv4Addr::new(flow.dst_addr[0], flow.dst_addr[1], flow.dst_addr[2], flow.dst_addr[3])),
+        ),
+        6 => (IpAddr::V6(Ipv6Addr::from(flow.src_addr)), IpAddr::V6(Ipv6Addr::from(flow.dst_addr))),
+        _ => {
+            return match flow.vlan_id {
+                0 => String::from("Non-IP"),
+                vlan_id => format!("Non-IP vlan {vlan_id}")
+            };
+        }
+    };
+
+    let protocol = IpProtocol::from(flow.protocol);
+    let name = match protocol {
+        IpProtocol::Tcp | IpProtocol::Udp => format!("{protocol} {} > {}", socket_name(src, flow.src_port), socket_name(dst, flow.dst_port)),
+        IpProtocol::Icmp | IpProtocol::Icmpv6 => format!("{protocol} {src} > {dst} type {} code {}", flow.src_port, flow.dst_port),
+        IpProtocol::Unknown(_) => format!("{protocol} {src} > {dst}")
+    };
+
+    match flow.vlan_id {
+        0 => name,
+        vlan_id => format!("{name} vlan {vlan_id}")
+    }
+}
+
+fn socket_name(addr: IpAddr, port: u16) -> String {
+    match addr {
+        IpAddr::V4(addr) => format!("{addr}:{port}"),
+        IpAddr::V6(addr) => format!("[{addr}]:{port}")
+    }
+}
+
+/// Breaks the throughput down per flow. The latency between the XDP program and the TC ingress program is measured per packet,
+/// from the time the XDP program stamped in the metadata of the packet.
+pub fn process_flows(throughput_stats: &[ThroughputStat], xdp_packets: &[(u64, XdpPacket)]) -> Vec<FlowRow> {
+    let mut rows: BTreeMap<String, FlowRow> = BTreeMap::new();
+    let mut latencies: HashMap<String, Vec<u64>> = HashMap::new();
+
+    for (_, packet) in xdp_packets {
+        let name = flow_name(&packet.flow);
+        rows.entry(name.clone()).or_insert_with(|| FlowRow { flow: name, ..FlowRow::default() }).xdp_packets += 1;
+    }
+
+    let mut throughput_stats = throughput_stats.to_vec();
+    throughput_stats.sort_by_key(|stat| stat.timestamp);
+
+    for stat in &throughput_stats {
+        let flow = stat.flow;
+        let name = flow_name(&flow);
+        let row = rows.entry(name.clone()).or_insert_with(|| FlowRow { flow: name.clone(), ..FlowRow::default() });
+
+        match stat.direction {
+            PacketDirection::Ingress => {
+                row.ingress_packets += 1;
+                row.ingress_bytes += stat.packet_size as u64;
+
+                // The packets without metadata were not stamped, the driver not supporting it in driver mode
+                if stat.xdp_time != 0 {
+                    latencies.entry(name).or_default().push(stat.timestamp.saturating_sub(stat.xdp_time));
+                }
+            }
+            PacketDirection::Egress => {
+                row.egress_packets += 1;
+                row.egress_bytes += stat.packet_size as u64;
+            }
+        }
+    }
+
+    for (name, mut times) in latencies {
+        times.sort_unstable();
+
+        if let Some(row) = rows.get_mut(&name) {
+            row.mean_latency = mean(&times);
+            row.median_latency = median(&times);
+        }
+    }
+
+    let rows = rows.into_values().collect::<Vec<FlowRow>>();
+
+    println!("===================================================== Flows =====================================================");
+    println!("{: <60} {: >10} {: >12} {: >10} {: >12} {: >10} {: >15} {: >15}", "Flow", "In pkts", "In bytes", "Out pkts", "Out bytes", "XDP pkts", "Mean XDP>TC", "Median XDP>TC");
+
+    for row in &rows {
+        println!(
+            "{: <60} {: >10} {: >12} {: >10} {: >12} {: >10} {: >12} ns {: >12} ns",
+            row.flow, row.ingress_packets, row.ingress_bytes, row.egress_packets, row.egress_bytes, row.xdp_packets, row.mean_latency, row.median_latency
+        );
+    }
+
+    rows
+}
@@ -0,0 +1,160 @@
+use std::collections::{BTreeMap, HashMap};
+use aya::maps::{HashMap as EHashMap, MapData, PerCpuArray};
+use serde::Serialize;
+use aya_network_deep_profiling_common::{Alloc, KernelFunction, HISTOGRAM_BUCKETS, ALLOC_VARIANTS, KERNEL_FUNCTION_VARIANTS, Histogram};
+use crate::symbols::{logical_name, symbol_table};
+use crate::time::program_name;
+use crate::uprobe::UserProbeTarget;
+use crate::ARGS;
+
+/// Latency histogram of a function over all the CPUs, the latencies being in nanoseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct HistogramRow {
+    pub function_name: String,
+    pub count: u64,
+    /// Upper bounds of the buckets holding the percentiles, at most `max`
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+    /// Calls of each log2 bucket, the bucket `n` holding the latencies in `[2^n, 2^(n+1))`
+    pub buckets: Vec<u64>,
+}
+
+/// Slots of `HISTOGRAMS`: the kernel functions, the allocs, a slot per `--uprobe` target, then the dynamic functions
+pub fn histogram_slots(dynamic_functions: &[String]) -> u32 {
+    (KernelFunction::VARIANT_COUNT + Alloc::VARIANT_COUNT + ARGS.uprobes.len() + dynamic_functions.len()) as u32
+}
+
+/// Slot of each address of the dynamic functions, several symbols sharing a name sharing its slot
+pub fn dynamic_function_slots(dynamic_functions: &[String]) -> Vec<(u64, u32)> {
+    let offset = KernelFunction::VARIANT_COUNT + Alloc::VARIANT_COUNT + ARGS.uprobes.len();
+    let indexes = dynamic_functions
+        .iter()
+        .enumerate()
+        .map(|(index, function)| (function.as_str(), index))
+        .collect::<HashMap<&str, usize>>();
+
+    symbol_table()
+        .iter()
+        .filter_map(|(address, name)| {
+            let index = indexes.get(name.as_str())?;
+            Some((*address, (offset + index) as u32))
+        })
+        .collect()
+}
+
+pub fn set_dynamic_function_slots(histogram_slots: &mut EHashMap<&mut MapData, u64, u32>, dynamic_functions: &[String]) -> anyhow::Result<()> {
+    for (address, slot) in dynamic_function_slots(dynamic_functions) {
+        histogram_slots.insert(address, slot, 0)?;
+    }
+
+    Ok(())
+}
+
+/// Merges the histograms of the CPUs, and of the functions reported under the same name, then prints their percentiles
+pub fn process_histograms(histograms: &PerCpuArray<MapData, Histogram>, user_probe_targets: &[UserProbeTarget], dynamic_functions: &[String]) -> anyhow::Result<Vec<HistogramRow>> {
+    let mut names = KERNEL_FUNCTION_VARIANTS.into_iter().map(program_name).collect::<Vec<String>>();
+    names.extend(ALLOC_VARIANTS.into_iter().map(program_name));
+    names.extend((0..ARGS.uprobes.len()).map(|index| user_probe_targets.get(index).map_or(String::new(), |target| target.symbol.clone())));
+    names.extend(dynamic_functions.iter().map(|function| logical_name(function).to_string()));
+
+    let mut merged: BTreeMap<String, Histogram> = BTreeMap::new();
+
+    for (slot, name) in names.into_iter().enumerate() {
+        for histogram in histograms.get(&(slot as u32), 0)?.iter() {
+            if histogram.count == 0 {
+                continue;
+            }
+
+            let entry = merged.entry(name.clone()).or_insert(Histogram {
+                buckets: [0; HISTOGRAM_BUCKETS],
+                count: 0,
+                max: 0,
+            });
+
+            for (bucket, count) in entry.buckets.iter_mut().zip(histogram.buckets) {
+                *bucket += count;
+            }
+
+            entry.count += histogram.count;
+            entry.max = entry.max.max(histogram.max);
+        }
+    }
+
+    let rows = merged
+        .into_iter()
+        .map(|(function_name, histogram)| HistogramRow {
+            function_name,
+            count: histogram.count,
+            p50: percentile(&histogram, 0.50),
+            p90: percentile(&histogram, 0.90),
+            p99: percentile(&histogram, 0.99),
+            max: histogram.max,
+            buckets: histogram.buckets.to_vec(),
+        })
+        .collect::<Vec<HistogramRow>>();
+
+    println!("============================================== Latency Histograms ==============================================");
+    println!("{: <35} {: >12} {: >15} {: >15} {: >15} {: >15}", "Name", "Count", "p50", "p90", "p99", "Max");
+    println!("----------------------------------------------------------------------------------------------------------------");
+
+    for row in &rows {
+        println!(
+            "{: <35} {: >12} {: >12} ns {: >12} ns {: >12} ns {: >12} ns",
+            row.function_name, row.count, row.p50, row.p90, row.p99, row.max
+        );
+    }
+
+    Ok(rows)
+}
+
+/// Upper bound of the bucket holding the call of rank `quantile * count`
+fn percentile(histogram: &Histogram, quantile: f64) -> u64 {
+    let rank = ((histogram.count as f64 * quantile).ceil() as u64).max(1);
+    let mut calls = 0;
+
+    for (bucket, count) in histogram.buckets.iter().enumerate() {
+        calls += count;
+
+        if calls >= rank {
+            let upper_bound = 1u64.checked_shl(bucket as u32 + 1).map_or(u64::MAX, |bound| bound - 1);
+
+            return upper_bound.min(histogram.max);
+        }
+    }
+
+    histogram.max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(calls: &[(usize, u64)], max: u64) -> Histogram {
+        let mut histogram = Histogram { buckets: [0; HISTOGRAM_BUCKETS], count: 0, max };
+
+        for (bucket, count) in calls {
+            histogram.buckets[*bucket] = *count;
+            histogram.count += count;
+        }
+
+        histogram
+    }
+
+    #[test]
+    fn percentile_upper_bounds() {
+        // 90 calls in [2^4, 2^5), 10 in [2^10, 2^11)
+        let histogram = histogram(&[(4, 90), (10, 10)], 1500);
+
+        assert_eq!(percentile(&histogram, 0.50), 31);
+        assert_eq!(percentile(&histogram, 0.90), 31);
+        assert_eq!(percentile(&histogram, 0.99), 1500);
+    }
+
+    #[test]
+    fn percentile_empty_and_last_bucket() {
+        assert_eq!(percentile(&histogram(&[], 0), 0.50), 0);
+        assert_eq!(percentile(&histogram(&[(HISTOGRAM_BUCKETS - 1, 1)], u64::MAX), 0.50), u64::MAX);
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use aya::Ebpf;
+use aya::maps::Array;
+use log::{info, warn};
+use serde::Serialize;
+use aya_network_deep_profiling_common::{ExecutionContext, IrqSpan, NapiPoll, NapiPollLayout, SOFTIRQ_VECTORS};
+use crate::drop::field_offset;
+use crate::utils::{mean, median};
+
+const NAPI_POLL_FORMAT: [&str; 2] = [
+    "/sys/kernel/tracing/events/napi/napi_poll/format",
+    "/sys/kernel/debug/tracing/events/napi/napi_poll/format",
+];
+
+const PROC_INTERRUPTS: &str = "/proc/interrupts";
+
+/// Run of a softirq or of an interrupt handler
+#[derive(Debug, Clone, Serialize)]
+pub struct IrqSpanRow {
+    /// Softirq vector, or action of the interrupt line from `/proc/interrupts`
+    pub name: String,
+    pub number: u32,
+    pub context: ExecutionContext,
+    pub cpuid: u32,
+    /// Interrupted thread, or ksoftirqd
+    pub tid: u32,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub duration: u64,
+    /// From the first raise of the softirq to its run, `None` for the interrupt handlers and the raises not seen
+    pub raise_latency: Option<u64>,
+}
+
+/// Time spent by a CPU in a softirq vector or an interrupt line, in nanoseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct IrqTimeRow {
+    pub cpuid: u32,
+    pub name: String,
+    pub context: ExecutionContext,
+    pub count: u64,
+    pub total_time: u64,
+    pub mean: u64,
+    pub max: u64,
+}
+
+/// Latency from the raise of a softirq vector to its run, in nanoseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct RaiseLatencyRow {
+    pub vector: String,
+    pub context: ExecutionContext,
+    pub count: u64,
+    pub mean: u64,
+    pub median: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+/// Share of the time of a softirq vector spent in ksoftirqd instead of inline
+#[derive(Debug, Clone, Serialize)]
+pub struct DeferredWorkRow {
+    pub vector: String,
+    pub inline_time: u64,
+    pub ksoftirqd_time: u64,
+    pub deferred_share: f64,
+}
+
+/// NAPI polls of a device in a context, the packets processed and the polls that exhausted their budget
+#[derive(Debug, Clone, Serialize)]
+pub struct NapiPollRow {
+    pub device: String,
+    pub context: ExecutionContext,
+    pub polls: u64,
+    pub packets: u64,
+    pub budget_exhausted: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IrqTimes {
+    pub spans: Vec<IrqSpanRow>,
+    pub cpus: Vec<IrqTimeRow>,
+    pub raise_latencies: Vec<RaiseLatencyRow>,
+    pub deferred_work: Vec<DeferredWorkRow>,
+    pub napi_polls: Vec<NapiPollRow>,
+}
+
+/// Pushes the layout of the `napi:napi_poll` tracepoint and enables the recording of the softirqs, interrupt handlers and NAPI polls
+pub fn apply_irq_layout(ebpf: &mut Ebpf) -> anyhow::Result<()> {
+    let layout = match NAPI_POLL_FORMAT.iter().find_map(|path| fs::read_to_string(path).ok()) {
+        Some(format) => NapiPollLayout {
+            napi: field_offset(&format, "napi").unwrap_or_default(),
+            dev_name: field_offset(&format, "dev_name").unwrap_or_default(),
+            work: field_offset(&format, "work").unwrap_or_default(),
+            budget: field_offset(&format, "budget").unwrap_or_default(),
+        },
+        None => {
+            warn!("Could not read the format of napi:napi_poll, the NAPI polls are not recorded");
+            NapiPollLayout::default()
+        }
+    };
+
+    info!("Recording the NAPI polls with {layout:?}");
+
+    let mut napi_poll_layout: Array<_, NapiPollLayout> = Array::try_from(ebpf.map_mut("NAPI_POLL_LAYOUT").unwrap())?;
+    napi_poll_layout.set(0, layout, 0)?;
+
+    let mut irq_timing: Array<_, u32> = Array::try_from(ebpf.map_mut("IRQ_TIMING").unwrap())?;
+    irq_timing.set(0, 1, 0)?;
+
+    Ok(())
+}
+
+/// Actions of the interrupt lines, the last column of `/proc/interrupts`
+fn interrupt_names() -> HashMap<u32, String> {
+    let Ok(interrupts) = fs::read_to_string(PROC_INTERRUPTS) else {
+        return HashMap::new();
+    };
+
+    interrupts
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let (number, columns) = line.split_once(':')?;
+            Some((number.trim().parse().ok()?, columns.split_whitespace().last()?.to_string()))
+        })
+        .collect()
+}
+
+/// Sorted latencies as (mean, median, p99, max), 0 when there is none
+fn latency_stats(latencies: &[u64]) -> (u64, u64, u64, u64) {
+    match latencies.last() {
+        Some(max) => (mean(latencies), median(latencies), latencies[(latencies.len() - 1) * 99 / 100], *max),
+        None => (0, 0, 0, 0)
+    }
+}
+
+/// Aggregates the softirqs and interrupt handlers per CPU, their raise latencies and the share of the softirqs deferred to ksoftirqd.
+/// The spans and the polls must be ordered by time.
+pub fn process_irq_times(spans: Vec<(u64, IrqSpan)>, polls: Vec<(u64, NapiPoll)>, initial_time: u64) -> IrqTimes {
+    let interrupts = interrupt_names();
+
+    let spans = spans
+        .into_iter()
+        .map(|(end, span)| IrqSpanRow {
+            name: match span.context {
+                ExecutionContext::Hardirq => interrupts.get(&span.number).cloned().unwrap_or_else(|| format!("irq {}", span.number)),
+                _ => SOFTIRQ_VECTORS.get(span.number as usize).map_or_else(|| span.number.to_string(), |vector| vector.to_string()),
+            },
+            number: span.number,
+            context: span.context,
+            cpuid: span.cpuid,
+            tid: span.tid,
+            start_time: span.start.saturating_sub(initial_time),
+            end_time: end.saturating_sub(initial_time),
+            duration: end.saturating_sub(span.start),
+            raise_latency: (span.raised != 0).then(|| span.start.saturating_sub(span.raised)),
+        })
+        .collect::<Vec<IrqSpanRow>>();
+
+    let mut durations: BTreeMap<(u32, &str, ExecutionContext), Vec<u64>> = BTreeMap::new();
+    let mut latencies: BTreeMap<(&str, ExecutionContext), Vec<u64>> = BTreeMap::new();
+    let mut vector_times: BTreeMap<&str, (u64, u64)> = BTreeMap::new();
+
+    for span in &spans {
+        durations.entry((span.cpuid, &span.name, span.context)).or_default().push(span.duration);
+
+        if let Some(raise_latency) = span.raise_latency {
+            latencies.entry((&span.name, span.context)).or_default().push(raise_latency);
+        }
+
+        match span.context {
+            ExecutionContext::Softirq => vector_times.entry(&span.name).or_default().0 += span.duration,
+            ExecutionContext::Ksoftirqd => vector_times.entry(&span.name).or_default().1 += span.duration,
+            _ => {}
+        }
+    }
+
+    let cpus = durations
+        .into_iter()
+        .map(|((cpuid, name, context), durations)| IrqTimeRow {
+            cpuid,
+            name: name.to_string(),
+            context,
+            count: durations.len() as u64,
+            total_time: durations.iter().sum(),
+            mean: mean(&durations),
+            max: durations.iter().max().copied().unwrap_or_default(),
+        })
+        .collect::<Vec<IrqTimeRow>>();
+
+    let raise_latencies = latencies
+        .into_iter()
+        .map(|((vector, context), mut latencies)| {
+            latencies.sort_unstable();
+            let (mean, median, p99, max) = latency_stats(&latencies);
+
+            RaiseLatencyRow {
+                vector: vector.to_string(),
+                context,
+                count: latencies.len() as u64,
+                mean,
+                median,
+                p99,
+                max,
+            }
+        })
+        .collect::<Vec<RaiseLatencyRow>>();
+
+    let deferred_work = vector_times
+        .into_iter()
+        .map(|(vector, (inline_time, ksoftirqd_time))| DeferredWorkRow {
+            vector: vector.to_string(),
+            inline_time,
+            ksoftirqd_time,
+            deferred_share: ksoftirqd_time as f64 / (inline_time + ksoftirqd_time).max(1) as f64,
+        })
+        .collect::<Vec<DeferredWorkRow>>();
+
+    let mut devices: BTreeMap<(String, ExecutionContext), NapiPollRow> = BTreeMap::new();
+
+    for (_, poll) in &polls {
+        let device = String::from_utf8_lossy(poll.device.split(|byte| *byte == 0).next().unwrap_or_default()).to_string();
+        let row = devices.entry((device.clone(), poll.context)).or_insert_with(|| NapiPollRow {
+            device,
+            context: poll.context,
+            polls: 0,
+            packets: 0,
+            budget_exhausted: 0,
+        });
+
+        row.polls += 1;
+        row.packets += poll.work as u64;
+        row.budget_exhausted += (poll.budget != 0 && poll.work >= poll.budget) as u64;
+    }
+
+    let napi_polls = devices.into_values().collect::<Vec<NapiPollRow>>();
+
+    println!("=========================================== Softirq and Hardirq Times ===========================================");
+    println!("{: <5} {: <24} {: <10} {: >10} {: >15} {: >15} {: >15}", "CPU", "Softirq / IRQ", "Context", "Count", "Total", "Mean", "Max");
+    println!("-----------------------------------------------------------------------------------------------------------------");
+
+    for row in &cpus {
+        println!(
+            "{: <5} {: <24} {: <10} {: >10} {: >12} ns {: >12} ns {: >12} ns",
+            row.cpuid, row.name, row.context.as_str(), row.count, row.total_time, row.mean, row.max
+        );
+    }
+
+    println!();
+    println!("{: <24} {: <10} {: >10} {: >15} {: >15} {: >15} {: >15}", "Raise to entry", "Context", "Count", "Mean", "Median", "p99", "Max");
+
+    for row in &raise_latencies {
+        println!(
+            "{: <24} {: <10} {: >10} {: >12} ns {: >12} ns {: >12} ns {: >12} ns",
+            row.vector, row.context.as_str(), row.count, row.mean, row.median, row.p99, row.max
+        );
+    }
+
+    println!();
+    println!("{: <24} {: >15} {: >15} {: >10}", "Softirq", "Inline", "ksoftirqd", "Deferred");
+
+    for row in &deferred_work {
+        println!("{: <24} {: >12} ns {: >12} ns {: >9.1}%", row.vector, row.inline_time, row.ksoftirqd_time, row.deferred_share * 100.0);
+    }
+
+    if !napi_polls.is_empty() {
+        println!();
+        println!("{: <24} {: <10} {: >10} {: >12} {: >18}", "NAPI device", "Context", "Polls", "Packets", "Budget exhausted");
+
+        for row in &napi_polls {
+            println!("{: <24} {: <10} {: >10} {: >12} {: >18}", row.device, row.context.as_str(), row.polls, row.packets, row.budget_exhausted);
+        }
+    }
+
+    IrqTimes {
+        spans,
+        cpus,
+        raise_latencies,
+        deferred_work,
+        napi_polls,
+    }
+}
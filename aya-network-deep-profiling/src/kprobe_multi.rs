@@ -0,0 +1,212 @@
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use anyhow::anyhow;
+use aya::Ebpf;
+use aya::maps::{Map, MapData};
+use aya_obj::generated::{bpf_attach_type, bpf_attr, bpf_cmd, bpf_insn, bpf_prog_type, BPF_F_KPROBE_MULTI_RETURN};
+use aya_obj::Object;
+use aya_network_deep_profiling_common::{MultiProbeCookie, MultiProbeKind, ALLOC_VARIANTS, KERNEL_FUNCTION_VARIANTS};
+use crate::symbols::{kernel_function_targets, TRACEABLE_FUNCTIONS};
+
+/// Size of the verifier log printed when a program is rejected
+const VERIFIER_LOG_SIZE: usize = 1024 * 1024;
+
+/// Generic entry and return programs, identifying the function hit by its attach cookie
+pub const KPROBE_MULTI_PROGRAMS: [(&str, bool); 2] = [("probe_enter_multi", false), ("probe_ret_multi", true)];
+
+/// A kprobe_multi link, detached when dropped
+pub struct KprobeMultiLink {
+    pub program: &'static str,
+    _fd: OwnedFd,
+}
+
+/// Symbols to attach the kprobe_multi programs to, with the cookie identifying their function.
+/// kprobe_multi is built on fprobe, so symbols not listed in available_filter_functions are left to the per-symbol kprobes,
+/// as well as the functions already attached with trampolines.
+pub fn kprobe_multi_targets(trampoline_functions: &HashSet<&str>, dynamic_functions: &[String]) -> Vec<(String, MultiProbeCookie)> {
+    let mut targets = Vec::new();
+    let mut seen = HashSet::new();
+
+    let mut push_target = |symbol: String, cookie: MultiProbeCookie| {
+        let is_traceable = TRACEABLE_FUNCTIONS.as_ref().is_none_or(|functions| functions.contains(&symbol));
+
+        if is_traceable && seen.insert(symbol.clone()) {
+            targets.push((symbol, cookie));
+        }
+    };
+
+    for function in KERNEL_FUNCTION_VARIANTS {
+        if trampoline_functions.contains(function.as_str()) {
+            continue;
+        }
+
+        for symbol in kernel_function_targets(&function.as_str().replace("_p_", ".")) {
+            push_target(symbol, MultiProbeCookie::new(MultiProbeKind::Kernel, function.as_id()));
+        }
+    }
+
+    for alloc in ALLOC_VARIANTS {
+        if trampoline_functions.contains(alloc.as_str()) {
+            continue;
+        }
+
+        for symbol in kernel_function_targets(&alloc.as_str().replace("_p_", ".")) {
+            push_target(symbol, MultiProbeCookie::new(MultiProbeKind::Alloc, alloc.as_id()));
+        }
+    }
+
+    for symbol in dynamic_functions {
+        push_target(symbol.clone(), MultiProbeCookie::new(MultiProbeKind::Dynamic, 0));
+    }
+
+    targets
+}
+
+/// Attaches the generic entry and return programs to all the `targets` with one kprobe_multi link each.
+///
+/// aya does not support kprobe_multi programs yet, so the programs are loaded from the eBPF object with the raw bpf syscalls,
+/// their map references being relocated against the maps already created by `ebpf`.
+pub fn attach_kprobe_multi(ebpf: &Ebpf, ebpf_object: &[u8], targets: &[(String, MultiProbeCookie)]) -> anyhow::Result<Vec<KprobeMultiLink>> {
+    if targets.is_empty() {
+        return Err(anyhow!("No traceable function to attach to"));
+    }
+
+    let mut object = Object::parse(ebpf_object)?;
+
+    let text_sections = object
+        .functions
+        .keys()
+        .map(|(section_index, _)| *section_index)
+        .collect::<HashSet<usize>>();
+    let object_maps = object.maps.clone();
+
+    let maps = object_maps
+        .iter()
+        .map(|(name, object_map)| {
+            let map = ebpf.map(name).ok_or_else(|| anyhow!("Map {name} was not created"))?;
+            Ok((name.as_str(), map_fd(map), object_map))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    object.relocate_maps(maps.into_iter(), &text_sections)?;
+    object.relocate_calls(&text_sections)?;
+    object.sanitize_functions(aya::features());
+
+    let symbols = targets
+        .iter()
+        .map(|(symbol, _)| CString::new(symbol.as_str()))
+        .collect::<Result<Vec<CString>, _>>()?;
+    let symbol_pointers = symbols.iter().map(|symbol| symbol.as_ptr() as u64).collect::<Vec<u64>>();
+    let cookies = targets.iter().map(|(_, cookie)| cookie.0).collect::<Vec<u64>>();
+
+    let mut links = Vec::new();
+
+    for (program, is_return) in KPROBE_MULTI_PROGRAMS {
+        let program_object = object.programs.get(program).ok_or_else(|| anyhow!("Program {program} not found"))?;
+        let function = object.functions.get(&program_object.function_key()).ok_or_else(|| anyhow!("Function of program {program} not found"))?;
+
+        let program_fd = load_program(program, &function.instructions, &object.license, object.kernel_version)?;
+        let link_fd = create_link(&program_fd, &symbol_pointers, &cookies, is_return)?;
+
+        links.push(KprobeMultiLink {
+            program,
+            _fd: link_fd,
+        });
+    }
+
+    Ok(links)
+}
+
+fn map_fd(map: &Map) -> RawFd {
+    map_data(map).fd().as_fd().as_raw_fd()
+}
+
+pub fn map_data(map: &Map) -> &MapData {
+    match map {
+        Map::Array(map_data)
+        | Map::BloomFilter(map_data)
+        | Map::CpuMap(map_data)
+        | Map::DevMap(map_data)
+        | Map::DevMapHash(map_data)
+        | Map::HashMap(map_data)
+        | Map::LpmTrie(map_data)
+        | Map::LruHashMap(map_data)
+        | Map::PerCpuArray(map_data)
+        | Map::PerCpuHashMap(map_data)
+        | Map::PerCpuLruHashMap(map_data)
+        | Map::PerfEventArray(map_data)
+        | Map::ProgramArray(map_data)
+        | Map::Queue(map_data)
+        | Map::RingBuf(map_data)
+        | Map::SockHash(map_data)
+        | Map::SockMap(map_data)
+        | Map::Stack(map_data)
+        | Map::StackTraceMap(map_data)
+        | Map::Unsupported(map_data)
+        | Map::XskMap(map_data) => map_data,
+    }
+}
+
+pub fn bpf(command: bpf_cmd, attr: &mut bpf_attr) -> io::Result<OwnedFd> {
+    let ret = unsafe { libc::syscall(libc::SYS_bpf, command as u32, attr as *mut bpf_attr, mem::size_of::<bpf_attr>()) };
+
+    match ret {
+        ret if ret < 0 => Err(io::Error::last_os_error()),
+        fd => Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+    }
+}
+
+fn load_program(program: &str, instructions: &[bpf_insn], license: &CString, kernel_version: Option<u32>) -> anyhow::Result<OwnedFd> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let load = unsafe { &mut attr.__bindgen_anon_3 };
+
+    load.prog_type = bpf_prog_type::BPF_PROG_TYPE_KPROBE as u32;
+    load.expected_attach_type = bpf_attach_type::BPF_TRACE_KPROBE_MULTI as u32;
+    load.insns = instructions.as_ptr() as u64;
+    load.insn_cnt = instructions.len() as u32;
+    load.license = license.as_ptr() as u64;
+    load.kern_version = kernel_version.unwrap_or(0);
+
+    // The kernel truncates program names to 15 characters
+    for (name_char, char) in load.prog_name.iter_mut().zip(program.bytes().take(15)) {
+        *name_char = char as libc::c_char;
+    }
+
+    if let Ok(program_fd) = bpf(bpf_cmd::BPF_PROG_LOAD, &mut attr) {
+        return Ok(program_fd);
+    }
+
+    // Load it again to get the verifier log
+    let mut log = vec![0u8; VERIFIER_LOG_SIZE];
+    let load = unsafe { &mut attr.__bindgen_anon_3 };
+    load.log_level = 1;
+    load.log_buf = log.as_mut_ptr() as u64;
+    load.log_size = log.len() as u32;
+
+    match bpf(bpf_cmd::BPF_PROG_LOAD, &mut attr) {
+        Ok(program_fd) => Ok(program_fd),
+        Err(error) => {
+            let log = String::from_utf8_lossy(&log);
+            Err(anyhow!("Could not load program {program}: {error}\n{}", log.trim_end_matches('\0')))
+        }
+    }
+}
+
+fn create_link(program_fd: &OwnedFd, symbol_pointers: &[u64], cookies: &[u64], is_return: bool) -> anyhow::Result<OwnedFd> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let link = unsafe { &mut attr.link_create };
+
+    link.__bindgen_anon_1.prog_fd = program_fd.as_raw_fd() as u32;
+    link.attach_type = bpf_attach_type::BPF_TRACE_KPROBE_MULTI as u32;
+
+    let kprobe_multi = unsafe { &mut link.__bindgen_anon_3.kprobe_multi };
+    kprobe_multi.flags = if is_return { BPF_F_KPROBE_MULTI_RETURN } else { 0 };
+    kprobe_multi.cnt = symbol_pointers.len() as u32;
+    kprobe_multi.syms = symbol_pointers.as_ptr() as u64;
+    kprobe_multi.cookies = cookies.as_ptr() as u64;
+
+    Ok(bpf(bpf_cmd::BPF_LINK_CREATE, &mut attr)?)
+}
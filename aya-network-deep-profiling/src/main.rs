@@ -4,14 +4,35 @@ mod time;
 mod xdp;
 mod throughput;
 mod utils;
-
-use std::collections::HashMap;
+mod symbols;
+mod attach;
+mod kprobe_multi;
+mod uprobe;
+mod usdt;
+mod pressure;
+mod scope;
+mod sizing;
+mod stream;
+mod histogram;
+mod packet;
+mod flow;
+mod protocol;
+mod residence;
+mod timeline;
+mod btf;
+mod drop;
+mod skb;
+mod irq;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::fs::{OpenOptions};
 use std::io::Write;
-use aya::maps;
-use aya::maps::{Queue, StackTraceMap, HashMap as EHashMap, PerCpuHashMap};
-use aya::programs::{KProbe, SchedClassifier, TcAttachType, TracePoint, UProbe, Xdp, XdpFlags};
+use std::sync::Arc;
+use aya::Btf;
+use aya::util::nr_cpus;
+use aya::maps::{Array, PerCpuArray, Queue, RingBuf, StackTraceMap, HashMap as EHashMap, PerCpuHashMap};
+use aya::programs::{FEntry, FExit, KProbe, SchedClassifier, TcAttachType, TracePoint, UProbe, Xdp};
 use aya::programs::tc::SchedClassifierLinkId;
 use aya::programs::xdp::XdpLinkId;
 use clap::Parser;
@@ -22,71 +43,123 @@ use once_cell::sync::Lazy;
 use pretty_env_logger::env_logger;
 use serde::Serialize;
 use tokio::signal;
-use aya_network_deep_profiling_common::{AllocInfo, KernelFunction, FunctionCall, Alloc, KERNEL_FUNCTIONS, ALLOCS, TRACEPOINTS, USER_FUNCTIONS, UserFunction, USER_FUNCTION_VARIANTS, ThroughputStat, EthHeader};
-use crate::args::Args;
+use tokio::sync::Notify;
+use aya_network_deep_profiling_common::{AllocInfo, KernelFunction, FunctionSpan, Alloc, KERNEL_FUNCTIONS, ALLOCS, TRACEPOINTS, IRQ_TRACEPOINTS, SKB_TRACEPOINT_VARIANTS, SkbTracepoint, UserFunction, ThroughputStat, Event, FunctionAddress, XdpPacket, UsdtEvent, UsdtSpec, RecordingMode, Histogram, PacketStage, PacketArrival, PacketDrop, SkbEvent, SocketEvent, IrqSpan, NapiPoll};
+use crate::args::{Args, ProbeBackend, XdpMode};
+use crate::attach::{attach_trampoline, AttachReport, AttachReportRow, ProbeMechanism};
+use crate::kprobe_multi::{attach_kprobe_multi, kprobe_multi_targets, KPROBE_MULTI_PROGRAMS};
 use crate::memory::{handle_memory_usage};
 use crate::throughput::{process_throughput, ThroughputRow};
-use crate::time::{filter_times, handle_execution_times, ExecutionTimeRow};
+use crate::symbols::{kernel_function_targets, kernel_symbol_name, load_kernel_symbols, logical_name, resolve_dynamic_functions};
+use crate::time::{filter_events, handle_execution_times, program_name, ExecutionTimeRow};
+use crate::uprobe::{attach_user_probe, resolve_user_probe, USER_PROBE_PROGRAMS};
+use crate::pressure::{spawn_pressure_monitor, DroppedEventsRow};
+use crate::scope::apply_scope;
+use crate::sizing::{ebpf_loader, map_sizes, print_memory_estimate};
+use crate::stream::{spawn_stream_consumer, StreamSummary};
+use crate::histogram::{process_histograms, set_dynamic_function_slots, HistogramRow};
+use crate::packet::{process_packet_paths, PacketPathRow};
+use crate::flow::{apply_flow_filter, process_flows, FlowRow};
+use crate::timeline::{apply_socket_layout, process_request_timelines, ConnectionTimelineRow, socket_call_targets, SOCKET_RECEIVE_FUNCTIONS, SOCKET_SEND_FUNCTIONS};
+use crate::drop::{apply_drop_layout, process_drops, DropReasons, PacketDrops};
+use crate::skb::{process_skb_lifetimes, skb_probe_targets, SkbLifetimes, SKB_ALLOC_FUNCTIONS, SKB_COPY_FUNCTIONS, SKB_FREE_FUNCTIONS};
+use crate::irq::{apply_irq_layout, process_irq_times, IrqTimes};
+use crate::residence::{process_residence_times, socket_enqueue_targets, ResidenceTimes};
+use crate::usdt::{process_usdt_events, resolve_usdt, usdt_locations, UsdtEventRow};
 use crate::utils::CPU_FREQUENCY;
-use crate::xdp::process_xdp;
+use crate::xdp::{attach_xdp, is_profiled_interface, process_xdp};
 
 static ARGS: Lazy<Args> = Lazy::new(Args::parse);
 
-static FUNCTIONS: Lazy<Vec<&str>> = Lazy::new(|| [KERNEL_FUNCTIONS.to_vec(), USER_FUNCTIONS.to_vec(), ALLOCS.to_vec()].concat());
+// This will include your eBPF object file as raw bytes at compile-time and load it at
+// runtime. This approach is recommended for most real-world use cases. If you would
+// like to specify the eBPF program at runtime rather than at compile-time, you can
+// reach for `Bpf::load_file` instead.
+const EBPF_OBJECT: &[u8] = aya::include_bytes_aligned!(concat!(
+    env!("OUT_DIR"),
+    "/aya-network-deep-profiling"
+));
+
+static FUNCTIONS: Lazy<Vec<&str>> = Lazy::new(|| [KERNEL_FUNCTIONS.to_vec(), ALLOCS.to_vec()].concat());
+
+/// eBPF program names with the targets they are attached to
+type ProgramTargets<T> = Vec<(String, Vec<T>)>;
 
-static TRACEPOINTS_: Lazy<Vec<(String, Vec<(&str, &str)>)>> = Lazy::new(|| {
+static TRACEPOINTS_: Lazy<ProgramTargets<(&str, &str)>> = Lazy::new(|| {
     let mut tracepoints = Vec::new();
 
     for tracepoint in TRACEPOINTS {
         tracepoints.push((format!("tracepoint_{tracepoint}"), vec![("kmem", tracepoint)]));
     }
 
+    for tracepoint in IRQ_TRACEPOINTS {
+        tracepoints.push((format!("tracepoint_{tracepoint}"), vec![("irq", tracepoint)]));
+    }
+
+    // The raises time the latency of the softirqs, the NAPI polls tell the packets processed inline from the ones deferred to ksoftirqd
+    if ARGS.irq_times {
+        tracepoints.push((String::from("tracepoint_softirq_raise"), vec![("irq", "softirq_raise")]));
+        tracepoints.push((String::from("tracepoint_napi_poll"), vec![("napi", "napi_poll")]));
+    }
+
+    // consume_skb and kfree_skb end the packet paths, the sk_buff lifetimes and the residences, kfree_skb also records the drops
+    for tracepoint in SKB_TRACEPOINT_VARIANTS {
+        if ARGS.packet_paths || ARGS.skb_lifetimes || ARGS.residence_times || (ARGS.drops && tracepoint == SkbTracepoint::kfree_skb) {
+            tracepoints.push((format!("tracepoint_{tracepoint}"), vec![("skb", tracepoint.as_str())]));
+        }
+    }
+
     tracepoints
 });
 
-static KERNEL_PROBES: Lazy<Vec<(String, Vec<String>)>> = Lazy::new(|| {
+/// Kprobes with their targets, the dynamic functions being resolved from `--functions`, `--functions-file` and `--kprobe`
+fn kernel_probes(dynamic_functions: &[String]) -> ProgramTargets<String> {
     let mut probes = Vec::new();
 
+    // Tells the softirqs deferred to ksoftirqd from the ones its thread runs inline, alongside the irq tracepoints
+    probes.push((String::from("probe_ksoftirqd_entry"), vec![String::from("run_ksoftirqd")]));
+    probes.push((String::from("probe_ksoftirqd_return"), vec![String::from("run_ksoftirqd")]));
+
     for function in KERNEL_FUNCTIONS {
-        let kernel_function = function.replace("_p_", ".");
+        let kernel_functions = kernel_function_targets(&function.replace("_p_", "."));
 
-        probes.push((format!("probe_enter_{function}"), vec![kernel_function.clone()]));
-        probes.push((format!("probe_ret_{function}"), vec![kernel_function]));
+        probes.push((format!("probe_enter_{function}"), kernel_functions.clone()));
+        probes.push((format!("probe_ret_{function}"), kernel_functions));
     }
 
     for alloc_variant in ALLOCS {
-        let kernel_alloc = alloc_variant.replace("_p_", ".");
+        let kernel_allocs = kernel_function_targets(&alloc_variant.replace("_p_", "."));
 
-        probes.push((format!("probe_enter_{alloc_variant}"), vec![kernel_alloc.clone()]));
-        probes.push((format!("probe_ret_{alloc_variant}"), vec![kernel_alloc]));
+        probes.push((format!("probe_enter_{alloc_variant}"), kernel_allocs.clone()));
+        probes.push((format!("probe_ret_{alloc_variant}"), kernel_allocs));
     }
 
-    probes
-});
+    if !dynamic_functions.is_empty() {
+        probes.push((String::from("probe_enter_dynamic"), dynamic_functions.to_vec()));
+        probes.push((String::from("probe_ret_dynamic"), dynamic_functions.to_vec()));
+    }
 
-static USER_PROBES: Lazy<Vec<(String, Vec<(String, String)>)>> = Lazy::new(|| {
-    let mut probes = Vec::new();
+    // The enqueues time the residence of the packets, and give the deliveries of the packets of the connections
+    if ARGS.residence_times || ARGS.connections {
+        probes.push((String::from("probe_socket_enqueue"), socket_enqueue_targets()));
+    }
 
-    for function in USER_FUNCTION_VARIANTS {
-        let user_function = function.as_str().replace("_p_", ".");
-        let lib_path = function.get_lib_path().to_string();
+    if ARGS.connections {
+        probes.push((String::from("probe_socket_receive"), socket_call_targets(&SOCKET_RECEIVE_FUNCTIONS)));
+        probes.push((String::from("probe_socket_send"), socket_call_targets(&SOCKET_SEND_FUNCTIONS)));
+    }
 
-        probes.push((
-            format!("probe_enter_{function}"),
-            vec![
-                (user_function.clone(), lib_path.clone()),
-            ])
-        );
-        probes.push((
-            format!("probe_ret_{function}"),
-            vec![
-                (user_function.clone(), lib_path),
-            ])
-        );
+    if ARGS.skb_lifetimes {
+        let copy_functions = skb_probe_targets(&SKB_COPY_FUNCTIONS);
+
+        probes.push((String::from("probe_skb_alloc_return"), skb_probe_targets(&SKB_ALLOC_FUNCTIONS)));
+        probes.push((String::from("probe_skb_copy_entry"), copy_functions.clone()));
+        probes.push((String::from("probe_skb_copy_return"), copy_functions));
+        probes.push((String::from("probe_skb_free"), skb_probe_targets(&SKB_FREE_FUNCTIONS)));
     }
 
     probes
-});
+}
 
 #[derive(Serialize)]
 pub struct JsonData {
@@ -94,12 +167,38 @@ pub struct JsonData {
     pub execution_times: Vec<ExecutionTimeRow>,
     pub xdp_times: Vec<(u64, String)>,
     pub throughput: Vec<ThroughputRow>,
+    pub flows: Vec<FlowRow>,
+    pub usdt_events: Vec<UsdtEventRow>,
+    pub dynamic_functions: Vec<String>,
+    pub attach_report: Vec<AttachReportRow>,
+    pub probe_mechanisms: BTreeMap<String, ProbeMechanism>,
+    pub xdp_modes: BTreeMap<String, XdpMode>,
+    pub stream: Option<StreamSummary>,
+    pub dropped_events: Vec<DroppedEventsRow>,
+    pub histograms: Vec<HistogramRow>,
+    pub packet_paths: Vec<PacketPathRow>,
+    pub residence_times: Option<ResidenceTimes>,
+    pub request_timelines: Vec<ConnectionTimelineRow>,
+    pub packet_drops: Option<PacketDrops>,
+    pub skb_lifetimes: Option<SkbLifetimes>,
+    pub irq_times: Option<IrqTimes>,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::builder().filter_level(ARGS.verbosity.log_level_filter()).init();
 
+    if let Some(path) = &ARGS.list_usdt {
+        for location in usdt_locations(path)? {
+            println!("{: <60} {:#x} {}", location.probe_name(), location.offset, location.args);
+        }
+
+        return Ok(());
+    }
+
+    load_kernel_symbols()?;
+    let dynamic_functions = resolve_dynamic_functions()?;
+
     /* --------- eBPF setup ----------- */
 
     // Bump the memlock rlimit. This is needed for older kernels that don't use the
@@ -113,14 +212,24 @@ async fn main() -> anyhow::Result<()> {
         debug!("remove limit on locked memory failed, ret is: {ret}");
     }
 
-    // This will include your eBPF object file as raw bytes at compile-time and load it at
-    // runtime. This approach is recommended for most real-world use cases. If you would
-    // like to specify the eBPF program at runtime rather than at compile-time, you can
-    // reach for `Bpf::load_file` instead.
-    let mut ebpf = aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
-        env!("OUT_DIR"),
-        "/aya-network-deep-profiling"
-    )))?;
+    let mut attach_report = AttachReport::default();
+
+    // Resolved before loading the programs so that the maps can be sized from them, each file being parsed once.
+    // The index of a location is given to the program as attach cookie, and indexes its arguments spec
+    let mut usdt_locations = Vec::new();
+
+    for target in &ARGS.usdts {
+        match resolve_usdt(target) {
+            Ok(locations) => usdt_locations.extend(locations),
+            Err(error) => attach_report.missing_symbol("probe_usdt", target, error)?
+        }
+    }
+
+    let nr_cpus = nr_cpus().map_err(|(_, error)| error)?;
+    let map_sizes = map_sizes(&dynamic_functions, &usdt_locations);
+    print_memory_estimate(EBPF_OBJECT, &map_sizes, nr_cpus)?;
+
+    let mut ebpf = ebpf_loader(&map_sizes).load(EBPF_OBJECT)?;
     if let Err(e) = aya_log::EbpfLogger::init(&mut ebpf) {
         // This can happen if you remove all log statements from your eBPF program.
         warn!("failed to initialize eBPF logger: {e}");
@@ -128,6 +237,63 @@ async fn main() -> anyhow::Result<()> {
 
     /* --------- Main preparation ----------- */
 
+    apply_scope(&mut ebpf)?;
+    apply_flow_filter(&mut ebpf)?;
+
+    let pressure_stop = Arc::new(Notify::new());
+    let pressure_monitor = spawn_pressure_monitor(&mut ebpf, pressure_stop.clone())?;
+
+    let recording_mode = match (ARGS.stream, ARGS.histogram) {
+        (true, _) => RecordingMode::Stream,
+        (_, true) => RecordingMode::Histogram,
+        _ => RecordingMode::Spans
+    };
+    let mut recording_mode_map: Array<_, u32> = Array::try_from(ebpf.map_mut("RECORDING_MODE").unwrap())?;
+    recording_mode_map.set(0, recording_mode as u32, 0)?;
+
+    if recording_mode == RecordingMode::Histogram {
+        let mut histogram_slots: EHashMap<_, u64, u32> = EHashMap::try_from(ebpf.map_mut("HISTOGRAM_SLOTS").unwrap())?;
+        set_dynamic_function_slots(&mut histogram_slots, &dynamic_functions)?;
+    }
+
+    if ARGS.packet_paths {
+        let mut packet_paths: Array<_, u32> = Array::try_from(ebpf.map_mut("PACKET_PATHS").unwrap())?;
+        packet_paths.set(0, 1, 0)?;
+    }
+
+    let drop_reasons: DropReasons = match ARGS.drops {
+        true => apply_drop_layout(&mut ebpf)?,
+        false => DropReasons::new()
+    };
+
+    if ARGS.skb_lifetimes {
+        let mut skb_tracking: Array<_, u32> = Array::try_from(ebpf.map_mut("SKB_TRACKING").unwrap())?;
+        skb_tracking.set(0, 1, 0)?;
+    }
+
+    if ARGS.irq_times {
+        apply_irq_layout(&mut ebpf)?;
+    }
+
+    if ARGS.connections {
+        apply_socket_layout(&mut ebpf)?;
+    }
+
+    if ARGS.residence_times {
+        let mut residence_times: Array<_, u32> = Array::try_from(ebpf.map_mut("RESIDENCE_TIMES").unwrap())?;
+        residence_times.set(0, 1, 0)?;
+    }
+
+    let stream_stop = Arc::new(Notify::new());
+    let stream_consumer = match recording_mode {
+        RecordingMode::Stream => {
+            let events = RingBuf::try_from(ebpf.take_map("EVENTS").unwrap())?;
+            let events_file_path = env::current_dir()?.join("shared").join("events.bin");
+            Some(spawn_stream_consumer(events, events_file_path, stream_stop.clone())?)
+        }
+        _ => None
+    };
+
     let mut ts = timespec {
         tv_sec: 0,
         tv_nsec: 0,
@@ -141,56 +307,236 @@ async fn main() -> anyhow::Result<()> {
     for (program, tracepoints) in TRACEPOINTS_.iter() {
         info!("Attaching program {program} to tracepoints: {tracepoints:?}");
         let tracepoint: &mut TracePoint = ebpf.program_mut(program).unwrap().try_into()?;
-        tracepoint.load()?;
+        let targets = tracepoints.iter().map(|(category, name)| format!("{category}:{name}")).collect::<Vec<String>>();
+
+        if !attach_report.load(program, &targets, tracepoint.load())? {
+            continue;
+        }
+
+        for ((category, name), target) in tracepoints.iter().zip(&targets) {
+            if let Some(link_id) = attach_report.attach(program, target, tracepoint.attach(category, name))? {
+                tracepoint_links.insert((program, (category, name)), link_id);
+            }
+        }
+    }
+
+    /* --------- Trampolines setup ----------- */
+
+    let mut fentry_links = HashMap::new();
+    let mut fexit_links = HashMap::new();
+    let mut trampoline_functions = HashSet::new();
+
+    if ARGS.backend == ProbeBackend::Fentry {
+        match Btf::from_sys_fs() {
+            Ok(btf) => {
+                for function in KERNEL_FUNCTIONS.iter().chain(ALLOCS.iter()) {
+                    match attach_trampoline(&mut ebpf, &btf, function) {
+                        Ok((target, fentry_link_id, fexit_link_id)) => {
+                            info!("Attached programs fentry_{function} and fexit_{function} to function {target}");
+                            attach_report.attached(&format!("fentry_{function}"), &target);
+                            attach_report.attached(&format!("fexit_{function}"), &target);
+                            fentry_links.insert(format!("fentry_{function}"), fentry_link_id);
+                            fexit_links.insert(format!("fexit_{function}"), fexit_link_id);
+                            trampoline_functions.insert(*function);
+                        }
+                        Err(error) => warn!("Falling back to kprobes for function {function}: {error}")
+                    }
+                }
+            }
+            Err(error) => warn!("BTF is not available, falling back to kprobes: {error}")
+        }
+    }
+
+    /* --------- kprobe_multi setup ----------- */
+
+    let mut kprobe_multi_links = Vec::new();
+    let mut kprobe_multi_symbols = HashSet::new();
+
+    if ARGS.backend == ProbeBackend::KprobeMulti {
+        let targets = kprobe_multi_targets(&trampoline_functions, &dynamic_functions);
+
+        match attach_kprobe_multi(&ebpf, EBPF_OBJECT, &targets) {
+            Ok(links) => {
+                info!("Attached programs probe_enter_multi and probe_ret_multi to {} functions", targets.len());
+
+                for (symbol, _) in targets {
+                    for (program, _) in KPROBE_MULTI_PROGRAMS {
+                        attach_report.attached(program, &symbol);
+                    }
 
-        for (category, name) in tracepoints {
-            let link_id = tracepoint.attach(category, name)?;
-            tracepoint_links.insert((program, (category, name)), link_id);
+                    kprobe_multi_symbols.insert(symbol);
+                }
+
+                kprobe_multi_links = links;
+            }
+            Err(error) => warn!("Falling back to kprobes, could not attach kprobe_multi programs: {error}")
         }
     }
 
     /* --------- Probes setup ----------- */
 
+    let kernel_probes = kernel_probes(&dynamic_functions);
     let mut kernel_probe_links = HashMap::new();
-    for (program, functions) in KERNEL_PROBES.iter() {
+    for (program, functions) in kernel_probes.iter() {
+        let function = program.trim_start_matches("probe_enter_").trim_start_matches("probe_ret_");
+
+        if trampoline_functions.contains(function) {
+            continue;
+        }
+
+        // Symbols of the timed functions not already covered by the kprobe_multi links, the other programs being attached to all their symbols
+        let is_timing = program.starts_with("probe_enter_") || program.starts_with("probe_ret_");
+        let functions = functions
+            .iter()
+            .filter(|function| !is_timing || !kprobe_multi_symbols.contains(*function))
+            .collect::<Vec<&String>>();
+
+        if functions.is_empty() {
+            continue;
+        }
+
         info!("Attaching program {program} to functions: {functions:?}");
         let probe: &mut KProbe = ebpf.program_mut(program).unwrap().try_into()?;
-        probe.load()?;
+
+        if !attach_report.load(program, &functions, probe.load())? {
+            continue;
+        }
 
         for function in functions {
-            let link_id = probe.attach(function, 0)?;
-            kernel_probe_links.insert((program, function), link_id);
+            if let Some(link_id) = attach_report.attach(program, function, probe.attach(function, 0))? {
+                kernel_probe_links.insert((program, function), link_id);
+            }
         }
     }
 
-    let mut user_probe_links = HashMap::new();
-    for (program, functions) in USER_PROBES.iter() {
-        info!("Attaching program {program} to functions: {functions:?}");
-        let probe: &mut UProbe = ebpf.program_mut(program).unwrap().try_into()?;
-        probe.load()?;
+    // The index of a target is the id of its function, given to the programs as attach cookie
+    let mut user_probe_targets = Vec::new();
+
+    for target in &ARGS.uprobes {
+        match resolve_user_probe(target) {
+            Ok(user_probe_target) => user_probe_targets.push(user_probe_target),
+            Err(error) => attach_report.missing_symbol("probe_enter_user", target, error)?
+        }
+    }
+
+    let mut user_probe_links = Vec::new();
+    if !user_probe_targets.is_empty() {
+        for (program, is_return) in USER_PROBE_PROGRAMS {
+            let targets = user_probe_targets.iter().map(|target| target.name()).collect::<Vec<String>>();
+            info!("Attaching program {program} to functions: {targets:?}");
+            let probe: &mut UProbe = ebpf.program_mut(program).unwrap().try_into()?;
+
+            if !attach_report.load(program, &targets, probe.load())? {
+                continue;
+            }
+
+            for (function, (target, name)) in user_probe_targets.iter().zip(&targets).enumerate() {
+                let result = attach_user_probe(probe.fd()?, program, target, function as u64, is_return);
+
+                if let Some(link) = attach_report.attach(program, name, result)? {
+                    user_probe_links.push(link);
+                }
+            }
+        }
+    }
 
-        for (function, lib) in functions {
-            let link_id = probe.attach(Some(function), 0, lib, None)?;
-            user_probe_links.insert((program, function), link_id);
+    let mut usdt_links = Vec::new();
+    if !usdt_locations.is_empty() {
+        let mut usdt_specs: EHashMap<_, u32, UsdtSpec> = EHashMap::try_from(ebpf.map_mut("USDT_SPECS").unwrap())?;
+
+        for (location_id, location) in usdt_locations.iter().enumerate() {
+            usdt_specs.insert(location_id as u32, location.spec(), 0)?;
+        }
+
+        let targets = usdt_locations.iter().map(|location| location.user_probe_target()).collect::<Vec<_>>();
+        let names = targets.iter().map(|target| target.name()).collect::<Vec<String>>();
+        info!("Attaching program probe_usdt to probes: {names:?}");
+        let probe: &mut UProbe = ebpf.program_mut("probe_usdt").unwrap().try_into()?;
+
+        if attach_report.load("probe_usdt", &names, probe.load())? {
+            for (location_id, (target, name)) in targets.iter().zip(&names).enumerate() {
+                let result = attach_user_probe(probe.fd()?, "probe_usdt", target, location_id as u64, false);
+
+                if let Some(link) = attach_report.attach("probe_usdt", name, result)? {
+                    usdt_links.push(link);
+                }
+            }
+        }
+    }
+
+    attach_report.print();
+
+    // The functions whose probes all failed to attach with `--keep-going` are left out
+    let mut probe_mechanisms = BTreeMap::new();
+
+    for function in KERNEL_FUNCTIONS.iter().chain(ALLOCS.iter()) {
+        let targets = kernel_function_targets(&function.replace("_p_", "."));
+        let program = format!("probe_enter_{function}");
+
+        let mechanism = if trampoline_functions.contains(function) {
+            ProbeMechanism::Fentry
         }
+        else if targets.iter().any(|target| attach_report.is_attached(&program, target)) {
+            ProbeMechanism::Kprobe
+        }
+        else if targets.iter().any(|target| kprobe_multi_symbols.contains(target)) {
+            ProbeMechanism::KprobeMulti
+        }
+        else {
+            continue;
+        };
+
+        probe_mechanisms.insert(function.replace("_p_", "."), mechanism);
+    }
+
+    for function in dynamic_functions.iter() {
+        let mechanism = if kprobe_multi_symbols.contains(function) {
+            ProbeMechanism::KprobeMulti
+        }
+        else if attach_report.is_attached("probe_enter_dynamic", function) {
+            ProbeMechanism::Kprobe
+        }
+        else {
+            continue;
+        };
+
+        probe_mechanisms.insert(logical_name(function).to_string(), mechanism);
+    }
+
+    for target in &user_probe_targets {
+        probe_mechanisms.insert(target.symbol.clone(), ProbeMechanism::Uprobe);
+    }
+
+    for location in &usdt_locations {
+        probe_mechanisms.insert(location.probe_name(), ProbeMechanism::Usdt);
     }
 
     /* --------- XDP setup ----------- */
 
     let interfaces = getifaddrs()?.collect::<Vec<_>>();
+    let profiled_interfaces = interfaces.iter().filter(|interface| is_profiled_interface(&interface.name)).collect::<Vec<_>>();
+
+    for name in &ARGS.ifaces {
+        if !interfaces.iter().any(|interface| &interface.name == name) {
+            warn!("Interface {name} not found");
+        }
+    }
 
     let mut xdp_links: HashMap<String, XdpLinkId> = HashMap::new();
     let program: &mut Xdp = ebpf.program_mut("xdp_packet_log").unwrap().try_into()?;
     program.load()?;
 
-    for interface in &interfaces {
+    let mut xdp_modes: BTreeMap<String, XdpMode> = BTreeMap::new();
+
+    for interface in &profiled_interfaces {
         if xdp_links.contains_key(&interface.name) {
             continue;
         }
 
         info!("Attaching program xdp_packet_log to iface {}", interface.name);
-        let link_id = program.attach(&interface.name, XdpFlags::default())?;
+        let (link_id, xdp_mode) = attach_xdp(program, &interface.name)?;
         xdp_links.insert(interface.name.clone(), link_id);
+        xdp_modes.insert(interface.name.clone(), xdp_mode);
     }
 
     /* --------- Classifiers setup --------- */
@@ -199,7 +545,7 @@ async fn main() -> anyhow::Result<()> {
     let ingress_program: &mut SchedClassifier = ebpf.program_mut("tc_ingress").unwrap().try_into()?;
     ingress_program.load()?;
 
-    for interface in &interfaces {
+    for interface in &profiled_interfaces {
         if classifiers_links.contains_key(&(interface.name.clone(), TcAttachType::Ingress)) {
             continue;
         }
@@ -212,7 +558,7 @@ async fn main() -> anyhow::Result<()> {
     let egress_program: &mut SchedClassifier = ebpf.program_mut("tc_egress").unwrap().try_into()?;
     egress_program.load()?;
 
-    for interface in &interfaces {
+    for interface in &profiled_interfaces {
         if classifiers_links.contains_key(&(interface.name.clone(), TcAttachType::Egress)) {
             continue;
         }
@@ -267,6 +613,27 @@ async fn main() -> anyhow::Result<()> {
         tracepoint.detach(link_id)?;
     }
 
+    /* --------- Trampolines end ----------- */
+
+    for (program, link_id) in fentry_links {
+        info!("Detaching program {program}");
+        let fentry: &mut FEntry = ebpf.program_mut(&program).unwrap().try_into()?;
+        fentry.detach(link_id)?;
+    }
+
+    for (program, link_id) in fexit_links {
+        info!("Detaching program {program}");
+        let fexit: &mut FExit = ebpf.program_mut(&program).unwrap().try_into()?;
+        fexit.detach(link_id)?;
+    }
+
+    /* --------- kprobe_multi end ----------- */
+
+    for link in kprobe_multi_links {
+        info!("Detaching program {}", link.program);
+        drop(link);
+    }
+
     /* --------- Probes end ----------- */
 
     for ((program, function), link_id) in kernel_probe_links {
@@ -275,10 +642,14 @@ async fn main() -> anyhow::Result<()> {
         probe.detach(link_id)?;
     }
 
-    for ((program, function), link_id) in user_probe_links {
-        info!("Detaching program {program} from function {function}");
-        let probe: &mut UProbe = ebpf.program_mut(program).unwrap().try_into()?;
-        probe.detach(link_id)?;
+    for link in user_probe_links {
+        info!("Detaching program {} from function {}", link.program, link.target);
+        drop(link);
+    }
+
+    for link in usdt_links {
+        info!("Detaching program {} from probe {}", link.program, link.target);
+        drop(link);
     }
 
     /* --------- XDP end ----------- */
@@ -315,6 +686,21 @@ async fn main() -> anyhow::Result<()> {
     /* --------- Main program ----------- */
     info!("Gathering data...");
 
+    pressure_stop.notify_one();
+    let dropped_events = pressure_monitor.await??;
+
+    let stream = match stream_consumer {
+        Some(stream_consumer) => {
+            stream_stop.notify_one();
+            let mut stream = stream_consumer.await??;
+            stream.user_functions = user_probe_targets.iter().map(|target| target.symbol.clone()).collect();
+
+            println!("Streamed {} function calls to {}", stream.records, stream.path.display());
+            Some(stream)
+        }
+        None => None
+    };
+
     let mut kmalloc_allocations: Queue<_, AllocInfo> = Queue::try_from(ebpf.take_map("KMALLOC_ALLOCATIONS").unwrap())?;
     let mut kmem_cache_allocations: Queue<_, AllocInfo> = Queue::try_from(ebpf.take_map("KMEM_CACHE_ALLOCATIONS").unwrap())?;
     let registered_functions: EHashMap<_, i64, u16> = EHashMap::try_from(ebpf.take_map("REGISTERED_FUNCTIONS").unwrap())?;
@@ -329,31 +715,130 @@ async fn main() -> anyhow::Result<()> {
     println!();
     println!("CPU frequency: {} Hz", *CPU_FREQUENCY);
 
+    if !dynamic_functions.is_empty() {
+        println!("Dynamically profiled functions ({}): {}", dynamic_functions.len(), dynamic_functions.join(", "));
+    }
+
     //let cache_misses: maps::HashMap<_, u64, u64> = maps::HashMap::try_from(ebpf.take_map("CACHE_MISSES").unwrap())?;
 
-    let kernel_functions_execution_times: PerCpuHashMap<_, u64, FunctionCall<KernelFunction>> = PerCpuHashMap::try_from(ebpf.take_map("KERNEL_FUNCTIONS_EXECUTION_TIMES").unwrap())?;
-    let kernel_functions_execution_times = filter_times(kernel_functions_execution_times, initial_time);
-    let kernel_functions_execution_times = handle_execution_times(kernel_functions_execution_times, initial_time);
+    let kernel_functions_execution_times: PerCpuHashMap<_, u64, Event<FunctionSpan<KernelFunction>>> = PerCpuHashMap::try_from(ebpf.take_map("KERNEL_FUNCTIONS_EXECUTION_TIMES").unwrap())?;
+    let kernel_functions_execution_times = filter_events(kernel_functions_execution_times, initial_time);
+    let kernel_functions_execution_times = handle_execution_times(kernel_functions_execution_times, program_name, initial_time);
 
     println!();
 
-    let user_functions_execution_times: PerCpuHashMap<_, u64, FunctionCall<UserFunction>> = PerCpuHashMap::try_from(ebpf.take_map("USER_FUNCTIONS_EXECUTION_TIMES").unwrap())?;
-    let user_functions_execution_times = filter_times(user_functions_execution_times, initial_time);
-    let user_functions_execution_times = handle_execution_times(user_functions_execution_times, initial_time);
+    let dynamic_functions_execution_times: PerCpuHashMap<_, u64, Event<FunctionSpan<FunctionAddress>>> = PerCpuHashMap::try_from(ebpf.take_map("DYNAMIC_FUNCTIONS_EXECUTION_TIMES").unwrap())?;
+    let dynamic_functions_execution_times = filter_events(dynamic_functions_execution_times, initial_time);
+    let dynamic_functions_execution_times = handle_execution_times(dynamic_functions_execution_times, |function| logical_name(&kernel_symbol_name(function.0)).to_string(), initial_time);
 
     println!();
 
-    let alloc_functions_execution_times: PerCpuHashMap<_, u64, FunctionCall<Alloc>> = PerCpuHashMap::try_from(ebpf.take_map("ALLOC_FUNCTIONS_EXECUTION_TIMES").unwrap())?;
-    let alloc_functions_execution_times = filter_times(alloc_functions_execution_times, initial_time);
-    let alloc_functions_execution_times = handle_execution_times(alloc_functions_execution_times, initial_time);
+    let user_functions_execution_times: PerCpuHashMap<_, u64, Event<FunctionSpan<UserFunction>>> = PerCpuHashMap::try_from(ebpf.take_map("USER_FUNCTIONS_EXECUTION_TIMES").unwrap())?;
+    let user_functions_execution_times = filter_events(user_functions_execution_times, initial_time);
+    let user_functions_execution_times = handle_execution_times(user_functions_execution_times, |function| user_probe_targets[function.0 as usize].symbol.clone(), initial_time);
 
-    let execution_times = [kernel_functions_execution_times, user_functions_execution_times, alloc_functions_execution_times].concat();
+    println!();
 
-    let xdp_times: maps::HashMap<_, u64, EthHeader> = maps::HashMap::try_from(ebpf.take_map("XDP_TIMES").unwrap())?;
-    let xdp_times = process_xdp(xdp_times, initial_time);
+    let alloc_functions_execution_times: PerCpuHashMap<_, u64, Event<FunctionSpan<Alloc>>> = PerCpuHashMap::try_from(ebpf.take_map("ALLOC_FUNCTIONS_EXECUTION_TIMES").unwrap())?;
+    let alloc_functions_execution_times = filter_events(alloc_functions_execution_times, initial_time);
+    let alloc_functions_execution_times = handle_execution_times(alloc_functions_execution_times, program_name, initial_time);
+
+    let request_timelines = match user_functions_execution_times.is_empty() {
+        true => Vec::new(),
+        false => {
+            println!();
+
+            let socket_events: PerCpuHashMap<_, u64, Event<SocketEvent>> = PerCpuHashMap::try_from(ebpf.take_map("SOCKET_EVENTS").unwrap())?;
+            let socket_events = filter_events(socket_events, initial_time);
+            process_request_timelines(&user_functions_execution_times, &[&kernel_functions_execution_times, &dynamic_functions_execution_times], socket_events, initial_time)
+        }
+    };
+
+    let execution_times = [kernel_functions_execution_times, dynamic_functions_execution_times, user_functions_execution_times, alloc_functions_execution_times].concat();
+
+    let histograms = match recording_mode {
+        RecordingMode::Histogram => {
+            println!();
+
+            let histograms: PerCpuArray<_, Histogram> = PerCpuArray::try_from(ebpf.take_map("HISTOGRAMS").unwrap())?;
+            process_histograms(&histograms, &user_probe_targets, &dynamic_functions)?
+        }
+        _ => Vec::new()
+    };
+
+    let packet_paths = match ARGS.packet_paths {
+        true => {
+            println!();
+
+            let packet_stages: PerCpuHashMap<_, u64, Event<PacketStage>> = PerCpuHashMap::try_from(ebpf.take_map("PACKET_STAGES").unwrap())?;
+            let packet_stages = filter_events(packet_stages, initial_time);
+            process_packet_paths(packet_stages, initial_time)
+        }
+        false => Vec::new()
+    };
+
+    let mut usdt_events: Queue<_, UsdtEvent> = Queue::try_from(ebpf.take_map("USDT_EVENTS").unwrap())?;
+    let usdt_events = usdt::collect_queue(&mut usdt_events, initial_time);
+    let usdt_events = process_usdt_events(usdt_events, &usdt_locations, initial_time);
+
+    let xdp_times: PerCpuHashMap<_, u64, Event<XdpPacket>> = PerCpuHashMap::try_from(ebpf.take_map("XDP_TIMES").unwrap())?;
+    let xdp_times = filter_events(xdp_times, initial_time);
 
     let mut throughput_stats: Queue<_, ThroughputStat> = Queue::try_from(ebpf.take_map("THROUGHPUT_STATS").unwrap())?;
     let throughput_stats = throughput::collect_queue(&mut throughput_stats, initial_time);
+
+    println!();
+    let flows = process_flows(&throughput_stats, &xdp_times);
+
+    let packet_drops = match ARGS.drops {
+        true => {
+            println!();
+
+            let packet_drops: PerCpuHashMap<_, u64, Event<PacketDrop>> = PerCpuHashMap::try_from(ebpf.take_map("PACKET_DROPS").unwrap())?;
+            let packet_drops = filter_events(packet_drops, initial_time);
+            Some(process_drops(packet_drops, &drop_reasons, &stack_traces, &interfaces, initial_time))
+        }
+        false => None
+    };
+
+    let skb_lifetimes = match ARGS.skb_lifetimes {
+        true => {
+            println!();
+
+            let skb_events: PerCpuHashMap<_, u64, Event<SkbEvent>> = PerCpuHashMap::try_from(ebpf.take_map("SKB_EVENTS").unwrap())?;
+            let skb_events = filter_events(skb_events, initial_time);
+            Some(process_skb_lifetimes(skb_events, &stack_traces, initial_time))
+        }
+        false => None
+    };
+
+    let irq_times = match ARGS.irq_times {
+        true => {
+            println!();
+
+            let irq_spans: PerCpuHashMap<_, u64, Event<IrqSpan>> = PerCpuHashMap::try_from(ebpf.take_map("IRQ_SPANS").unwrap())?;
+            let irq_spans = filter_events(irq_spans, initial_time);
+            let napi_polls: PerCpuHashMap<_, u64, Event<NapiPoll>> = PerCpuHashMap::try_from(ebpf.take_map("NAPI_POLLS").unwrap())?;
+            let napi_polls = filter_events(napi_polls, initial_time);
+            Some(process_irq_times(irq_spans, napi_polls, initial_time))
+        }
+        false => None
+    };
+
+    let residence_times = match ARGS.residence_times {
+        true => {
+            println!();
+
+            let packet_residences: PerCpuHashMap<_, u64, Event<PacketArrival>> = PerCpuHashMap::try_from(ebpf.take_map("PACKET_RESIDENCES").unwrap())?;
+            let packet_residences = filter_events(packet_residences, initial_time);
+            let packet_arrivals: EHashMap<_, u64, PacketArrival> = EHashMap::try_from(ebpf.take_map("PACKET_ARRIVALS").unwrap())?;
+            Some(process_residence_times(packet_residences, &packet_arrivals, &interfaces, initial_time))
+        }
+        false => None
+    };
+
+    println!();
+    let xdp_times = process_xdp(xdp_times, initial_time);
     let throughput = process_throughput(throughput_stats, interfaces, initial_time);
 
     info!("Writing results to file...");
@@ -363,6 +848,21 @@ async fn main() -> anyhow::Result<()> {
         execution_times,
         xdp_times,
         throughput,
+        flows,
+        usdt_events,
+        dynamic_functions,
+        attach_report: attach_report.rows,
+        probe_mechanisms,
+        xdp_modes,
+        stream,
+        dropped_events,
+        histograms,
+        packet_paths,
+        residence_times,
+        request_timelines,
+        packet_drops,
+        skb_lifetimes,
+        irq_times,
     };
     let results_json = serde_json::to_string(&json_data)?;
     let results_file_path = env::current_dir()?.join("shared").join("results.json");
@@ -1,6 +1,6 @@
 use crate::{ARGS, FUNCTIONS};
+use crate::symbols::{logical_name, symbol_table};
 use aya::maps::{HashMap as EHashMap, MapData, Queue, StackTraceMap};
-use aya::util::kernel_symbols;
 use aya_network_deep_profiling::MemStat;
 use aya_network_deep_profiling_common::{Alloc, AllocDirection, AllocInfo};
 use rayon::prelude::*;
@@ -21,7 +21,7 @@ pub fn collect_queue(allocations: &mut Queue<MapData, AllocInfo>, initial_time:
 }
 
 pub fn handle_memory_usage(allocations: &mut Vec<AllocInfo>, registered_functions: &EHashMap<MapData, i64, u16>, stack_traces: &StackTraceMap<MapData>, initial_time: u64) -> anyhow::Result<()> {
-    let ksyms = kernel_symbols()?;
+    let ksyms = symbol_table();
 
     let mut memory_stats: HashMap<i64, MemStat> = HashMap::new();
 
@@ -63,7 +63,7 @@ pub fn handle_memory_usage(allocations: &mut Vec<AllocInfo>, registered_function
     }
 
     for (stack_id, id) in registered_functions.iter().filter_map(|t| t.ok()) {
-        println!("{stack_id:X} {}", Alloc::from_id(id).as_str());
+        println!("{stack_id:X} {}", Alloc::from_id(id).map_or("unknown", |alloc| alloc.as_str()));
     }
 
     println!("==================================== Memory Usage Statistics ====================================");
@@ -74,9 +74,9 @@ pub fn handle_memory_usage(allocations: &mut Vec<AllocInfo>, registered_function
     println!("-------------------------------------------------------------------------------------------------");
 
     memory_stats.par_iter().for_each(|(stack_id, mem_stat)| {
-        let function_name = match registered_functions.get(stack_id, 0) {
-            Ok(function_id) => Alloc::from_id(function_id).to_string(),
-            Err(_) => String::from("Unknown")
+        let function_name = match registered_functions.get(stack_id, 0).ok().and_then(Alloc::from_id) {
+            Some(alloc) => alloc.to_string(),
+            None => String::from("Unknown")
         };
 
         println!(
@@ -106,7 +106,7 @@ pub fn handle_memory_usage(allocations: &mut Vec<AllocInfo>, registered_function
                     let targets = symbols
                         .par_iter()
                         .filter_map(|symbol| symbol.1)
-                        .filter_map(|symbol_name| FUNCTIONS.iter().find_map(|name| match &name.replace("_p_", ".") == symbol_name {
+                        .filter_map(|symbol_name| FUNCTIONS.iter().find_map(|name| match name.replace("_p_", ".") == logical_name(symbol_name) {
                             true => Some(symbol_name.as_str()),
                             false => None
                         }))
@@ -0,0 +1,92 @@
+use std::collections::{BTreeMap, HashMap};
+use serde::Serialize;
+use aya_network_deep_profiling_common::{PacketStage, PacketStageKind, SkbTracepoint};
+use crate::time::program_name;
+use crate::utils::{mean, median};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketStageRow {
+    pub stage: String,
+    pub timestamp: u64,
+    pub cpuid: u32,
+    pub tid: u32,
+    pub tgid: u32,
+}
+
+/// Path of a packet through the stack, from the first profiled function it entered to the release of its `sk_buff`
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketPathRow {
+    pub skb: u64,
+    pub stages: Vec<PacketStageRow>,
+    pub dropped: bool,
+    /// Time between the first stage and the release of the `sk_buff`
+    pub residence_time: u64,
+}
+
+fn stage_name(stage: &PacketStage) -> String {
+    match (stage.kind, stage.function) {
+        (PacketStageKind::Consumed, _) => SkbTracepoint::consume_skb.to_string(),
+        (PacketStageKind::Freed, _) => SkbTracepoint::kfree_skb.to_string(),
+        (PacketStageKind::Function, Some(function)) => program_name(function),
+        (PacketStageKind::Function, None) => String::from("Unknown"),
+    }
+}
+
+/// Stitches the stages of each `sk_buff` into paths, a path ending when the `sk_buff` is released since its address is then reused.
+/// The stages must be ordered by time.
+pub fn process_packet_paths(stages: Vec<(u64, PacketStage)>, initial_time: u64) -> Vec<PacketPathRow> {
+    let mut open_paths: HashMap<u64, Vec<PacketStageRow>> = HashMap::new();
+    let mut paths: Vec<PacketPathRow> = Vec::new();
+
+    for (time, stage) in stages {
+        let row = PacketStageRow {
+            stage: stage_name(&stage),
+            timestamp: time.saturating_sub(initial_time),
+            cpuid: stage.cpuid,
+            tid: stage.tid,
+            tgid: stage.tgid,
+        };
+
+        if stage.kind == PacketStageKind::Function {
+            open_paths.entry(stage.skb).or_default().push(row);
+            continue;
+        }
+
+        // A released sk_buff that went through no profiled function
+        let Some(mut stages) = open_paths.remove(&stage.skb) else {
+            continue;
+        };
+
+        let residence_time = row.timestamp.saturating_sub(stages[0].timestamp);
+        stages.push(row);
+
+        paths.push(PacketPathRow {
+            skb: stage.skb,
+            stages,
+            dropped: stage.kind == PacketStageKind::Freed,
+            residence_time,
+        });
+    }
+
+    let mut residence_times: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+
+    for path in &paths {
+        let stages = path.stages.iter().map(|stage| stage.stage.as_str()).collect::<Vec<&str>>().join(" > ");
+        residence_times.entry(stages).or_default().push(path.residence_time);
+    }
+
+    println!("================================================= Packet Paths =================================================");
+    println!("{: >8} {: >18} {: >18}   Path", "Count", "Mean residence", "Median residence");
+    println!("----------------------------------------------------------------------------------------------------------------");
+
+    for (stages, mut times) in residence_times {
+        times.sort_unstable();
+        println!("{: >8} {: >15} ns {: >15} ns   {}", times.len(), mean(&times), median(&times), stages);
+    }
+
+    if !open_paths.is_empty() {
+        println!("{} packets were not released before the end of the capture", open_paths.len());
+    }
+
+    paths
+}
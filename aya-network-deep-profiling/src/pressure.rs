@@ -0,0 +1,134 @@
+use std::sync::Arc;
+use std::time::Duration;
+use aya::Ebpf;
+use aya::maps::{MapData, PerCpuArray};
+use log::warn;
+use serde::Serialize;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time;
+use aya_network_deep_profiling_common::{EventMap, MapPressure, EVENT_MAP_VARIANTS};
+use crate::kprobe_multi::map_data;
+use crate::ARGS;
+
+/// LRU maps, whose inserts evict the least recently used entry instead of failing when they are full
+const LRU_MAPS: [EventMap; 3] = [EventMap::OpenCalls, EventMap::PacketArrivals, EventMap::SkbCopySources];
+
+/// Events lost by the eBPF programs because a map was full, the results being partial when `total` is not 0
+#[derive(Debug, Clone, Serialize)]
+pub struct DroppedEventsRow {
+    pub map: String,
+    pub total: u64,
+    pub per_cpu: Vec<u64>,
+}
+
+/// Reads the `MAP_PRESSURE` counters every `--pressure-interval` seconds to print the fill level of the maps,
+/// until `stop` is notified. Returns the dropped events of each map.
+pub fn spawn_pressure_monitor(ebpf: &mut Ebpf, stop: Arc<Notify>) -> anyhow::Result<JoinHandle<anyhow::Result<Vec<DroppedEventsRow>>>> {
+    let capacities = map_capacities(ebpf)?;
+    let map_pressure: PerCpuArray<MapData, MapPressure> = PerCpuArray::try_from(ebpf.take_map("MAP_PRESSURE").unwrap())?;
+
+    Ok(tokio::spawn(async move {
+        match ARGS.pressure_interval {
+            0 => stop.notified().await,
+            interval => {
+                let mut ticks = time::interval(Duration::from_secs(interval));
+                // The first tick completes immediately
+                ticks.tick().await;
+
+                loop {
+                    tokio::select! {
+                        _ = ticks.tick() => print_fill_levels(&map_pressure, &capacities)?,
+                        _ = stop.notified() => break
+                    }
+                }
+            }
+        }
+
+        dropped_events(&map_pressure)
+    }))
+}
+
+/// Max entries of each event map, `None` for the ring buffer, consumed during the run
+fn map_capacities(ebpf: &Ebpf) -> anyhow::Result<Vec<Option<u32>>> {
+    EVENT_MAP_VARIANTS
+        .into_iter()
+        .map(|map| match map {
+            EventMap::Events => Ok(None),
+            map => {
+                let name = map.as_str().to_uppercase();
+                let map = ebpf.map(&name).ok_or_else(|| anyhow::anyhow!("Map {name} not found"))?;
+                Ok(Some(map_data(map).info()?.max_entries()))
+            }
+        })
+        .collect()
+}
+
+fn read_pressure(map_pressure: &PerCpuArray<MapData, MapPressure>, map: EventMap) -> anyhow::Result<Vec<MapPressure>> {
+    Ok(map_pressure.get(&(map as u32), 0)?.iter().copied().collect())
+}
+
+fn print_fill_levels(map_pressure: &PerCpuArray<MapData, MapPressure>, capacities: &[Option<u32>]) -> anyhow::Result<()> {
+    println!("=================================================== Map pressure ====================================================");
+    println!("{: <36} {: >14} {: >14} {: >8} {: >14}", "Map", "Entries", "Max entries", "Fill", "Dropped");
+    let mut has_lru_map = false;
+
+    for (map, capacity) in EVENT_MAP_VARIANTS.into_iter().zip(capacities) {
+        let pressure = read_pressure(map_pressure, map)?;
+        // A CPU removing an entry written by another one has its count wrapped below zero
+        let entries = pressure.iter().fold(0u64, |entries, cpu| entries.wrapping_add(cpu.recorded));
+        let dropped = pressure.iter().map(|cpu| cpu.dropped).sum::<u64>();
+
+        if entries == 0 && dropped == 0 {
+            continue;
+        }
+
+        let (capacity, fill) = match capacity {
+            Some(capacity) => (capacity.to_string(), format!("{:.1}%", entries as f64 * 100.0 / *capacity as f64)),
+            None => ("-".to_string(), "-".to_string())
+        };
+
+        let name = match LRU_MAPS.contains(&map) {
+            true => {
+                has_lru_map = true;
+                format!("{} (LRU)", map.as_str().to_uppercase())
+            }
+            false => map.as_str().to_uppercase()
+        };
+
+        println!("{: <36} {: >14} {: >14} {: >8} {: >14}", name, entries, capacity, fill, dropped);
+    }
+
+    if has_lru_map {
+        println!("The LRU maps evict their least recently used entries instead of dropping, their evictions are not counted in their fill level");
+    }
+
+    Ok(())
+}
+
+fn dropped_events(map_pressure: &PerCpuArray<MapData, MapPressure>) -> anyhow::Result<Vec<DroppedEventsRow>> {
+    let rows = EVENT_MAP_VARIANTS
+        .into_iter()
+        .map(|map| {
+            let per_cpu = read_pressure(map_pressure, map)?
+                .iter()
+                .map(|cpu| cpu.dropped)
+                .collect::<Vec<u64>>();
+
+            Ok(DroppedEventsRow {
+                map: map.as_str().to_uppercase(),
+                total: per_cpu.iter().sum(),
+                per_cpu,
+            })
+        })
+        .collect::<anyhow::Result<Vec<DroppedEventsRow>>>()?;
+
+    for (map, row) in EVENT_MAP_VARIANTS.into_iter().zip(&rows).filter(|(_, row)| row.total > 0) {
+        match LRU_MAPS.contains(&map) {
+            true => warn!("{} entries were evicted from {} before being used, the results are partial", row.total, row.map),
+            false => warn!("{} events were dropped because {} was full, the results are partial", row.total, row.map)
+        }
+    }
+
+    Ok(rows)
+}
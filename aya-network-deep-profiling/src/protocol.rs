@@ -0,0 +1,135 @@
+use std::fmt;
+use serde::Serialize;
+
+/// Highest value of the EtherType field standing for the length of an IEEE 802.3 frame
+const MAX_IEEE_8023_LENGTH: u16 = 0x05DC;
+
+/// EtherType of an Ethernet frame, decoded from the raw value read by the XDP program.
+/// Add a variant here to give a name to another protocol, unnamed values are kept as `Unknown`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize)]
+pub enum EtherType {
+    Ipv4,
+    Arp,
+    Ipv6,
+    /// 802.1Q tag, the VLAN id and the EtherType of the payload follow
+    Vlan,
+    /// 802.1ad service tag of a QinQ frame, followed by an 802.1Q tag
+    QinQ,
+    MplsUnicast,
+    MplsMulticast,
+    PppoeDiscovery,
+    PppoeSession,
+    Lldp,
+    FibreChannel,
+    Infiniband,
+    LoopbackIeee8023,
+    /// IEEE 802.3 frame, the field holding the length of the payload
+    Ieee8023Length(u16),
+    Unknown(u16),
+}
+
+impl EtherType {
+    /// From the raw field of `EthHeader`, in network byte order
+    pub fn from_wire(ether_type: u16) -> EtherType {
+        EtherType::from(u16::from_be(ether_type))
+    }
+}
+
+impl From<u16> for EtherType {
+    fn from(ether_type: u16) -> EtherType {
+        match ether_type {
+            0x0800 => EtherType::Ipv4,
+            0x0806 => EtherType::Arp,
+            0x86DD => EtherType::Ipv6,
+            0x8100 => EtherType::Vlan,
+            // 0x9100 is the pre-standard QinQ tag, still used by some switches
+            0x88A8 | 0x9100 => EtherType::QinQ,
+            0x8847 => EtherType::MplsUnicast,
+            0x8848 => EtherType::MplsMulticast,
+            0x8863 => EtherType::PppoeDiscovery,
+            0x8864 => EtherType::PppoeSession,
+            0x88CC => EtherType::Lldp,
+            0x8906 => EtherType::FibreChannel,
+            0x8915 => EtherType::Infiniband,
+            0x9000 => EtherType::LoopbackIeee8023,
+            length if length <= MAX_IEEE_8023_LENGTH => EtherType::Ieee8023Length(length),
+            ether_type => EtherType::Unknown(ether_type)
+        }
+    }
+}
+
+impl fmt::Display for EtherType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EtherType::Ipv4 => write!(f, "IPv4"),
+            EtherType::Arp => write!(f, "ARP"),
+            EtherType::Ipv6 => write!(f, "IPv6"),
+            EtherType::Vlan => write!(f, "802.1Q"),
+            EtherType::QinQ => write!(f, "QinQ"),
+            EtherType::MplsUnicast => write!(f, "MPLS"),
+            EtherType::MplsMulticast => write!(f, "MPLS multicast"),
+            EtherType::PppoeDiscovery => write!(f, "PPPoE discovery"),
+            EtherType::PppoeSession => write!(f, "PPPoE session"),
+            EtherType::Lldp => write!(f, "LLDP"),
+            EtherType::FibreChannel => write!(f, "FibreChannel"),
+            EtherType::Infiniband => write!(f, "Infiniband"),
+            EtherType::LoopbackIeee8023 => write!(f, "LoopbackIeee8023"),
+            EtherType::Ieee8023Length(length) => write!(f, "802.3 ({length} bytes)"),
+            EtherType::Unknown(ether_type) => write!(f, "Unknown EtherType {ether_type:#06X}")
+        }
+    }
+}
+
+/// Transport protocol of an IP packet, decoded from the protocol number of its `FlowId`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize)]
+pub enum IpProtocol {
+    Icmp,
+    Tcp,
+    Udp,
+    Icmpv6,
+    Unknown(u8),
+}
+
+impl From<u8> for IpProtocol {
+    fn from(protocol: u8) -> IpProtocol {
+        match protocol {
+            1 => IpProtocol::Icmp,
+            6 => IpProtocol::Tcp,
+            17 => IpProtocol::Udp,
+            58 => IpProtocol::Icmpv6,
+            protocol => IpProtocol::Unknown(protocol)
+        }
+    }
+}
+
+impl fmt::Display for IpProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpProtocol::Icmp => write!(f, "ICMP"),
+            IpProtocol::Tcp => write!(f, "TCP"),
+            IpProtocol::Udp => write!(f, "UDP"),
+            IpProtocol::Icmpv6 => write!(f, "ICMPv6"),
+            IpProtocol::Unknown(protocol) => write!(f, "IP protocol {protocol}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ether_type_from_value() {
+        assert_eq!(EtherType::from(0x0800), EtherType::Ipv4);
+        assert_eq!(EtherType::from(0x86DD), EtherType::Ipv6);
+        assert_eq!(EtherType::from(0x9100), EtherType::QinQ);
+        assert_eq!(EtherType::from(0x0060), EtherType::Ieee8023Length(0x0060));
+        assert_eq!(EtherType::from(MAX_IEEE_8023_LENGTH), EtherType::Ieee8023Length(MAX_IEEE_8023_LENGTH));
+        assert_eq!(EtherType::from(0x05DD), EtherType::Unknown(0x05DD));
+    }
+
+    #[test]
+    fn ether_type_from_wire() {
+        assert_eq!(EtherType::from_wire(0x0800u16.to_be()), EtherType::Ipv4);
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use aya::maps::{HashMap, MapData};
+use getifaddrs::Interface;
+use log::warn;
+use serde::Serialize;
+use aya_network_deep_profiling_common::PacketArrival;
+use crate::symbols::{is_kernel_function, kernel_function_targets};
+use crate::utils::{mean, median};
+
+/// Kernel functions enqueuing a received `sk_buff` to a socket: for UDP since Linux 4.10, for raw and the other sockets, and for TCP
+pub const SOCKET_ENQUEUE_FUNCTIONS: [&str; 3] = ["__udp_enqueue_schedule_skb", "__sock_queue_rcv_skb", "tcp_queue_rcv"];
+
+/// Targets of `probe_socket_enqueue`, skipping the enqueue functions inlined by the running kernel
+pub fn socket_enqueue_targets() -> Vec<String> {
+    let mut targets = Vec::new();
+
+    for function in SOCKET_ENQUEUE_FUNCTIONS {
+        if is_kernel_function(function) {
+            targets.extend(kernel_function_targets(function));
+        } else {
+            warn!("{function} can not be probed, the packets it enqueues have no socket time");
+        }
+    }
+
+    targets
+}
+
+/// Times of a received packet at the XDP hook, the TC ingress hook and its enqueue to a socket
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketResidenceRow {
+    pub interface: String,
+    pub xdp_time: u64,
+    pub tc_time: u64,
+    /// `None` when the packet was still in the stack at the end of the capture, the forwarded and dropped packets being forgotten
+    pub socket_time: Option<u64>,
+}
+
+/// Latencies of the packets received on an interface, in nanoseconds
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InterfaceResidenceRow {
+    pub interface: String,
+    pub packets: u64,
+    pub delivered_packets: u64,
+    pub mean_xdp_to_tc: u64,
+    pub median_xdp_to_tc: u64,
+    pub mean_tc_to_socket: u64,
+    pub median_tc_to_socket: u64,
+    pub mean_xdp_to_socket: u64,
+    pub median_xdp_to_socket: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResidenceTimes {
+    pub packets: Vec<PacketResidenceRow>,
+    pub interfaces: Vec<InterfaceResidenceRow>,
+}
+
+#[derive(Default)]
+struct Latencies {
+    xdp_to_tc: Vec<u64>,
+    tc_to_socket: Vec<u64>,
+    xdp_to_socket: Vec<u64>,
+}
+
+fn interface_name(interfaces: &[Interface], if_index: u32) -> String {
+    interfaces
+        .iter()
+        .find(|interface| interface.index == Some(if_index))
+        .map(|interface| interface.name.clone())
+        .unwrap_or_else(|| String::from("Unknown"))
+}
+
+/// Mean and median of unsorted latencies, 0 when there is none
+fn mean_median(mut latencies: Vec<u64>) -> (u64, u64) {
+    if latencies.is_empty() {
+        return (0, 0);
+    }
+
+    latencies.sort_unstable();
+    (mean(&latencies), median(&latencies))
+}
+
+/// Gathers the packets enqueued to a socket and the packets still in the arrivals map, and aggregates their latencies per interface.
+/// The residences must be ordered by time.
+pub fn process_residence_times(
+    residences: Vec<(u64, PacketArrival)>,
+    arrivals: &HashMap<MapData, u64, PacketArrival>,
+    interfaces: &[Interface],
+    initial_time: u64
+) -> ResidenceTimes {
+    let mut undelivered = arrivals
+        .iter()
+        .filter_map(|arrival| arrival.ok())
+        .map(|(_, arrival)| arrival)
+        .filter(|arrival| arrival.xdp_time >= initial_time)
+        .collect::<Vec<PacketArrival>>();
+    undelivered.sort_by_key(|arrival| arrival.xdp_time);
+
+    let packets = residences
+        .into_iter()
+        .map(|(socket_time, arrival)| (arrival, Some(socket_time)))
+        .chain(undelivered.into_iter().map(|arrival| (arrival, None)))
+        .map(|(arrival, socket_time): (PacketArrival, Option<u64>)| PacketResidenceRow {
+            interface: interface_name(interfaces, arrival.if_index),
+            xdp_time: arrival.xdp_time.saturating_sub(initial_time),
+            tc_time: arrival.tc_time.saturating_sub(initial_time),
+            socket_time: socket_time.map(|time| time.saturating_sub(initial_time)),
+        })
+        .collect::<Vec<PacketResidenceRow>>();
+
+    let mut latencies: BTreeMap<&str, Latencies> = BTreeMap::new();
+
+    for packet in &packets {
+        let interface_latencies = latencies.entry(&packet.interface).or_default();
+        interface_latencies.xdp_to_tc.push(packet.tc_time.saturating_sub(packet.xdp_time));
+
+        if let Some(socket_time) = packet.socket_time {
+            interface_latencies.tc_to_socket.push(socket_time.saturating_sub(packet.tc_time));
+            interface_latencies.xdp_to_socket.push(socket_time.saturating_sub(packet.xdp_time));
+        }
+    }
+
+    let interfaces = latencies
+        .into_iter()
+        .map(|(interface, latencies)| {
+            let packets = latencies.xdp_to_tc.len() as u64;
+            let delivered_packets = latencies.xdp_to_socket.len() as u64;
+            let (mean_xdp_to_tc, median_xdp_to_tc) = mean_median(latencies.xdp_to_tc);
+            let (mean_tc_to_socket, median_tc_to_socket) = mean_median(latencies.tc_to_socket);
+            let (mean_xdp_to_socket, median_xdp_to_socket) = mean_median(latencies.xdp_to_socket);
+
+            InterfaceResidenceRow {
+                interface: interface.to_string(),
+                packets,
+                delivered_packets,
+                mean_xdp_to_tc,
+                median_xdp_to_tc,
+                mean_tc_to_socket,
+                median_tc_to_socket,
+                mean_xdp_to_socket,
+                median_xdp_to_socket,
+            }
+        })
+        .collect::<Vec<InterfaceResidenceRow>>();
+
+    println!("=============================================== Residence Times ================================================");
+    println!("{: <16} {: >10} {: >10} {: >14} {: >14} {: >14} {: >14} {: >14} {: >14}", "Interface", "Packets", "Delivered", "Mean XDP>TC", "Median XDP>TC", "Mean TC>sock", "Median TC>sock", "Mean XDP>sock", "Median XDP>sock");
+
+    for row in &interfaces {
+        println!(
+            "{: <16} {: >10} {: >10} {: >11} ns {: >11} ns {: >11} ns {: >11} ns {: >11} ns {: >11} ns",
+            row.interface, row.packets, row.delivered_packets, row.mean_xdp_to_tc, row.median_xdp_to_tc,
+            row.mean_tc_to_socket, row.median_tc_to_socket, row.mean_xdp_to_socket, row.median_xdp_to_socket
+        );
+    }
+
+    ResidenceTimes { packets, interfaces }
+}
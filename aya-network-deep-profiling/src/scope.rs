@@ -0,0 +1,55 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use anyhow::anyhow;
+use aya::Ebpf;
+use aya::maps::{Array, HashMap as EHashMap};
+use log::info;
+use aya_network_deep_profiling_common::Scope;
+use crate::ARGS;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Pushes the `--pid`, `--comm` and `--cgroup` filters to the eBPF programs, which drop the events of the tasks out of scope
+pub fn apply_scope(ebpf: &mut Ebpf) -> anyhow::Result<()> {
+    let mut scope = Scope {
+        filter_pids: !ARGS.pids.is_empty(),
+        ..Scope::default()
+    };
+
+    if let Some(comm) = &ARGS.comm {
+        // The kernel keeps the first 15 characters of a task name
+        if comm.len() >= scope.comm.len() {
+            return Err(anyhow!("Task name {comm} is longer than {} characters", scope.comm.len() - 1));
+        }
+
+        scope.comm[..comm.len()].copy_from_slice(comm.as_bytes());
+    }
+
+    if let Some(cgroup) = &ARGS.cgroup {
+        // The id of a cgroup v2 is the inode number of its directory.
+        // A path not under the root is relative to it, as printed in `/proc/<pid>/cgroup` (`/system.slice/x.service`).
+        let path = match cgroup.starts_with(CGROUP_ROOT) {
+            true => cgroup.clone(),
+            false => Path::new(CGROUP_ROOT).join(cgroup.strip_prefix("/").unwrap_or(cgroup.as_path()))
+        };
+        scope.cgroup_id = fs::metadata(&path)
+            .map_err(|error| anyhow!("Could not open cgroup {}: {error}", path.display()))?
+            .ino();
+    }
+
+    let mut scope_pids: EHashMap<_, u32, u8> = EHashMap::try_from(ebpf.map_mut("SCOPE_PIDS").unwrap())?;
+
+    for pid in &ARGS.pids {
+        scope_pids.insert(pid, 1, 0)?;
+    }
+
+    let mut scope_map: Array<_, Scope> = Array::try_from(ebpf.map_mut("SCOPE").unwrap())?;
+    scope_map.set(0, scope, 0)?;
+
+    if scope.filter_pids || ARGS.comm.is_some() || ARGS.cgroup.is_some() {
+        info!("Recording only the events of pids {:?}, task {:?}, cgroup {:?}", ARGS.pids, ARGS.comm, ARGS.cgroup);
+    }
+
+    Ok(())
+}
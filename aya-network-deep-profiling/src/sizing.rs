@@ -0,0 +1,127 @@
+use aya::EbpfLoader;
+use aya_obj::generated::bpf_map_type;
+use aya_obj::Object;
+use crate::histogram::{dynamic_function_slots, histogram_slots};
+use crate::usdt::UsdtLocation;
+use crate::ARGS;
+
+/// Maps holding one entry per recorded event, sized by `--max-events`
+const EVENT_MAPS: [&str; 19] = [
+    "KMALLOC_ALLOCATIONS",
+    "TEMP_KMALLOC_ALLOCATIONS",
+    "KMEM_CACHE_ALLOCATIONS",
+    "TEMP_KMEM_CACHE_ALLOCATIONS",
+    "KERNEL_FUNCTIONS_EXECUTION_TIMES",
+    "USER_FUNCTIONS_EXECUTION_TIMES",
+    "DYNAMIC_FUNCTIONS_EXECUTION_TIMES",
+    "ALLOC_FUNCTIONS_EXECUTION_TIMES",
+    "USDT_EVENTS",
+    "XDP_TIMES",
+    "PACKET_STAGES",
+    "PACKET_ARRIVALS",
+    "PACKET_RESIDENCES",
+    "PACKET_DROPS",
+    "SKB_EVENTS",
+    "IRQ_SPANS",
+    "NAPI_POLLS",
+    "SOCKET_EVENTS",
+    "THROUGHPUT_STATS",
+];
+
+/// Calls that may be open at once in a thread, sizing the open calls map from `--max-tasks`
+const OPEN_CALLS_PER_TASK: u32 = 16;
+
+/// Kernel bookkeeping of a hash map element (`struct htab_elem`) and of a stack trace (`struct stack_map_bucket`)
+const HASH_ELEMENT_OVERHEAD: u64 = 48;
+const STACK_BUCKET_OVERHEAD: u64 = 24;
+
+/// Max entries of the resized maps, from `--max-events`, `--max-stacks`, `--max-registered-functions`, `--max-tasks` and `--stream-buffer`.
+/// The histograms are only allocated with `--histogram`, with a slot per function, and the maps filled by user space are sized from their content.
+pub fn map_sizes(dynamic_functions: &[String], usdt_locations: &[UsdtLocation]) -> Vec<(&'static str, u32)> {
+    let mut sizes = EVENT_MAPS.map(|map| (map, ARGS.max_events)).to_vec();
+
+    sizes.push(("TASK_CALL_STATES", ARGS.max_tasks));
+    sizes.push(("OPEN_CALLS", ARGS.max_tasks.saturating_mul(OPEN_CALLS_PER_TASK)));
+    sizes.push(("SKB_COPY_SOURCES", ARGS.max_tasks));
+    sizes.push(("STACK_TRACES", ARGS.max_stacks));
+    sizes.push(("REGISTERED_FUNCTIONS", ARGS.max_registered_functions));
+    // The size of a ring buffer is in bytes, a power of two multiple of the page size, a single page when nothing is streamed
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u32;
+    sizes.push(("EVENTS", match ARGS.stream {
+        true => ARGS.stream_buffer.next_power_of_two() << 20,
+        false => page_size
+    }));
+    sizes.push(("SCOPE_PIDS", (ARGS.pids.len() as u32).max(1)));
+    sizes.push(("USDT_SPECS", (usdt_locations.len() as u32).max(1)));
+
+    if ARGS.histogram {
+        sizes.push(("HISTOGRAMS", histogram_slots(dynamic_functions)));
+        sizes.push(("HISTOGRAM_SLOTS", (dynamic_function_slots(dynamic_functions).len() as u32).max(1)));
+    }
+
+    sizes
+}
+
+pub fn ebpf_loader<'a>(sizes: &[(&'a str, u32)]) -> EbpfLoader<'a> {
+    let mut loader = EbpfLoader::new();
+
+    for (map, max_entries) in sizes {
+        loader.set_max_entries(map, *max_entries);
+    }
+
+    loader
+}
+
+/// Prints an estimate of the kernel memory taken by the maps of the eBPF object once resized, maps being preallocated
+pub fn print_memory_estimate(ebpf_object: &[u8], sizes: &[(&str, u32)], nr_cpus: usize) -> anyhow::Result<()> {
+    let object = Object::parse(ebpf_object)?;
+
+    let mut estimates = object
+        .maps
+        .iter()
+        .map(|(name, map)| {
+            let max_entries = sizes
+                .iter()
+                .find(|(map, _)| map == name)
+                .map_or(map.max_entries(), |(_, max_entries)| *max_entries);
+
+            (name.as_str(), max_entries, map_memory(map.map_type(), map.key_size(), map.value_size(), max_entries, nr_cpus as u64))
+        })
+        .collect::<Vec<(&str, u32, u64)>>();
+
+    estimates.sort_by_key(|(_, _, memory)| u64::MAX - memory);
+
+    println!("================================================ Map memory estimate ================================================");
+    println!("{: <36} {: >14} {: >14}", "Map", "Max entries", "Memory");
+
+    for (name, max_entries, memory) in &estimates {
+        println!("{: <36} {: >14} {: >11} MiB", name, max_entries, memory >> 20);
+    }
+
+    let total = estimates.iter().map(|(_, _, memory)| memory).sum::<u64>();
+    println!("{: <36} {: >14} {: >11} MiB", "Total", "", total >> 20);
+    println!();
+
+    Ok(())
+}
+
+/// Approximation of the kernel allocation of a map, following the layout of the preallocated map types
+fn map_memory(map_type: u32, key_size: u32, value_size: u32, max_entries: u32, nr_cpus: u64) -> u64 {
+    let key_size = (key_size as u64).next_multiple_of(8);
+    let value_size = (value_size as u64).next_multiple_of(8);
+    let max_entries = max_entries as u64;
+    let buckets = max_entries.next_power_of_two() * 8;
+
+    match map_type {
+        map_type if map_type == bpf_map_type::BPF_MAP_TYPE_HASH as u32 || map_type == bpf_map_type::BPF_MAP_TYPE_LRU_HASH as u32 => buckets + max_entries * (HASH_ELEMENT_OVERHEAD + key_size + value_size),
+        // The element holds a pointer to the per-CPU values
+        map_type if map_type == bpf_map_type::BPF_MAP_TYPE_PERCPU_HASH as u32 => buckets + max_entries * (HASH_ELEMENT_OVERHEAD + key_size + 8 + value_size * nr_cpus),
+        map_type if map_type == bpf_map_type::BPF_MAP_TYPE_ARRAY as u32 => max_entries * value_size,
+        map_type if map_type == bpf_map_type::BPF_MAP_TYPE_PERCPU_ARRAY as u32 => max_entries * value_size * nr_cpus,
+        map_type if map_type == bpf_map_type::BPF_MAP_TYPE_QUEUE as u32 => (max_entries + 1) * value_size,
+        map_type if map_type == bpf_map_type::BPF_MAP_TYPE_STACK_TRACE as u32 => buckets + max_entries * (STACK_BUCKET_OVERHEAD + value_size),
+        // The size of a ring buffer is given in bytes
+        map_type if map_type == bpf_map_type::BPF_MAP_TYPE_RINGBUF as u32 => max_entries,
+        _ => max_entries * (key_size + value_size)
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, HashMap};
+use aya::maps::{MapData, StackTraceMap};
+use log::warn;
+use serde::Serialize;
+use aya_network_deep_profiling_common::{SkbEvent, SkbEventKind};
+use crate::symbols::{is_kernel_function, kernel_function_targets, kernel_symbol_name, logical_name};
+use crate::utils::{mean, median};
+
+/// Functions returning a new `sk_buff`, some of them calling the others
+pub const SKB_ALLOC_FUNCTIONS: [&str; 7] = ["__alloc_skb", "__netdev_alloc_skb", "__napi_alloc_skb", "napi_alloc_skb", "__build_skb", "build_skb", "napi_build_skb"];
+/// Functions returning a clone or a copy of the `sk_buff` given as first argument
+pub const SKB_COPY_FUNCTIONS: [&str; 4] = ["skb_clone", "skb_copy", "__pskb_copy_fclone", "skb_copy_expand"];
+/// Frees an `sk_buff` directly, and after the `consume_skb` and `kfree_skb` tracepoints
+pub const SKB_FREE_FUNCTIONS: [&str; 1] = ["__kfree_skb"];
+
+/// Wrappers skipped in the stacks to find the code allocating or releasing an `sk_buff`
+const SKB_WRAPPERS: [&str; 12] = [
+    "alloc_skb", "__alloc_skb_fclone", "netdev_alloc_skb", "napi_alloc_skb", "__napi_build_skb", "consume_skb", "napi_consume_skb",
+    "kfree_skb_reason", "sk_skb_reason_drop", "kfree_skb_list_reason", "dev_kfree_skb_any_reason", "dev_kfree_skb_irq_reason",
+];
+
+/// Targets of the probes following the sk_buffs, the renamed and inlined functions of the running kernel being skipped
+pub fn skb_probe_targets(functions: &[&str]) -> Vec<String> {
+    let mut targets = Vec::new();
+
+    for function in functions {
+        if is_kernel_function(function) {
+            targets.extend(kernel_function_targets(function));
+        }
+    }
+
+    if targets.is_empty() {
+        warn!("None of {functions:?} can be probed, the sk_buff lifetimes are partial");
+    }
+
+    targets
+}
+
+/// Lifetime of an `sk_buff` from its allocation or copy to its release, `None` when it was not released before the end of the capture
+#[derive(Debug, Clone, Serialize)]
+pub struct SkbLifetimeRow {
+    pub skb: u64,
+    pub allocator: String,
+    pub allocation_site: String,
+    pub allocated_at: u64,
+    /// sk_buff it is a clone or a copy of
+    pub parent: Option<u64>,
+    /// Clones and copies made of it
+    pub copies: u32,
+    pub release: Option<String>,
+    pub release_site: Option<String>,
+    pub released_at: Option<u64>,
+    pub lifetime: Option<u64>,
+}
+
+/// Lifetimes of the sk_buffs returned by an allocator or copy function, in nanoseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct SkbLifetimeStatsRow {
+    pub allocator: String,
+    pub count: u64,
+    pub outstanding: u64,
+    pub mean: u64,
+    pub median: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkbSiteRow {
+    pub site: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkbLifetimes {
+    pub lifetimes: Vec<SkbLifetimeRow>,
+    pub allocators: Vec<SkbLifetimeStatsRow>,
+    pub allocation_sites: Vec<SkbSiteRow>,
+    pub release_sites: Vec<SkbSiteRow>,
+    /// Allocation sites of the sk_buffs not released at the end of the capture
+    pub outstanding_sites: Vec<SkbSiteRow>,
+}
+
+/// First function of a stack that is not an sk_buff helper or a probe trampoline
+fn stack_site(stack_traces: &StackTraceMap<MapData>, stack_id: i64, sites: &mut HashMap<i64, String>) -> String {
+    if stack_id < 0 {
+        return String::from("Unknown");
+    }
+
+    sites
+        .entry(stack_id)
+        .or_insert_with(|| {
+            let Ok(stack_trace) = stack_traces.get(&(stack_id as u32), 0) else {
+                return String::from("Unknown");
+            };
+
+            stack_trace
+                .frames()
+                .iter()
+                .map(|frame| logical_name(&kernel_symbol_name(frame.ip)).to_string())
+                .find(|function| {
+                    !SKB_ALLOC_FUNCTIONS.contains(&function.as_str())
+                        && !SKB_COPY_FUNCTIONS.contains(&function.as_str())
+                        && !SKB_FREE_FUNCTIONS.contains(&function.as_str())
+                        && !SKB_WRAPPERS.contains(&function.as_str())
+                        && !function.contains("trampoline")
+                        && !function.contains("rethook")
+                        && !function.contains("kprobe")
+                        && !function.starts_with("perf_trace_")
+                        && !function.starts_with("trace_event_raw_event_")
+                        && !function.starts_with("bpf_")
+                })
+                .unwrap_or_else(|| String::from("Unknown"))
+        })
+        .clone()
+}
+
+fn site_rows(counts: HashMap<String, u64>) -> Vec<SkbSiteRow> {
+    let mut rows = counts.into_iter().map(|(site, count)| SkbSiteRow { site, count }).collect::<Vec<SkbSiteRow>>();
+    rows.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.site.cmp(&b.site)));
+    rows
+}
+
+fn release_name(kind: SkbEventKind) -> &'static str {
+    match kind {
+        SkbEventKind::Consumed => "consume_skb",
+        SkbEventKind::Dropped => "kfree_skb",
+        _ => "__kfree_skb",
+    }
+}
+
+/// Pairs the allocation or copy of each `sk_buff` with its release by address, a lifetime ending when the sk_buff is released
+/// since its address is then reused. The events must be ordered by time.
+pub fn process_skb_lifetimes(events: Vec<(u64, SkbEvent)>, stack_traces: &StackTraceMap<MapData>, initial_time: u64) -> SkbLifetimes {
+    let mut sites: HashMap<i64, String> = HashMap::new();
+    let mut open: HashMap<u64, SkbLifetimeRow> = HashMap::new();
+    let mut lifetimes: Vec<SkbLifetimeRow> = Vec::new();
+    let mut unknown_releases = 0;
+
+    for (time, event) in events {
+        let time = time.saturating_sub(initial_time);
+
+        match event.kind {
+            SkbEventKind::Allocated | SkbEventKind::Cloned => {
+                let allocator = logical_name(&kernel_symbol_name(event.function)).to_string();
+                let allocation_site = stack_site(stack_traces, event.stack_id, &mut sites);
+
+                // Nested allocators return the same sk_buff, the outermost one returning last names the allocation.
+                // The copies allocate through the probed allocators too, the copy returning last turns the allocation into a copy.
+                let is_nested = |lifetime: &&mut SkbLifetimeRow| lifetime.parent.is_none() && lifetime.copies == 0;
+
+                // The parent is 0 for the allocations
+                if let Some(parent) = open.get_mut(&event.parent) {
+                    parent.copies += 1;
+                }
+
+                if let Some(lifetime) = open.get_mut(&event.skb).filter(is_nested) {
+                    lifetime.allocator = allocator;
+                    lifetime.allocation_site = allocation_site;
+                    lifetime.parent = (event.parent != 0).then_some(event.parent);
+                    continue;
+                }
+
+                // The release of the previous sk_buff at this address was missed, it is reported as not released
+                if let Some(previous) = open.insert(event.skb, SkbLifetimeRow {
+                    skb: event.skb,
+                    allocator,
+                    allocation_site,
+                    allocated_at: time,
+                    parent: (event.parent != 0).then_some(event.parent),
+                    copies: 0,
+                    release: None,
+                    release_site: None,
+                    released_at: None,
+                    lifetime: None,
+                }) {
+                    lifetimes.push(previous);
+                }
+            }
+            SkbEventKind::Consumed | SkbEventKind::Dropped | SkbEventKind::Freed => {
+                // Freed after a consume_skb or kfree_skb already seen, or allocated before the capture
+                let Some(mut lifetime) = open.remove(&event.skb) else {
+                    if event.kind != SkbEventKind::Freed {
+                        unknown_releases += 1;
+                    }
+                    continue;
+                };
+
+                lifetime.release = Some(release_name(event.kind).to_string());
+                lifetime.release_site = Some(stack_site(stack_traces, event.stack_id, &mut sites));
+                lifetime.released_at = Some(time);
+                lifetime.lifetime = Some(time.saturating_sub(lifetime.allocated_at));
+                lifetimes.push(lifetime);
+            }
+        }
+    }
+
+    let outstanding = open.len();
+    lifetimes.extend(open.into_values());
+    lifetimes.sort_by_key(|lifetime| lifetime.allocated_at);
+
+    let mut durations: BTreeMap<&str, (Vec<u64>, u64)> = BTreeMap::new();
+    let mut allocation_sites: HashMap<String, u64> = HashMap::new();
+    let mut release_sites: HashMap<String, u64> = HashMap::new();
+    let mut outstanding_sites: HashMap<String, u64> = HashMap::new();
+
+    for lifetime in &lifetimes {
+        let (allocator_durations, allocator_outstanding) = durations.entry(&lifetime.allocator).or_default();
+        *allocation_sites.entry(lifetime.allocation_site.clone()).or_default() += 1;
+
+        match (lifetime.lifetime, &lifetime.release_site) {
+            (Some(duration), Some(release_site)) => {
+                allocator_durations.push(duration);
+                *release_sites.entry(release_site.clone()).or_default() += 1;
+            }
+            _ => {
+                *allocator_outstanding += 1;
+                *outstanding_sites.entry(lifetime.allocation_site.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    let allocators = durations
+        .into_iter()
+        .map(|(allocator, (mut durations, outstanding))| {
+            durations.sort_unstable();
+
+            let (mean, median, p99, max) = match durations.last() {
+                Some(max) => (mean(&durations), median(&durations), durations[(durations.len() - 1) * 99 / 100], *max),
+                None => (0, 0, 0, 0)
+            };
+
+            SkbLifetimeStatsRow {
+                allocator: allocator.to_string(),
+                count: durations.len() as u64 + outstanding,
+                outstanding,
+                mean,
+                median,
+                p99,
+                max,
+            }
+        })
+        .collect::<Vec<SkbLifetimeStatsRow>>();
+
+    let allocation_sites = site_rows(allocation_sites);
+    let release_sites = site_rows(release_sites);
+    let outstanding_sites = site_rows(outstanding_sites);
+
+    println!("================================================ sk_buff Lifetimes ================================================");
+    println!("{: <25} {: >10} {: >12} {: >15} {: >15} {: >15} {: >15}", "Allocator", "Count", "Outstanding", "Mean", "Median", "p99", "Max");
+    println!("-------------------------------------------------------------------------------------------------------------------");
+
+    for row in &allocators {
+        println!(
+            "{: <25} {: >10} {: >12} {: >12} ns {: >12} ns {: >12} ns {: >12} ns",
+            row.allocator, row.count, row.outstanding, row.mean, row.median, row.p99, row.max
+        );
+    }
+
+    println!();
+    println!("{: <45} {: >10}   {: <45} {: >10}", "Allocation site", "sk_buffs", "Release site", "sk_buffs");
+
+    for index in 0..allocation_sites.len().max(release_sites.len()).min(20) {
+        let column = |rows: &[SkbSiteRow]| match rows.get(index) {
+            Some(row) => format!("{: <45} {: >10}", row.site, row.count),
+            None => format!("{: <56}", "")
+        };
+
+        println!("{}   {}", column(&allocation_sites), column(&release_sites));
+    }
+
+    println!();
+    println!("{outstanding} sk_buffs were not released before the end of the capture");
+
+    for row in outstanding_sites.iter().take(10) {
+        println!("  {: <45} {: >10}", row.site, row.count);
+    }
+
+    if unknown_releases > 0 {
+        println!("{unknown_releases} sk_buffs released were allocated before the capture");
+    }
+
+    SkbLifetimes {
+        lifetimes,
+        allocators,
+        allocation_sites,
+        release_sites,
+        outstanding_sites,
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+use aya::maps::{MapData, RingBuf};
+use log::info;
+use serde::Serialize;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::{self, JoinHandle};
+use aya_network_deep_profiling_common::{RecordKind, StreamRecord, ALLOC_VARIANTS, KERNEL_FUNCTION_VARIANTS};
+use crate::symbols::{kernel_symbol_name, logical_name};
+use crate::time::program_name;
+
+/// Stream file written during the run with `--stream`, and what is needed to read it
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamSummary {
+    pub path: PathBuf,
+    pub records: u64,
+    pub record_size: usize,
+    /// Names of the functions of the kernel, alloc and user records, by id
+    pub kernel_functions: Vec<String>,
+    pub alloc_functions: Vec<String>,
+    pub user_functions: Vec<String>,
+    /// Names of the functions of the dynamic records, by address
+    pub dynamic_functions: BTreeMap<u64, String>,
+}
+
+/// Consumes the ring buffer until `stop` is notified, appending each `StreamRecord` to the file at `path`.
+/// The records are written by a blocking task, so that the file I/O does not stall the runtime.
+pub fn spawn_stream_consumer(ring_buf: RingBuf<MapData>, path: PathBuf, stop: Arc<Notify>) -> anyhow::Result<JoinHandle<anyhow::Result<StreamSummary>>> {
+    let mut async_fd = AsyncFd::new(ring_buf)?;
+    let mut writer = BufWriter::new(File::create(&path)?);
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Vec<u8>>();
+
+    info!("Streaming function calls to {}", path.display());
+
+    let file_writer = task::spawn_blocking(move || -> anyhow::Result<()> {
+        while let Some(records) = receiver.blocking_recv() {
+            writer.write_all(&records)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    });
+
+    Ok(tokio::spawn(async move {
+        let mut records = 0;
+        let mut addresses = BTreeSet::new();
+
+        loop {
+            tokio::select! {
+                guard = async_fd.readable_mut() => {
+                    let mut guard = guard?;
+                    records += drain(guard.get_inner_mut(), &sender, &mut addresses)?;
+                    guard.clear_ready();
+                }
+                _ = stop.notified() => {
+                    // Records sent between the last wake up and the detach of the programs
+                    records += drain(async_fd.get_mut(), &sender, &mut addresses)?;
+                    break;
+                }
+            }
+        }
+
+        // Closing the channel ends the writer once it has written the last records
+        drop(sender);
+        file_writer.await??;
+
+        let dynamic_functions = addresses
+            .into_iter()
+            .map(|address| (address, logical_name(&kernel_symbol_name(address)).to_string()))
+            .collect();
+
+        Ok(StreamSummary {
+            path,
+            records,
+            record_size: mem::size_of::<StreamRecord>(),
+            kernel_functions: KERNEL_FUNCTION_VARIANTS.into_iter().map(program_name).collect(),
+            alloc_functions: ALLOC_VARIANTS.into_iter().map(program_name).collect(),
+            user_functions: Vec::new(),
+            dynamic_functions,
+        })
+    }))
+}
+
+/// Sends the records available in the ring buffer to the writer, in a single buffer
+fn drain(ring_buf: &mut RingBuf<MapData>, sender: &mpsc::UnboundedSender<Vec<u8>>, addresses: &mut BTreeSet<u64>) -> anyhow::Result<u64> {
+    let mut records = 0;
+    let mut buffer = Vec::new();
+
+    while let Some(item) = ring_buf.next() {
+        if item.len() < mem::size_of::<StreamRecord>() {
+            continue;
+        }
+
+        let record = unsafe { (item.as_ptr() as *const StreamRecord).read_unaligned() };
+
+        if record.kind == RecordKind::Dynamic {
+            addresses.insert(record.id);
+        }
+
+        buffer.extend_from_slice(&item[..mem::size_of::<StreamRecord>()]);
+        records += 1;
+    }
+
+    if !buffer.is_empty() {
+        sender.send(buffer).map_err(|_| anyhow::anyhow!("The stream file writer stopped"))?;
+    }
+
+    Ok(records)
+}
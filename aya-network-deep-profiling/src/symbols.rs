@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use anyhow::{anyhow, Context};
+use aya::util::kernel_symbols;
+use log::{info, warn};
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
+use aya_network_deep_profiling_common::KERNEL_FUNCTIONS;
+use crate::ARGS;
+
+const AVAILABLE_FILTER_FUNCTIONS: [&str; 2] = [
+    "/sys/kernel/tracing/available_filter_functions",
+    "/sys/kernel/debug/tracing/available_filter_functions",
+];
+
+const KPROBES_BLACKLIST: &str = "/sys/kernel/debug/kprobes/blacklist";
+
+/// Suffixes appended by the compiler to the clones of a function, e.g. `ip_fragment.constprop.0` or `ip_rcv_finish_core.isra.0`
+const CLONE_SUFFIXES: [&str; 5] = ["constprop", "isra", "part", "cold", "lto_priv"];
+
+static KERNEL_SYMBOLS: OnceCell<BTreeMap<u64, String>> = OnceCell::new();
+
+/// Reads the kernel symbols from `/proc/kallsyms`, before anything looks a kernel function up
+pub fn load_kernel_symbols() -> anyhow::Result<()> {
+    let symbols = kernel_symbols().context("Could not read the kernel symbols from /proc/kallsyms")?;
+    let _ = KERNEL_SYMBOLS.set(symbols);
+    Ok(())
+}
+
+/// Kernel symbols by address, loaded at startup by `load_kernel_symbols`
+pub fn symbol_table() -> &'static BTreeMap<u64, String> {
+    KERNEL_SYMBOLS.get().expect("The kernel symbols are loaded at startup")
+}
+
+/// Kernel symbols grouped by logical function name
+static KERNEL_SYMBOL_CLONES: Lazy<HashMap<String, Vec<String>>> = Lazy::new(|| {
+    let mut clones: HashMap<String, Vec<String>> = HashMap::new();
+
+    for symbol in symbol_table().values() {
+        let symbols = clones.entry(logical_name(symbol).to_string()).or_default();
+
+        if !symbols.contains(symbol) {
+            symbols.push(symbol.clone());
+        }
+    }
+
+    clones
+});
+
+/// Function name without the compiler clone suffixes, `ip_fragment.constprop.0` becomes `ip_fragment`
+pub fn logical_name(symbol: &str) -> &str {
+    match symbol.split_once('.') {
+        Some((name, suffix)) => {
+            let mut parts = suffix.split('.');
+            let is_clone = parts.next().is_some_and(|part| CLONE_SUFFIXES.contains(&part))
+                && parts.all(|part| CLONE_SUFFIXES.contains(&part) || part.parse::<u32>().is_ok());
+
+            match is_clone {
+                true => name,
+                false => symbol
+            }
+        }
+        None => symbol
+    }
+}
+
+/// Kernel symbols to attach to for a function.
+/// A logical name resolves to the function itself and all of its clones present in kallsyms,
+/// except `.cold` parts which are jumped to rather than called, so a return probe on them would corrupt the stack,
+/// and `.part` clones when the function itself exists, since it calls them and each call would be recorded twice.
+/// A name already carrying a suffix only resolves to itself.
+pub fn kernel_function_targets(function: &str) -> Vec<String> {
+    if logical_name(function) != function {
+        return vec![function.to_string()];
+    }
+
+    match KERNEL_SYMBOL_CLONES.get(function) {
+        Some(symbols) => {
+            let has_function = symbols.iter().any(|symbol| symbol == function);
+
+            symbols
+                .iter()
+                .filter(|symbol| !symbol.split('.').any(|part| part == "cold" || (has_function && part == "part")))
+                .cloned()
+                .collect()
+        }
+        None => vec![function.to_string()]
+    }
+}
+
+/// Whether a function, given by its logical name, is in kallsyms
+pub fn is_kernel_function(function: &str) -> bool {
+    KERNEL_SYMBOL_CLONES.contains_key(function)
+}
+
+/// Name of the kernel symbol containing `address`
+pub fn kernel_symbol_name(address: u64) -> String {
+    match symbol_table().range(..=address).next_back() {
+        Some((_, name)) => name.clone(),
+        None => format!("{address:#X}")
+    }
+}
+
+/// Functions that can be traced, as listed by ftrace, `None` if tracefs is not available
+pub static TRACEABLE_FUNCTIONS: Lazy<Option<BTreeSet<String>>> = Lazy::new(|| {
+    for path in AVAILABLE_FILTER_FUNCTIONS {
+        if let Ok(content) = fs::read_to_string(path) {
+            // Lines are "function" or "function [module]"
+            let functions = content
+                .lines()
+                .filter_map(|line| line.split_whitespace().next())
+                .map(String::from)
+                .collect();
+
+            return Some(functions);
+        }
+    }
+
+    None
+});
+
+/// Functions on which kprobes are refused by the kernel
+fn kprobes_blacklist() -> HashSet<String> {
+    match fs::read_to_string(KPROBES_BLACKLIST) {
+        // Lines are "0xstart-0xend\tfunction"
+        Ok(content) => content
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(1))
+            .map(String::from)
+            .collect(),
+        Err(error) => {
+            warn!("Could not read {KPROBES_BLACKLIST}: {error}");
+            HashSet::new()
+        }
+    }
+}
+
+/// A pattern is either a regex between slashes, or a glob using `*` and `?`
+fn pattern_regex(pattern: &str) -> anyhow::Result<Regex> {
+    let regex = match pattern.strip_prefix('/').and_then(|pattern| pattern.strip_suffix('/')) {
+        Some(regex) => regex.to_string(),
+        None => format!("^{}$", regex::escape(pattern).replace(r"\*", ".*").replace(r"\?", "."))
+    };
+
+    Ok(Regex::new(&regex)?)
+}
+
+fn expand_kprobe_patterns(patterns: &[String]) -> anyhow::Result<Vec<String>> {
+    let traceable = TRACEABLE_FUNCTIONS
+        .as_ref()
+        .ok_or_else(|| anyhow!("Could not read available_filter_functions, is tracefs mounted?"))?;
+    let mut functions = Vec::new();
+
+    for pattern in patterns {
+        let regex = pattern_regex(pattern).with_context(|| format!("Invalid --kprobe pattern {pattern}"))?;
+        let matches = traceable
+            .iter()
+            .filter(|function| regex.is_match(function))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        info!("Pattern {pattern} matched {} functions", matches.len());
+        functions.extend(matches);
+    }
+
+    Ok(functions)
+}
+
+/// Functions given with `--functions`, `--functions-file` and `--kprobe` that can be probed and are not already profiled.
+/// Logical names are replaced by their clones, see `kernel_function_targets`.
+pub fn resolve_dynamic_functions() -> anyhow::Result<Vec<String>> {
+    let mut requested = ARGS.functions.clone();
+
+    if let Some(path) = &ARGS.functions_file {
+        let content = fs::read_to_string(path).with_context(|| format!("Could not read the functions file {}", path.display()))?;
+
+        for line in content.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            requested.push(line.to_string());
+        }
+    }
+
+    if !ARGS.kprobes.is_empty() {
+        requested.extend(expand_kprobe_patterns(&ARGS.kprobes)?);
+    }
+
+    let requested = requested
+        .iter()
+        .flat_map(|function| kernel_function_targets(function))
+        .collect::<Vec<String>>();
+
+    if requested.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let blacklist = kprobes_blacklist();
+    let symbols = symbol_table().values().collect::<HashSet<&String>>();
+    let mut functions: Vec<String> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+
+    for function in requested {
+        if !seen.insert(function.clone()) {
+            continue;
+        }
+
+        if KERNEL_FUNCTIONS.iter().any(|name| *name == logical_name(&function)) {
+            warn!("Function {function} is already profiled, skipping");
+            continue;
+        }
+
+        if blacklist.contains(&function) {
+            warn!("Function {function} is blacklisted for kprobes, skipping");
+            continue;
+        }
+
+        if !symbols.contains(&function) {
+            warn!("Function {function} not found in kallsyms, skipping");
+            continue;
+        }
+
+        functions.push(function);
+    }
+
+    Ok(functions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_patterns() {
+        let regex = pattern_regex("tcp_v4_*").unwrap();
+
+        assert!(regex.is_match("tcp_v4_rcv"));
+        assert!(!regex.is_match("__tcp_v4_rcv"));
+        assert!(pattern_regex("ip?_rcv").unwrap().is_match("ip6_rcv"));
+        assert!(!pattern_regex("ip.rcv").unwrap().is_match("ip_rcv"));
+    }
+
+    #[test]
+    fn regex_patterns() {
+        let regex = pattern_regex("/^(tcp|udp)_rcv/").unwrap();
+
+        assert!(regex.is_match("udp_rcv_established"));
+        assert!(!regex.is_match("ip_rcv"));
+        assert!(pattern_regex("/(/").is_err());
+    }
+}
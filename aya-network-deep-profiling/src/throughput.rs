@@ -3,9 +3,10 @@ use getifaddrs::Interface;
 use serde::Serialize;
 use rayon::prelude::*;
 use aya_network_deep_profiling_common::{PacketDirection, ThroughputStat};
+use crate::flow::flow_name;
 
 #[derive(Serialize)]
-pub struct ThroughputRow(pub u64, pub u32, pub PacketDirection, pub String);
+pub struct ThroughputRow(pub u64, pub u32, pub PacketDirection, pub String, pub String);
 
 pub fn collect_queue(throughput_stats: &mut Queue<MapData, ThroughputStat>, initial_time: u64) -> Vec<ThroughputStat> {
     let mut throughput: Vec<ThroughputStat> = Vec::new();
@@ -38,6 +39,7 @@ pub fn process_throughput(throughput_stats: Vec<ThroughputStat>, interfaces: Vec
                 throughput_stat.packet_size,
                 throughput_stat.direction,
                 interface_name,
+                flow_name(&{ throughput_stat.flow }),
             )
         })
         .collect()
@@ -1,7 +1,8 @@
 use crate::utils::{mean, median, CPU_FREQUENCY};
 use crate::ARGS;
 use aya::maps::{MapData, PerCpuHashMap};
-use aya_network_deep_profiling_common::{FunctionCall, FunctionDirection, Program};
+use aya::Pod;
+use aya_network_deep_profiling_common::{Event, ExecutionContext, FunctionSpan, Program};
 use rayon::prelude::*;
 use serde::Serialize;
 use std::collections::HashMap;
@@ -15,76 +16,77 @@ pub struct ExecutionTimeRow {
     pub inner_duration: u64,
     pub depth: u32,
     pub cpuid: u32,
+    pub context: ExecutionContext,
+    /// Used to find the calls nested in a task context call, which may have migrated between CPUs
+    pub tid: u32,
+    pub tgid: u32,
     //pub l1d_cache_misses: u64,
 }
 
 unsafe impl Send for ExecutionTimeRow {}
 unsafe impl Sync for ExecutionTimeRow {}
 
-pub fn filter_times<F: Program + 'static>(times: PerCpuHashMap<MapData, u64, FunctionCall<F>>, initial_time: u64) -> Vec<(u64, FunctionCall<F>)> {
-    let mut filtered_times: Vec<(u64, FunctionCall<F>)> = vec![];
+/// Name of a compile-time profiled function, `_p_` standing for the `.` of an explicit compiler clone suffix
+pub fn program_name<F: Program>(function: F) -> String {
+    function.to_str().replace("_p_", ".")
+}
 
-    for (time, function_calls) in times.iter().filter_map(|t| t.ok()) {
-        if time < initial_time {
-            continue;
-        }
+/// Events of a per-CPU event map recorded after `initial_time`, ordered by time.
+/// Events of the same nanosecond keep the order in which their CPU recorded them.
+pub fn filter_events<T: Pod>(events: PerCpuHashMap<MapData, u64, Event<T>>, initial_time: u64) -> Vec<(u64, T)> {
+    let mut filtered_events: Vec<(u64, usize, u64, T)> = vec![];
+
+    for (sequence, cpu_events) in events.iter().filter_map(|e| e.ok()) {
+        for (cpu, event) in cpu_events.iter().enumerate() {
+            // Zeroed slot of a CPU that did not record an event with this sequence number
+            if !event.valid || event.timestamp < initial_time {
+                continue;
+            }
 
-        for function_call in function_calls.iter() {
-            filtered_times.push((time, *function_call));
+            filtered_events.push((event.timestamp, cpu, sequence, event.data));
         }
     }
 
-    filtered_times.sort_by(|(a, _), (b, _)| a.cmp(b));
+    filtered_events.sort_by_key(|(time, cpu, sequence, _)| (*time, *cpu, *sequence));
 
-    filtered_times
+    filtered_events
+        .into_iter()
+        .map(|(time, _, _, data)| (time, data))
+        .collect()
 }
 
-pub fn handle_execution_times<F: Program>(times: Vec<(u64, FunctionCall<F>)>, /*cache_misses: &maps::HashMap<MapData, u64, u64>,*/ initial_time: u64) -> Vec<ExecutionTimeRow> {
+pub fn handle_execution_times<F: Copy>(spans: Vec<(u64, FunctionSpan<F>)>, function_name: impl Fn(F) -> String, /*cache_misses: &maps::HashMap<MapData, u64, u64>,*/ initial_time: u64) -> Vec<ExecutionTimeRow> {
     let mut arranged_times: HashMap<String, Vec<u64>> = HashMap::new();
     let mut execution_times: Vec<ExecutionTimeRow> = Vec::new();
 
-    for (time, function_call) in times {
-        let function = function_call.function.to_str().replace("_p_", ".");
-        let direction = function_call.direction;
-        let depth = function_call.depth;
-        let cpuid = function_call.cpuid;
-
-        match arranged_times.get_mut(&function) {
-            Some(arranged_time) => match direction {
-                FunctionDirection::Entry => arranged_time.push(time),
-                FunctionDirection::Exit => {
-                    let len = arranged_time.len() - 1;
-                    let start_time = arranged_time[len];
-                    let duration = time - arranged_time[len];
-
-                    /*
-                    let mut l1d_cache_misses = 0;
-                    for (miss_time, miss_count) in cache_misses.iter().filter_map(|c| c.ok()) {
-                        if miss_time >= start_time && miss_time <= time {
-                            l1d_cache_misses += miss_count;
-                        }
-                    }*/
-
-                    execution_times.push(ExecutionTimeRow {
-                        function_name: function.to_string(),
-                        start_time: start_time.saturating_sub(initial_time),
-                        end_time: time.saturating_sub(initial_time),
-                        duration,
-                        inner_duration: duration,
-                        depth,
-                        cpuid,
-                        //l1d_cache_misses
-                    });
-                    arranged_time[len] = duration;
-                }
+    for (_, span) in spans {
+        let function = function_name(span.function);
+        let start_time = span.start;
+        let end_time = span.end;
+        let duration = end_time.saturating_sub(start_time);
+
+        /*
+        let mut l1d_cache_misses = 0;
+        for (miss_time, miss_count) in cache_misses.iter().filter_map(|c| c.ok()) {
+            if miss_time >= start_time && miss_time <= end_time {
+                l1d_cache_misses += miss_count;
             }
-            None => match direction {
-                FunctionDirection::Entry => {
-                    arranged_times.insert(function, vec![time]);
-                },
-                FunctionDirection::Exit => {}
-            }
-        };
+        }*/
+
+        execution_times.push(ExecutionTimeRow {
+            function_name: function.clone(),
+            start_time: start_time.saturating_sub(initial_time),
+            end_time: end_time.saturating_sub(initial_time),
+            duration,
+            inner_duration: duration,
+            depth: span.depth,
+            cpuid: span.cpuid,
+            context: span.context,
+            tid: span.tid,
+            tgid: span.tgid,
+            //l1d_cache_misses
+        });
+        arranged_times.entry(function).or_default().push(duration);
     }
 
     arranged_times.par_iter_mut().for_each(|(_, a)| a.retain(|e| *e < ARGS.timeout));
@@ -96,6 +98,9 @@ pub fn handle_execution_times<F: Program>(times: Vec<(u64, FunctionCall<F>)>, /*
         let parent_start = parent.start_time + initial_time;
         let parent_end = parent.end_time + initial_time;
         let parent_depth = parent.depth;
+        let parent_cpuid = parent.cpuid;
+        let parent_tid = parent.tid;
+        let parent_context = parent.context;
 
             // Look for child calls (higher depth, within parent's time window)
             let child_duration_sum = execution_times
@@ -104,6 +109,11 @@ pub fn handle_execution_times<F: Program>(times: Vec<(u64, FunctionCall<F>)>, /*
                 .filter(|&(j, _)| i != j)
                 .filter(|(_, candidate)| {
                     candidate.depth > parent_depth
+                        && candidate.context == parent_context
+                        && match parent_context {
+                            ExecutionContext::Task => candidate.tid == parent_tid,
+                            _ => candidate.cpuid == parent_cpuid,
+                        }
                         && candidate.start_time + initial_time >= parent_start
                         && candidate.end_time + initial_time <= parent_end
                 })
@@ -0,0 +1,362 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use aya::Ebpf;
+use aya::maps::Array;
+use log::{info, warn};
+use serde::Serialize;
+use aya_network_deep_profiling_common::{ExecutionContext, SockLayout, SocketEvent, SocketEventKind};
+use crate::btf::KernelBtf;
+use crate::flow::flow_name;
+use crate::symbols::{is_kernel_function, kernel_function_targets, kernel_symbol_name};
+use crate::time::ExecutionTimeRow;
+use crate::utils::median;
+
+/// Kernel functions receiving from a socket in the context of the task, for TCP and UDP over IPv4 and IPv6
+pub const SOCKET_RECEIVE_FUNCTIONS: [&str; 3] = ["tcp_recvmsg", "udp_recvmsg", "udpv6_recvmsg"];
+
+/// Kernel functions sending to a socket in the context of the task, for TCP and UDP over IPv4 and IPv6
+pub const SOCKET_SEND_FUNCTIONS: [&str; 3] = ["tcp_sendmsg", "udp_sendmsg", "udpv6_sendmsg"];
+
+/// Targets of the socket receive or send probe, skipping the functions missing from the running kernel, e.g. without IPv6
+pub fn socket_call_targets(functions: &[&str]) -> Vec<String> {
+    let mut targets = Vec::new();
+
+    for function in functions {
+        if is_kernel_function(function) {
+            targets.extend(kernel_function_targets(function));
+        } else {
+            warn!("{function} can not be probed, its calls are not attributed to the connections");
+        }
+    }
+
+    targets
+}
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+const UNKNOWN_CONNECTION: &str = "Unknown";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub enum SpanKind {
+    User,
+    Kernel,
+    /// Kernel call delivering a packet to the socket of the request, whichever thread or CPU it ran on
+    Delivery,
+}
+
+/// Call made during a user-space call, in the timeline of that call
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineSpanRow {
+    pub function_name: String,
+    pub kind: SpanKind,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub duration: u64,
+    pub depth: u32,
+    pub context: ExecutionContext,
+    pub cpuid: u32,
+    pub tid: u32,
+}
+
+/// Call of a `--uprobe` function with the kernel work done by its thread until it returned, from the system calls it made
+/// and from the softirqs run on its behalf, and with `--connections` the delivery of the packets of its socket done on other threads or CPUs
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestTimelineRow {
+    pub function_name: String,
+    pub tgid: u32,
+    pub tid: u32,
+    /// First socket the thread received from or sent to during the call, 0 when none was seen
+    pub socket: u64,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub duration: u64,
+    /// Time spent in the outermost kernel calls, the softirqs being counted separately
+    pub kernel_time: u64,
+    pub softirq_time: u64,
+    /// Packets enqueued to the socket since the previous call using it
+    pub deliveries: u64,
+    /// Time of the outermost calls delivering these packets which did not run in the thread during the call
+    pub delivery_time: u64,
+    pub spans: Vec<TimelineSpanRow>,
+}
+
+/// Request timelines of the calls using a socket
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionTimelineRow {
+    /// Local address and port of the socket as source, remote ones as destination
+    pub connection: String,
+    pub socket: u64,
+    pub requests: Vec<RequestTimelineRow>,
+}
+
+/// Pushes the layout of `struct sock_common` read from the kernel BTF and enables the recording of the uses of the sockets
+pub fn apply_socket_layout(ebpf: &mut Ebpf) -> anyhow::Result<()> {
+    let btf = match KernelBtf::from_sys_fs() {
+        Ok(btf) => btf,
+        Err(e) => {
+            warn!("Could not read the kernel BTF, the request timelines are not grouped per connection: {e}");
+            return Ok(());
+        }
+    };
+
+    let member = |member: &str| btf.member_offset("sock_common", member);
+    let (Some(family), Some(num), Some(dport), Some(rcv_saddr), Some(daddr)) =
+        (member("skc_family"), member("skc_num"), member("skc_dport"), member("skc_rcv_saddr"), member("skc_daddr")) else {
+        warn!("Could not find the addresses of struct sock_common in the kernel BTF, the request timelines are not grouped per connection");
+        return Ok(());
+    };
+
+    let layout = SockLayout {
+        family,
+        num,
+        dport,
+        rcv_saddr,
+        daddr,
+        v6_rcv_saddr: member("skc_v6_rcv_saddr").unwrap_or_default(),
+        v6_daddr: member("skc_v6_daddr").unwrap_or_default(),
+    };
+
+    info!("Recording the uses of the sockets with {layout:?}");
+
+    let mut sock_layout: Array<_, SockLayout> = Array::try_from(ebpf.map_mut("SOCK_LAYOUT").unwrap())?;
+    sock_layout.set(0, layout, 0)?;
+
+    let mut socket_tracking: Array<_, u32> = Array::try_from(ebpf.map_mut("SOCKET_TRACKING").unwrap())?;
+    socket_tracking.set(0, 1, 0)?;
+
+    Ok(())
+}
+
+fn timeline_span(row: &ExecutionTimeRow, kind: SpanKind) -> TimelineSpanRow {
+    TimelineSpanRow {
+        function_name: row.function_name.clone(),
+        kind,
+        start_time: row.start_time,
+        end_time: row.end_time,
+        duration: row.duration,
+        depth: row.depth,
+        context: row.context,
+        cpuid: row.cpuid,
+        tid: row.tid,
+    }
+}
+
+/// Name of the connection of a socket, the protocol being told by the function probed
+fn connection_name(event: &SocketEvent) -> String {
+    let mut flow = event.flow;
+    let function = kernel_symbol_name(event.function);
+
+    flow.protocol = match function.trim_start_matches('_') {
+        function if function.starts_with("tcp") => IPPROTO_TCP,
+        function if function.starts_with("udp") => IPPROTO_UDP,
+        _ => 0,
+    };
+
+    flow_name(&flow)
+}
+
+/// Kernel calls enclosing a time, from the innermost to the outermost, in the calls of a thread or of a CPU ordered by start time
+fn enclosing_spans<'a>(spans: &[&'a ExecutionTimeRow], time: u64) -> Vec<&'a ExecutionTimeRow> {
+    let last = spans.partition_point(|span| span.start_time <= time);
+    let mut enclosing = Vec::new();
+
+    // The outermost calls do not overlap, so the calls started before an outermost one that ended before the time cannot enclose it
+    for span in spans[..last].iter().rev() {
+        if span.end_time >= time {
+            enclosing.push(*span);
+        }
+
+        if span.depth == 0 {
+            break;
+        }
+    }
+
+    enclosing
+}
+
+/// Nests the kernel calls of each thread under the user-space calls of that thread they happened in.
+/// Interrupts run on behalf of the interrupted thread, so the softirqs processing the packets of a sleeping thread are not nested in its calls:
+/// they are attached from the socket events, each call taking the packets delivered to its socket since the previous call using it.
+/// The calls are grouped per connection, the ones without a socket under `Unknown`.
+pub fn process_request_timelines(
+    user_spans: &[ExecutionTimeRow],
+    kernel_spans: &[&[ExecutionTimeRow]],
+    socket_events: Vec<(u64, SocketEvent)>,
+    initial_time: u64,
+) -> Vec<ConnectionTimelineRow> {
+    let mut threads: HashMap<u32, Vec<(&ExecutionTimeRow, SpanKind)>> = HashMap::new();
+
+    for row in user_spans {
+        threads.entry(row.tid).or_default().push((row, SpanKind::User));
+    }
+
+    // The calls of the threads that called a user-space function, and the calls of each context on each CPU to find the deliveries in
+    let mut thread_calls: HashMap<u32, Vec<&ExecutionTimeRow>> = HashMap::new();
+    let mut cpu_calls: HashMap<(u32, ExecutionContext), Vec<&ExecutionTimeRow>> = HashMap::new();
+
+    for row in kernel_spans.iter().flat_map(|spans| spans.iter()).filter(|row| row.context != ExecutionContext::Hardirq) {
+        if let Some(spans) = threads.get_mut(&row.tid) {
+            spans.push((row, SpanKind::Kernel));
+        }
+
+        match row.context {
+            ExecutionContext::Task => thread_calls.entry(row.tid).or_default().push(row),
+            context => cpu_calls.entry((row.cpuid, context)).or_default().push(row),
+        }
+    }
+
+    for spans in threads.values_mut() {
+        spans.sort_by_key(|(row, kind)| (row.start_time, *kind == SpanKind::Kernel, row.depth));
+    }
+
+    for spans in thread_calls.values_mut().chain(cpu_calls.values_mut()) {
+        spans.sort_by_key(|row| (row.start_time, row.depth));
+    }
+
+    // Receives and sends of each thread, and deliveries of each socket, ordered by time
+    let mut thread_sockets: HashMap<u32, Vec<(u64, &SocketEvent)>> = HashMap::new();
+    let mut deliveries: HashMap<u64, Vec<(u64, &SocketEvent)>> = HashMap::new();
+    let mut connections: HashMap<u64, String> = HashMap::new();
+
+    for (time, event) in &socket_events {
+        let time = time.saturating_sub(initial_time);
+
+        match event.kind {
+            SocketEventKind::Deliver => deliveries.entry(event.sock).or_default().push((time, event)),
+            _ => {
+                thread_sockets.entry(event.tid).or_default().push((time, event));
+                connections.entry(event.sock).or_insert_with(|| connection_name(event));
+            }
+        }
+    }
+
+    // Deliveries of each socket already attached to a previous call
+    let mut delivered: HashMap<u64, usize> = HashMap::new();
+    let mut rows: BTreeMap<(&str, u64), Vec<RequestTimelineRow>> = BTreeMap::new();
+
+    let mut user_spans = user_spans.iter().collect::<Vec<&ExecutionTimeRow>>();
+    user_spans.sort_by_key(|row| row.end_time);
+
+    for row in user_spans {
+        let thread_spans = &threads[&row.tid];
+        let first = thread_spans.partition_point(|(span, _)| span.start_time < row.start_time);
+
+        let socket = thread_sockets
+            .get(&row.tid)
+            .and_then(|events| events.iter().find(|(time, _)| *time >= row.start_time && *time <= row.end_time))
+            .map_or(0, |(_, event)| event.sock);
+
+        let mut timeline = RequestTimelineRow {
+            function_name: row.function_name.clone(),
+            tgid: row.tgid,
+            tid: row.tid,
+            socket,
+            start_time: row.start_time,
+            end_time: row.end_time,
+            duration: row.duration,
+            kernel_time: 0,
+            softirq_time: 0,
+            deliveries: 0,
+            delivery_time: 0,
+            spans: Vec::new(),
+        };
+
+        // Outermost kernel calls of each context, whose time is not counted yet
+        let mut outermost_end: HashMap<ExecutionContext, u64> = HashMap::new();
+        let mut nested: HashSet<*const ExecutionTimeRow> = HashSet::new();
+
+        for (span, kind) in thread_spans[first..].iter().take_while(|(span, _)| span.start_time <= row.end_time) {
+            if std::ptr::eq(*span, row) || span.end_time > row.end_time {
+                continue;
+            }
+
+            if *kind == SpanKind::Kernel && outermost_end.get(&span.context).is_none_or(|end| span.start_time >= *end) {
+                outermost_end.insert(span.context, span.end_time);
+
+                match span.context {
+                    ExecutionContext::Task => timeline.kernel_time += span.duration,
+                    _ => timeline.softirq_time += span.duration,
+                }
+            }
+
+            nested.insert(*span);
+            timeline.spans.push(timeline_span(span, *kind));
+        }
+
+        if let Some(socket_deliveries) = deliveries.get(&socket) {
+            let first = delivered.get(&socket).copied().unwrap_or_default();
+            let last = first + socket_deliveries[first..].partition_point(|(time, _)| *time <= row.end_time);
+            delivered.insert(socket, last);
+
+            for (time, event) in &socket_deliveries[first..last] {
+                timeline.deliveries += 1;
+
+                let calls = match event.context {
+                    ExecutionContext::Task => thread_calls.get(&event.tid),
+                    context => cpu_calls.get(&(event.cpuid, context)),
+                };
+
+                let enclosing = calls.map(|calls| enclosing_spans(calls, *time)).unwrap_or_default();
+
+                // Deliveries in the calls already nested, or in the same packet processing as a previous delivery, are not repeated
+                if let Some(outermost) = enclosing.last().filter(|outermost| !nested.contains(&(**outermost as *const ExecutionTimeRow))) {
+                    timeline.delivery_time += outermost.duration;
+                }
+
+                for span in enclosing.into_iter().rev() {
+                    if nested.insert(span) {
+                        timeline.spans.push(timeline_span(span, SpanKind::Delivery));
+                    }
+                }
+            }
+        }
+
+        timeline.spans.sort_by_key(|span| (span.start_time, span.kind == SpanKind::Kernel, span.depth));
+
+        let connection = connections.get(&socket).map_or(UNKNOWN_CONNECTION, String::as_str);
+        rows.entry((connection, socket)).or_default().push(timeline);
+    }
+
+    println!("=============================================== Request Timelines ===============================================");
+    println!(
+        "{: <60} {: <35} {: >8} {: >15} {: >15} {: >15} {: >12} {: >15}",
+        "Connection", "User function", "Calls", "Median time", "Median kernel", "Median softirq", "Deliveries", "Median delivery"
+    );
+    println!("-----------------------------------------------------------------------------------------------------------------");
+
+    for ((connection, _), requests) in &rows {
+        let mut summaries: BTreeMap<&str, Vec<&RequestTimelineRow>> = BTreeMap::new();
+
+        for request in requests {
+            summaries.entry(&request.function_name).or_default().push(request);
+        }
+
+        for (function, calls) in summaries {
+            let sorted = |time: fn(&RequestTimelineRow) -> u64| {
+                let mut times = calls.iter().map(|call| time(call)).collect::<Vec<u64>>();
+                times.sort_unstable();
+                times
+            };
+
+            let durations = sorted(|call| call.duration);
+            let kernel_times = sorted(|call| call.kernel_time);
+            let softirq_times = sorted(|call| call.softirq_time);
+            let delivery_times = sorted(|call| call.delivery_time);
+            let deliveries = calls.iter().map(|call| call.deliveries).sum::<u64>();
+
+            println!(
+                "{: <60} {: <35} {: >8} {: >12} ns {: >12} ns {: >12} ns {: >12} {: >12} ns",
+                connection, function, calls.len(), median(&durations), median(&kernel_times), median(&softirq_times), deliveries, median(&delivery_times)
+            );
+        }
+    }
+
+    rows
+        .into_iter()
+        .map(|((connection, socket), requests)| ConnectionTimelineRow {
+            connection: connection.to_string(),
+            socket,
+            requests,
+        })
+        .collect()
+}
@@ -0,0 +1,182 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use anyhow::anyhow;
+use aya::programs::ProgramError;
+use aya::sys::SyscallError;
+use aya_obj::generated::{bpf_attach_type, bpf_attr, bpf_cmd, perf_event_attr, PERF_FLAG_FD_CLOEXEC};
+use object::{Object, ObjectSection, ObjectSymbol};
+use crate::kprobe_multi::bpf;
+
+const UPROBE_TYPE: &str = "/sys/bus/event_source/devices/uprobe/type";
+const UPROBE_RETPROBE: &str = "/sys/bus/event_source/devices/uprobe/format/retprobe";
+const UPROBE_REF_CTR_OFFSET: &str = "/sys/bus/event_source/devices/uprobe/format/ref_ctr_offset";
+
+/// Generic entry and return programs, identifying the function hit by its attach cookie
+pub const USER_PROBE_PROGRAMS: [(&str, bool); 2] = [("probe_enter_user", false), ("probe_ret_user", true)];
+
+/// User-space function to attach to, resolved from a `--uprobe` target
+#[derive(Debug, Clone)]
+pub struct UserProbeTarget {
+    pub path: PathBuf,
+    pub symbol: String,
+    /// Offset of the function in the file
+    pub offset: u64,
+    /// Offset in the file of the reference counter incremented while the probe is attached, such as a USDT semaphore, 0 if none
+    pub ref_ctr_offset: u64,
+}
+
+impl UserProbeTarget {
+    pub fn name(&self) -> String {
+        format!("{}:{}", self.path.display(), self.symbol)
+    }
+}
+
+/// A uprobe and its bpf link, detached when dropped
+pub struct UserProbeLink {
+    pub program: &'static str,
+    pub target: String,
+    _perf_event_fd: OwnedFd,
+    _link_fd: OwnedFd,
+}
+
+/// Resolves a `/path/to/binary:symbol` or `library.so:symbol` target
+pub fn resolve_user_probe(target: &str) -> anyhow::Result<UserProbeTarget> {
+    let (path, symbol) = target
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow!("Invalid uprobe target {target}, expected PATH:SYMBOL"))?;
+
+    let path = resolve_library(path)?;
+    let offset = symbol_offset(&path, symbol)?;
+
+    Ok(UserProbeTarget {
+        path,
+        symbol: symbol.to_string(),
+        offset,
+        ref_ctr_offset: 0,
+    })
+}
+
+/// A path with no slash is a library name searched in the ld.so cache, `librte_eal.so` resolves to e.g. `/usr/lib/x86_64-linux-gnu/librte_eal.so.23`
+pub fn resolve_library(path: &str) -> anyhow::Result<PathBuf> {
+    if path.contains('/') {
+        return Ok(PathBuf::from(path));
+    }
+
+    let output = Command::new("ldconfig").arg("-p").output()?;
+    let libraries = String::from_utf8_lossy(&output.stdout);
+
+    // Lines are "\tlibrte_eal.so.23 (libc6,x86-64) => /usr/lib/x86_64-linux-gnu/librte_eal.so.23",
+    // the name being followed by a suffix so that `libc.so` matches `libc.so.6` and `libc` does not match `libcrypto.so.3`
+    libraries
+        .lines()
+        .filter_map(|line| line.trim().split_once(" => "))
+        .filter_map(|(library, library_path)| Some((library.split_whitespace().next()?, library_path)))
+        .find(|(library, _)| library.strip_prefix(path).is_some_and(|version| version.is_empty() || version.starts_with('.')))
+        .map(|(_, library_path)| PathBuf::from(library_path))
+        .ok_or_else(|| anyhow!("Library {path} not found in the ld.so cache"))
+}
+
+/// Offset in the file of a function, used by the kernel to place the uprobe
+fn symbol_offset(path: &Path, symbol_name: &str) -> anyhow::Result<u64> {
+    let data = fs::read(path)?;
+    let file = object::File::parse(&*data)?;
+
+    let symbol = file
+        .symbols()
+        .chain(file.dynamic_symbols())
+        // Imported symbols are listed too, undefined with an address of 0
+        .find(|symbol| !symbol.is_undefined() && symbol.name() == Ok(symbol_name))
+        .ok_or_else(|| anyhow!("Symbol {symbol_name} not found in {}", path.display()))?;
+
+    let section = symbol
+        .section_index()
+        .and_then(|index| file.section_by_index(index).ok())
+        .ok_or_else(|| anyhow!("Symbol {symbol_name} is not defined in {}", path.display()))?;
+
+    let (section_offset, _) = section
+        .file_range()
+        .ok_or_else(|| anyhow!("Section of symbol {symbol_name} is not in {}", path.display()))?;
+
+    Ok(symbol.address() - section.address() + section_offset)
+}
+
+/// Attaches a program to a uprobe, passing `cookie` to it.
+/// aya does not support bpf cookies yet, so the perf event and the link are created with the raw syscalls.
+pub fn attach_user_probe(program_fd: impl AsFd, program: &'static str, target: &UserProbeTarget, cookie: u64, is_return: bool) -> Result<UserProbeLink, ProgramError> {
+    let perf_event_fd = perf_event_open_uprobe(target, is_return).map_err(|io_error| SyscallError {
+        call: "perf_event_open",
+        io_error,
+    })?;
+
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let link = unsafe { &mut attr.link_create };
+
+    link.__bindgen_anon_1.prog_fd = program_fd.as_fd().as_raw_fd() as u32;
+    link.__bindgen_anon_2.target_fd = perf_event_fd.as_raw_fd() as u32;
+    link.attach_type = bpf_attach_type::BPF_PERF_EVENT as u32;
+    link.__bindgen_anon_3.perf_event.bpf_cookie = cookie;
+
+    let link_fd = bpf(bpf_cmd::BPF_LINK_CREATE, &mut attr).map_err(|io_error| SyscallError {
+        call: "bpf_link_create",
+        io_error,
+    })?;
+
+    Ok(UserProbeLink {
+        program,
+        target: target.name(),
+        _perf_event_fd: perf_event_fd,
+        _link_fd: link_fd,
+    })
+}
+
+fn perf_event_open_uprobe(target: &UserProbeTarget, is_return: bool) -> io::Result<OwnedFd> {
+    let perf_type = read_sys_file(UPROBE_TYPE)?.parse::<u32>().map_err(io::Error::other)?;
+    let path = CString::new(target.path.as_os_str().as_bytes())?;
+
+    let mut attr = unsafe { mem::zeroed::<perf_event_attr>() };
+    attr.size = mem::size_of::<perf_event_attr>() as u32;
+    attr.type_ = perf_type;
+    attr.__bindgen_anon_3.uprobe_path = path.as_ptr() as u64;
+    attr.__bindgen_anon_4.probe_offset = target.offset;
+
+    if is_return {
+        // The file content is "config:<bit>"
+        let retprobe_bit = read_sys_file(UPROBE_RETPROBE)?
+            .trim_start_matches("config:")
+            .parse::<u32>()
+            .map_err(io::Error::other)?;
+
+        attr.config = 1 << retprobe_bit;
+    }
+
+    if target.ref_ctr_offset != 0 {
+        // The file content is "config:<first bit>-<last bit>"
+        let ref_ctr_shift = read_sys_file(UPROBE_REF_CTR_OFFSET)?
+            .trim_start_matches("config:")
+            .split('-')
+            .next()
+            .unwrap_or_default()
+            .parse::<u32>()
+            .map_err(io::Error::other)?;
+
+        attr.config |= target.ref_ctr_offset << ref_ctr_shift;
+    }
+
+    // All processes, the cpu is then required
+    let ret = unsafe { libc::syscall(libc::SYS_perf_event_open, &attr as *const perf_event_attr, -1, 0, -1, PERF_FLAG_FD_CLOEXEC) };
+
+    match ret {
+        ret if ret < 0 => Err(io::Error::last_os_error()),
+        fd => Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+    }
+}
+
+fn read_sys_file(path: &str) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::anyhow;
+use aya::maps::{MapData, Queue};
+use object::{Object, ObjectSection, ObjectSegment};
+use serde::Serialize;
+use aya_network_deep_profiling_common::{UsdtArgKind, UsdtArgSpec, UsdtEvent, UsdtSpec, USDT_MAX_ARGS};
+use crate::uprobe::{resolve_library, UserProbeTarget};
+
+const STAPSDT_NOTE_SECTION: &str = ".note.stapsdt";
+const STAPSDT_BASE_SECTION: &str = ".stapsdt.base";
+const STAPSDT_NOTE_NAME: &[u8] = b"stapsdt\0";
+const STAPSDT_NOTE_TYPE: u32 = 3;
+
+/// x86_64 registers with their offset in `pt_regs`, the 32, 16 and low 8 bits names standing for the same register
+const REGISTERS: [(&[&str], u16); 17] = [
+    (&["r15", "r15d", "r15w", "r15b"], 0),
+    (&["r14", "r14d", "r14w", "r14b"], 8),
+    (&["r13", "r13d", "r13w", "r13b"], 16),
+    (&["r12", "r12d", "r12w", "r12b"], 24),
+    (&["rbp", "ebp", "bp", "bpl"], 32),
+    (&["rbx", "ebx", "bx", "bl"], 40),
+    (&["r11", "r11d", "r11w", "r11b"], 48),
+    (&["r10", "r10d", "r10w", "r10b"], 56),
+    (&["r9", "r9d", "r9w", "r9b"], 64),
+    (&["r8", "r8d", "r8w", "r8b"], 72),
+    (&["rax", "eax", "ax", "al"], 80),
+    (&["rcx", "ecx", "cx", "cl"], 88),
+    (&["rdx", "edx", "dx", "dl"], 96),
+    (&["rsi", "esi", "si", "sil"], 104),
+    (&["rdi", "edi", "di", "dil"], 112),
+    (&["rip", "eip"], 128),
+    (&["rsp", "esp", "sp", "spl"], 152),
+];
+
+/// Location of a USDT probe, described by a stapsdt note
+#[derive(Debug, Clone)]
+pub struct UsdtLocation {
+    pub path: PathBuf,
+    pub provider: String,
+    pub name: String,
+    /// Offset of the probe in the file
+    pub offset: u64,
+    /// Offset of the semaphore in the file, 0 if the probe has none
+    pub semaphore_offset: u64,
+    /// Argument string, e.g. `-4@%edi 8@-8(%rbp)`
+    pub args: String,
+}
+
+impl UsdtLocation {
+    pub fn probe_name(&self) -> String {
+        format!("{}:{}", self.provider, self.name)
+    }
+
+    pub fn user_probe_target(&self) -> UserProbeTarget {
+        UserProbeTarget {
+            path: self.path.clone(),
+            symbol: self.probe_name(),
+            offset: self.offset,
+            ref_ctr_offset: self.semaphore_offset,
+        }
+    }
+
+    pub fn spec(&self) -> UsdtSpec {
+        let mut spec = UsdtSpec {
+            args: [UsdtArgSpec { value: 0, register_offset: 0, kind: UsdtArgKind::Unsupported, size: 8 }; USDT_MAX_ARGS],
+            arg_count: 0,
+        };
+
+        for (index, arg) in self.args.split_whitespace().take(USDT_MAX_ARGS).enumerate() {
+            spec.args[index] = parse_arg(arg);
+            spec.arg_count += 1;
+        }
+
+        spec
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(untagged)]
+pub enum UsdtArgValue {
+    Signed(i64),
+    Unsigned(u64),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsdtEventRow {
+    pub probe: String,
+    pub timestamp: u64,
+    pub pid: u32,
+    pub tid: u32,
+    pub cpuid: u32,
+    pub args: Vec<UsdtArgValue>,
+}
+
+/// Lists the USDT probe locations of a binary or shared library, from its `.note.stapsdt` section
+pub fn usdt_locations(path: &str) -> anyhow::Result<Vec<UsdtLocation>> {
+    let path = resolve_library(path)?;
+    let data = fs::read(&path)?;
+    let file = object::File::parse(&*data)?;
+
+    let Some(notes) = file.section_by_name(STAPSDT_NOTE_SECTION) else {
+        return Ok(Vec::new());
+    };
+    let notes = notes.data()?;
+
+    // Prelinking may move the binary, the notes are relative to the address of `.stapsdt.base` at link time
+    let base_address = file.section_by_name(STAPSDT_BASE_SECTION).map(|section| section.address());
+
+    let mut locations = Vec::new();
+    let mut position = 0;
+
+    // Notes are "namesz: u32, descsz: u32, type: u32, name, desc", the name and the desc being padded to 4 bytes
+    while position + 12 <= notes.len() {
+        let name_size = u32::from_le_bytes(notes[position..position + 4].try_into()?) as usize;
+        let desc_size = u32::from_le_bytes(notes[position + 4..position + 8].try_into()?) as usize;
+        let note_type = u32::from_le_bytes(notes[position + 8..position + 12].try_into()?);
+
+        let name_start = position + 12;
+        let desc_start = name_start + name_size.next_multiple_of(4);
+        let desc_end = desc_start + desc_size;
+        position = desc_start + desc_size.next_multiple_of(4);
+
+        if desc_end > notes.len() {
+            return Err(anyhow!("Truncated {STAPSDT_NOTE_SECTION} section in {}", path.display()));
+        }
+
+        if note_type != STAPSDT_NOTE_TYPE || &notes[name_start..name_start + name_size] != STAPSDT_NOTE_NAME {
+            continue;
+        }
+
+        locations.push(parse_note(&file, &path, &notes[desc_start..desc_end], base_address)?);
+    }
+
+    Ok(locations)
+}
+
+/// Locations of the probes given with `--usdt PATH:PROVIDER:NAME`
+pub fn resolve_usdt(target: &str) -> anyhow::Result<Vec<UsdtLocation>> {
+    let mut parts = target.rsplitn(3, ':');
+
+    let (Some(name), Some(provider), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(anyhow!("Invalid USDT target {target}, expected PATH:PROVIDER:NAME"));
+    };
+
+    let locations = usdt_locations(path)?
+        .into_iter()
+        .filter(|location| location.provider == provider && location.name == name)
+        .collect::<Vec<UsdtLocation>>();
+
+    if locations.is_empty() {
+        return Err(anyhow!("USDT probe {provider}:{name} not found in {path}"));
+    }
+
+    Ok(locations)
+}
+
+/// Desc of a stapsdt note: "pc: u64, base: u64, semaphore: u64, provider\0, name\0, args\0"
+fn parse_note(file: &object::File, path: &Path, desc: &[u8], base_address: Option<u64>) -> anyhow::Result<UsdtLocation> {
+    if desc.len() < 24 {
+        return Err(anyhow!("Invalid stapsdt note in {}", path.display()));
+    }
+
+    let mut pc = u64::from_le_bytes(desc[0..8].try_into()?);
+    let note_base = u64::from_le_bytes(desc[8..16].try_into()?);
+    let semaphore = u64::from_le_bytes(desc[16..24].try_into()?);
+
+    if let Some(base_address) = base_address {
+        pc = pc.wrapping_add(base_address).wrapping_sub(note_base);
+    }
+
+    let mut strings = desc[24..].split(|byte| *byte == 0).map(|string| String::from_utf8_lossy(string).to_string());
+    let provider = strings.next().unwrap_or_default();
+    let name = strings.next().unwrap_or_default();
+    let args = strings.next().unwrap_or_default();
+
+    let semaphore_offset = match semaphore {
+        0 => 0,
+        semaphore => file_offset(file, semaphore).ok_or_else(|| anyhow!("Semaphore of {provider}:{name} not found in {}", path.display()))?
+    };
+
+    Ok(UsdtLocation {
+        path: path.to_path_buf(),
+        offset: file_offset(file, pc).ok_or_else(|| anyhow!("Location of {provider}:{name} not found in {}", path.display()))?,
+        semaphore_offset,
+        provider,
+        name,
+        args,
+    })
+}
+
+/// Offset in the file of a virtual address, using the loadable segment containing it
+fn file_offset(file: &object::File, address: u64) -> Option<u64> {
+    file.segments().find_map(|segment| {
+        let (offset, size) = segment.file_range();
+        let start = segment.address();
+
+        match address >= start && address < start + size {
+            true => Some(address - start + offset),
+            false => None
+        }
+    })
+}
+
+/// Argument of the form `size@operand`, the operand being `$constant`, `%register` or `offset(%register)`
+fn parse_arg(arg: &str) -> UsdtArgSpec {
+    let unsupported = UsdtArgSpec { value: 0, register_offset: 0, kind: UsdtArgKind::Unsupported, size: 8 };
+
+    let Some((size, operand)) = arg.split_once('@') else {
+        return unsupported;
+    };
+    let Ok(size) = size.parse::<i8>() else {
+        return unsupported;
+    };
+
+    if let Some(constant) = operand.strip_prefix('$') {
+        return match constant.parse::<i64>() {
+            Ok(value) => UsdtArgSpec { value, register_offset: 0, kind: UsdtArgKind::Constant, size },
+            Err(_) => UsdtArgSpec { size, ..unsupported }
+        };
+    }
+
+    if let Some(register) = operand.strip_prefix('%') {
+        return match register_offset(register) {
+            Some(register_offset) => UsdtArgSpec { value: 0, register_offset, kind: UsdtArgKind::Register, size },
+            None => UsdtArgSpec { size, ..unsupported }
+        };
+    }
+
+    if let Some((offset, register)) = operand.strip_suffix(')').and_then(|operand| operand.split_once("(%")) {
+        let offset = match offset {
+            "" => Some(0),
+            offset => offset.parse::<i64>().ok()
+        };
+
+        if let (Some(value), Some(register_offset)) = (offset, register_offset(register)) {
+            return UsdtArgSpec { value, register_offset, kind: UsdtArgKind::RegisterDeref, size };
+        }
+    }
+
+    UsdtArgSpec { size, ..unsupported }
+}
+
+fn register_offset(register: &str) -> Option<u16> {
+    REGISTERS
+        .iter()
+        .find(|(names, _)| names.contains(&register))
+        .map(|(_, offset)| *offset)
+}
+
+pub fn collect_queue(usdt_events: &mut Queue<MapData, UsdtEvent>, initial_time: u64) -> Vec<UsdtEvent> {
+    let mut events: Vec<UsdtEvent> = Vec::new();
+
+    while let Ok(usdt_event) = usdt_events.pop(0) {
+        if usdt_event.timestamp < initial_time {
+            continue;
+        }
+
+        events.push(usdt_event);
+    }
+
+    events
+}
+
+/// Names the probe of each event and truncates its arguments to their size
+pub fn process_usdt_events(events: Vec<UsdtEvent>, locations: &[UsdtLocation], initial_time: u64) -> Vec<UsdtEventRow> {
+    let specs = locations.iter().map(UsdtLocation::spec).collect::<Vec<UsdtSpec>>();
+    let mut hits: BTreeMap<String, usize> = BTreeMap::new();
+
+    let rows = events
+        .into_iter()
+        .filter_map(|event| {
+            let location = locations.get(event.location as usize)?;
+            let spec = specs.get(event.location as usize)?;
+
+            let args = spec.args[..spec.arg_count as usize]
+                .iter()
+                .zip(event.args)
+                .map(|(arg, value)| arg_value(arg, value))
+                .collect();
+
+            *hits.entry(location.probe_name()).or_default() += 1;
+
+            Some(UsdtEventRow {
+                probe: location.probe_name(),
+                timestamp: event.timestamp.saturating_sub(initial_time),
+                pid: event.pid,
+                tid: event.tid,
+                cpuid: event.cpuid,
+                args,
+            })
+        })
+        .collect();
+
+    if !hits.is_empty() {
+        println!("================================================= USDT Probes =================================================");
+        println!("{: <60} {: <20}", "Probe", "Hits");
+
+        for (probe, count) in hits {
+            println!("{: <60} {: <20}", probe, count);
+        }
+    }
+
+    rows
+}
+
+fn arg_value(arg: &UsdtArgSpec, value: u64) -> UsdtArgValue {
+    let bits = (arg.size.unsigned_abs() as u32 * 8).clamp(8, 64);
+    let shift = 64 - bits;
+
+    match arg.size < 0 {
+        true => UsdtArgValue::Signed(((value << shift) as i64) >> shift),
+        false => UsdtArgValue::Unsigned((value << shift) >> shift)
+    }
+}
@@ -10,7 +10,7 @@ pub fn mean(list: &[u64]) -> u64 {
 pub fn median(list: &[u64]) -> u64 {
     let len = list.len();
     let mid = len / 2;
-    if len % 2 == 0 {
+    if len.is_multiple_of(2) {
         mean(&list[(mid - 1)..(mid + 1)])
     } else {
         list[mid]
@@ -1,28 +1,64 @@
-use aya::maps;
-use aya::maps::MapData;
+use std::collections::HashMap;
+use aya::programs::{ProgramError, Xdp, XdpFlags};
+use aya::programs::xdp::XdpLinkId;
+use log::warn;
 use rayon::prelude::*;
-use aya_network_deep_profiling_common::{EthHeader, EtherHeaderType};
+use aya_network_deep_profiling_common::XdpPacket;
+use crate::ARGS;
+use crate::args::XdpMode;
+use crate::flow::flow_name;
+use crate::protocol::EtherType;
 
-pub fn process_xdp(xdp_times: maps::HashMap<MapData, u64, EthHeader>, initial_time: u64) -> Vec<(u64, String)> {
-    xdp_times.iter()
-        .par_bridge()
-        .filter_map(|x| x.ok())
-        .map(|(time, eth_header)| {
+/// Whether the XDP and TC programs are attached to an interface, see `--iface` and `--exclude-iface`
+pub fn is_profiled_interface(interface: &str) -> bool {
+    let included = ARGS.ifaces.is_empty() || ARGS.ifaces.iter().any(|name| name == interface);
+    let excluded = ARGS.exclude_ifaces.iter().any(|name| name == interface);
+
+    included && !excluded
+}
+
+/// Attaches the XDP program with the `--xdp-mode` mode, falling back from driver to generic mode. Returns the mode used.
+pub fn attach_xdp(program: &mut Xdp, interface: &str) -> Result<(XdpLinkId, XdpMode), ProgramError> {
+    match program.attach(interface, ARGS.xdp_mode.flags()) {
+        Ok(link_id) => Ok((link_id, ARGS.xdp_mode)),
+        Err(error) if ARGS.xdp_mode == XdpMode::Drv => {
+            warn!("Interface {interface} refused native XDP ({error}), falling back to generic mode");
+            let link_id = program.attach(interface, XdpFlags::SKB_MODE)?;
+            Ok((link_id, XdpMode::Skb))
+        }
+        Err(error) => Err(error)
+    }
+}
+
+pub fn process_xdp(xdp_times: Vec<(u64, XdpPacket)>, initial_time: u64) -> Vec<(u64, String)> {
+    let mut ether_types: HashMap<EtherType, usize> = HashMap::new();
+
+    for (_, packet) in &xdp_times {
+        *ether_types.entry(EtherType::from_wire(packet.eth_header.ether_type)).or_default() += 1;
+    }
+
+    if !ether_types.is_empty() {
+        let mut ether_types = ether_types.into_iter().collect::<Vec<(EtherType, usize)>>();
+        ether_types.sort_by_key(|(_, count)| usize::MAX - count);
+
+        println!("================================================= XDP Frames =================================================");
+        println!("{: <40} {: >12}", "EtherType", "Frames");
+
+        for (ether_type, count) in ether_types {
+            println!("{: <40} {: >12}", ether_type.to_string(), count);
+        }
+    }
+
+    xdp_times.into_par_iter()
+        .map(|(time, packet)| {
+            let eth_header = packet.eth_header;
             let new_time = time.saturating_sub(initial_time);
 
-            let eth_type = match eth_header.ether_type {
-                EtherHeaderType::Loop => "Loop",
-                EtherHeaderType::Ipv4 => "IPv4",
-                EtherHeaderType::Arp => "ARP",
-                EtherHeaderType::Ipv6 => "IPv6",
-                EtherHeaderType::FibreChannel => "FibreChannel",
-                EtherHeaderType::Infiniband => "Infiniband",
-                EtherHeaderType::LoopbackIeee8023 => "LoopbackIeee8023",
-            };
+            let eth_type = EtherType::from_wire(eth_header.ether_type);
 
             let src = format!("{:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}", eth_header.src_addr[0], eth_header.src_addr[1], eth_header.src_addr[2], eth_header.src_addr[3], eth_header.src_addr[4], eth_header.src_addr[5]);
             let dst = format!("{:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}:{:0>2X}", eth_header.dst_addr[0], eth_header.dst_addr[1], eth_header.dst_addr[2], eth_header.dst_addr[3], eth_header.dst_addr[4], eth_header.dst_addr[5]);
-            let info = format!("{eth_type}, SRC: {src}, DST: {dst}");
+            let info = format!("{eth_type}, SRC: {src}, DST: {dst}, {}", flow_name(&packet.flow));
 
             (new_time, info)
         })
@@ -194,7 +194,7 @@ def plot_memory(data, ax, time_filter):
     ax.legend(by_label.values(), by_label.keys(), loc="upper right", fontsize="x-small")
 
 def plot_throughput(data, ax, time_filter):
-    throughput_df = pd.DataFrame(data['throughput'], columns=['timestamp', 'packet_size', 'direction', 'interface'])
+    throughput_df = pd.DataFrame(data['throughput'], columns=['timestamp', 'packet_size', 'direction', 'interface', 'flow'])
     time_window = 1_000_000_000.0
 
     if time_filter is not None:
@@ -258,6 +258,26 @@ def plot_throughput(data, ax, time_filter):
     by_label = dict(zip(labels, handles))
     ax.legend(by_label.values(), by_label.keys(), loc="upper right", fontsize="x-small")
 
+def plot_drops(data, ax, time_filter):
+    if not data.get('packet_drops'):
+        return
+
+    drops_df = pd.DataFrame(data['packet_drops']['drops'], columns=['timestamp', 'reason', 'function', 'interface'])
+
+    if time_filter is not None:
+        drops_df = drops_df.query('timestamp >= @time_filter[0] and timestamp <= @time_filter[1]')
+
+    used_reasons = sorted(list(dict.fromkeys(drops_df['reason'])))
+    colormap = cm.get_cmap('tab10', max(len(used_reasons), 1))
+
+    for i, reason in enumerate(used_reasons):
+        reason_df = drops_df.query('reason == @reason')
+        ax.scatter(reason_df['timestamp'], [0] * len(reason_df), marker='x', s=12, color=colormap(i), label=f'drop {reason}', zorder=3)
+
+    handles, labels = ax.get_legend_handles_labels()
+    by_label = dict(zip(labels, handles))
+    ax.legend(by_label.values(), by_label.keys(), loc="upper right", fontsize="x-small")
+
 def plot_xdp(data, ax, time_filter, xdp_labels):
     xdp_times_df = pd.DataFrame(data['xdp_times'], columns=['timestamp', 'text'])
 
@@ -344,6 +364,9 @@ def main():
         elif name.startswith('throughput'):
             plot_throughput(data, ax, time_filter)
 
+            if args.drops:
+                plot_drops(data, ax, time_filter)
+
         if args.xdp and (name.startswith('stack_merged') or name.startswith('stack_per_cpu')):
             plot_xdp(data, ax, time_filter, args.xdp_labels)
 
@@ -373,6 +396,7 @@ if __name__ == '__main__':
     parser.add_argument('-m', '--memory', action='store_true', default=False, help='Plot the memory usage')
     parser.add_argument('-t', '--throughput', action='store_true', default=False, help='Plot the throughput')
     parser.add_argument('-x', '--xdp', action='store_true', default=False, help='Plot XDP packet reception')
+    parser.add_argument('-d', '--drops', action='store_true', default=False, help='Plot the packet drops on the throughput')
     parser.add_argument('-f', '--filter', default=None, help='Filter time in nanoseconds, e.g. "1000000000-2000000000" for data only from 1s to 2s')
     parser.add_argument('--ignored-cpus', nargs='+', default=[], help='Hide specific CPUs in plot')
     parser.add_argument('--xdp-labels', action='store_true', default=False, help='Show ethernet header for XDP packets')
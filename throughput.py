@@ -30,7 +30,7 @@ data = json.load(f)
 fig = plt.figure()
 ax = fig.add_subplot(1, 1, 1)
 
-throughput_df = pd.DataFrame(data['throughput'], columns=['timestamp', 'packet_size', 'direction', 'interface'])
+throughput_df = pd.DataFrame(data['throughput'], columns=['timestamp', 'packet_size', 'direction', 'interface', 'flow'])
 time_window = 1_000_000_000.0
 
 if time_filter is not None: